@@ -1,5 +1,6 @@
-use crate::tokeniser;
-use std::collections::VecDeque;
+use crate::{cst, evaluator, tokeniser, transpiler};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
 
 const SCENE: &str = "SCENE";
 const OBJECT: &str = "OBJECT";
@@ -12,36 +13,204 @@ const SPHERE: &str = "SPHERE";
 const RECTANGLE: &str = "RECTANGLE";
 const IMAGE: &str = "IMAGE";
 const ACTIVE: &str = "ACTIVE";
+const WINDOW: &str = "WINDOW";
+const RENDER: &str = "RENDER";
+const TEXT: &str = "TEXT";
+const PANEL: &str = "PANEL";
+const BUTTON: &str = "BUTTON";
+const SPRITE: &str = "SPRITE";
+const ANIMATION: &str = "ANIMATION";
+const ZIG: &str = "ZIG";
+const FOR: &str = "FOR";
+const IN: &str = "IN";
+const IF: &str = "IF";
+const ELSE: &str = "ELSE";
+const CONST: &str = "CONST";
+const PREFAB: &str = "PREFAB";
+const EXTENDS: &str = "EXTENDS";
+const IMPORT: &str = "IMPORT";
+const FROM: &str = "FROM";
+const START: &str = "START";
 
-const OBJECT_TYPES: [&str; 10] = [
-    OBJECT, CAMERA, LIGHT, PHYSICS, MATERIAL, CONTROLLER, SPHERE, RECTANGLE, IMAGE, ACTIVE,
+// Protect the build from runaway loops, including loops that generate nothing
+const MAX_GENERATED_OBJECTS: usize = 10_000;
+const MAX_ITERATIONS: usize = 100_000;
+
+pub const OBJECT_TYPES: [&str; 17] = [
+    OBJECT, CAMERA, LIGHT, PHYSICS, MATERIAL, CONTROLLER, SPHERE, RECTANGLE, IMAGE, ACTIVE, WINDOW,
+    RENDER, TEXT, PANEL, BUTTON, SPRITE, ANIMATION,
+];
+
+const KEYWORDS: [&str; 12] = [
+    SCENE, ZIG, FOR, IN, IF, ELSE, CONST, PREFAB, EXTENDS, IMPORT, FROM, START,
 ];
 
+/// Whether `name` is a keyword, which cannot name anything.
+pub fn is_keyword(name: &str) -> bool {
+    OBJECT_TYPES.contains(&name) || KEYWORDS.contains(&name)
+}
+
 pub struct Constructor {
+    /// The file being parsed, which diagnostics and imports are relative to
+    pub file: String,
     pub tokens: VecDeque<tokeniser::Token>,
+    positions: VecDeque<tokeniser::Position>,
+    // Where the first unknown character is, as nothing after it is tokenised
+    unknown: Option<tokeniser::Position>,
+    // Position of the last token taken from `tokens`
+    position: tokeniser::Position,
     pub engine: Engine,
     step: Step,
+    // Blocks whose bodies are still being parsed, innermost last
+    frames: Vec<Frame>,
+    // An IF block inside the current object, and whether it is in its ELSE branch
+    property_conditional: Option<(Conditional<Property>, bool)>,
+    // `-D name=value` values, which take precedence over CONST declarations
+    defines: Vec<(String, Expression)>,
+    in_scene: bool,
+    /// Where the START declaration is, to report unknown scenes
+    pub start: Option<tokeniser::Position>,
+    // The objects generated so far for the scene being expanded
+    objects: Vec<Object>,
+    // The loop iterations expanded so far for the scene
+    iterations: usize,
+    // Prefabs declared so far while expanding, with the bindings they were declared in
+    prefabs: HashMap<String, (Prefab, Vec<(String, Expression)>)>,
+    // Prepended to the names of objects expanded from a prefab
+    prefix: String,
+    // Prefabs currently being expanded, to catch recursive prefabs
+    instances: Vec<String>,
+    // Whether prefabs are being expanded only for the names of their objects
+    naming: bool,
+}
+
+enum Frame {
+    For(ForLoop),
+    // The bool is set once the ELSE branch is reached
+    If(Conditional<Statement>, bool),
+    Prefab(Prefab),
 }
 
 #[derive(Debug)]
 pub struct Engine {
-    pub scene: Scene,
+    /// Statements outside of the scenes, shared by all of them
+    pub body: Vec<Statement>,
+    pub scenes: Vec<Scene>,
+    /// The scene the game starts in, set by `START name` or the first scene
+    pub start: String,
+    /// The `WINDOW { ... }` block, if there is one
+    pub window: Option<Object>,
+    /// The `RENDER { ... }` block, if there is one
+    pub render: Option<Object>,
+    /// ZIG blocks outside of the scenes
+    pub zig: Vec<Object>,
 }
 
 #[derive(Debug)]
 pub struct Scene {
     pub name: String,
+    /// Where the SCENE keyword is
+    pub position: tokeniser::Position,
+    /// The scene as written, before loops are expanded
+    pub body: Vec<Statement>,
+    /// The concrete objects produced by expanding `body`
     pub objects: Vec<Object>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Object(Object),
+    For(ForLoop),
+    If(Conditional<Statement>),
+    Const(Constant),
+    Prefab(Prefab),
+    Instance(Instance),
+    Import(Import),
+}
+
+/// `IMPORT "lib.zest"` or `IMPORT mat_red, mat_blue FROM "lib.zest"`
+#[derive(Debug, Clone)]
+pub struct Import {
+    /// The names to import, or every statement in the file if empty
+    pub names: Vec<String>,
+    /// The path as written, relative to the importing file
+    pub path: String,
+    /// The importing file and the position of the IMPORT, for diagnostics
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+/// `CONST name = value`
+#[derive(Debug, Clone)]
+pub struct Constant {
+    pub name: String,
+    pub value: Expression,
+    /// The file the constant is declared in, for diagnostics
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+/// `PREFAB lamp(position, colour = #ffaa00) { ... }`
+#[derive(Debug, Clone)]
+pub struct Prefab {
+    pub name: String,
+    /// Parameter names and their default values
+    pub parameters: Vec<(String, Option<Expression>)>,
+    pub body: Vec<Statement>,
+    /// The file the prefab is declared in, for diagnostics
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+/// `lamp l1 { .position = (0, 0, 0) }`, expanded into the prefab's objects
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub prefab: String,
+    pub name: String,
+    pub arguments: Vec<Property>,
+    pub conditionals: Vec<Conditional<Property>>,
+    /// The file the instance is in and where its prefab is named, for
+    /// diagnostics
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForLoop {
+    pub variable: String,
+    pub iterable: Expression,
+    pub body: Vec<Statement>,
+    /// The file the loop is in, for diagnostics
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+/// `IF a { ... } ELSE IF b { ... } ELSE { ... }`
+#[derive(Debug, Clone)]
+pub struct Conditional<T> {
+    pub branches: Vec<(Expression, Vec<T>)>,
+    pub otherwise: Vec<T>,
+    /// The file the block is in, for diagnostics
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+#[derive(Debug, Clone)]
 pub struct Object {
     pub name: String,
     pub obj_type: ObjectType,
+    /// The object this one copies its properties from
+    pub base: Option<String>,
     pub properties: Vec<Property>,
+    /// Properties inside IF blocks, applied after the plain properties
+    pub conditionals: Vec<Conditional<Property>>,
+    /// The file the object is declared in, for diagnostics
+    pub file: String,
+    /// Where the object starts in its file, or (0, 0) if it was not parsed
+    pub position: tokeniser::Position,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ObjectType {
     Camera,
     Light,
@@ -52,6 +221,64 @@ pub enum ObjectType {
     Rectangle,
     Image,
     Active,
+    Window,
+    Render,
+    Text,
+    Panel,
+    Button,
+    Sprite,
+    Animation,
+    /// A `ZIG { ... }` block in a scene or at the top level
+    Zig,
+}
+
+impl ObjectType {
+    /// The Zest keyword declaring objects of this type
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            ObjectType::Camera => CAMERA,
+            ObjectType::Light => LIGHT,
+            ObjectType::Physics => PHYSICS,
+            ObjectType::Material => MATERIAL,
+            ObjectType::Controller => CONTROLLER,
+            ObjectType::Sphere => SPHERE,
+            ObjectType::Rectangle => RECTANGLE,
+            ObjectType::Image => IMAGE,
+            ObjectType::Active => ACTIVE,
+            ObjectType::Window => WINDOW,
+            ObjectType::Render => RENDER,
+            ObjectType::Text => TEXT,
+            ObjectType::Panel => PANEL,
+            ObjectType::Button => BUTTON,
+            ObjectType::Sprite => SPRITE,
+            ObjectType::Animation => ANIMATION,
+            ObjectType::Zig => ZIG,
+        }
+    }
+
+    /// The object type declared by `keyword`, if it is one
+    pub fn from_keyword(keyword: &str) -> Option<ObjectType> {
+        match keyword {
+            CAMERA => Some(ObjectType::Camera),
+            LIGHT => Some(ObjectType::Light),
+            PHYSICS => Some(ObjectType::Physics),
+            MATERIAL => Some(ObjectType::Material),
+            CONTROLLER => Some(ObjectType::Controller),
+            SPHERE => Some(ObjectType::Sphere),
+            RECTANGLE => Some(ObjectType::Rectangle),
+            IMAGE => Some(ObjectType::Image),
+            ACTIVE => Some(ObjectType::Active),
+            WINDOW => Some(ObjectType::Window),
+            RENDER => Some(ObjectType::Render),
+            TEXT => Some(ObjectType::Text),
+            PANEL => Some(ObjectType::Panel),
+            BUTTON => Some(ObjectType::Button),
+            SPRITE => Some(ObjectType::Sprite),
+            ANIMATION => Some(ObjectType::Animation),
+            ZIG => Some(ObjectType::Zig),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for ObjectType {
@@ -66,14 +293,310 @@ impl std::fmt::Display for ObjectType {
             ObjectType::Rectangle => write!(f, "objects.Rectangle"),
             ObjectType::Image => write!(f, "images.Image"),
             ObjectType::Active => write!(f, "Active"),
+            ObjectType::Window => write!(f, "engine.WindowFlags"),
+            ObjectType::Render => write!(f, "graphics.RayCastingOptions"),
+            ObjectType::Text => write!(f, "gui.Text"),
+            ObjectType::Panel => write!(f, "gui.Panel"),
+            ObjectType::Button => write!(f, "gui.Button"),
+            ObjectType::Sprite => write!(f, "gui.Sprite"),
+            ObjectType::Animation => write!(f, "Animation"),
+            ObjectType::Zig => write!(f, "Zig"),
         }
     }
 }
 
-#[derive(Debug)]
+/// The properties of the object types other than GUI elements, with their
+/// documentation
+const PROPERTIES: [(ObjectType, &str, &str); 37] = [
+    (
+        ObjectType::Sphere,
+        "position",
+        "The centre of the sphere, as `(x, y, z)`.",
+    ),
+    (ObjectType::Sphere, "radius", "The radius of the sphere."),
+    (
+        ObjectType::Sphere,
+        "material",
+        "The MATERIAL the sphere is drawn with.",
+    ),
+    (
+        ObjectType::Rectangle,
+        "v0",
+        "A corner of the rectangle, as `(x, y, z)`.",
+    ),
+    (
+        ObjectType::Rectangle,
+        "v1",
+        "The corner opposite `.v0`, as `(x, y, z)`.",
+    ),
+    (
+        ObjectType::Rectangle,
+        "material",
+        "The MATERIAL the rectangle is drawn with.",
+    ),
+    (
+        ObjectType::Rectangle,
+        "inverted",
+        "Whether the rectangle faces the other way.",
+    ),
+    (
+        ObjectType::Camera,
+        "position",
+        "Where the camera is, as `(x, y, z)`.",
+    ),
+    (
+        ObjectType::Camera,
+        "direction",
+        "The direction the camera looks in, as `(x, y, z)`.",
+    ),
+    (
+        ObjectType::Camera,
+        "look_at",
+        "An object or `(x, y, z)` point to look at, instead of a `.direction`.",
+    ),
+    (
+        ObjectType::Camera,
+        "event_handler",
+        "The CONTROLLER that moves the camera.",
+    ),
+    (
+        ObjectType::Light,
+        "position",
+        "Where the light is, as `(x, y, z)`.",
+    ),
+    (
+        ObjectType::Light,
+        "intensity",
+        "The brightness of each channel, as `(r, g, b)` from 0 to 1.",
+    ),
+    (
+        ObjectType::Light,
+        "direction",
+        "The direction the light shines in, as `(x, y, z)`.",
+    ),
+    (
+        ObjectType::Light,
+        "look_at",
+        "An object or `(x, y, z)` point to shine at, instead of a `.direction`.",
+    ),
+    (
+        ObjectType::Material,
+        "color",
+        "A solid colour, as `#rrggbb` or `(r, g, b)` from 0 to 255.",
+    ),
+    (
+        ObjectType::Material,
+        "image",
+        "An IMAGE to texture with, instead of a `.color`.",
+    ),
+    (
+        ObjectType::Material,
+        "reflectivity",
+        "How much the surface reflects, from 0 to 1.",
+    ),
+    (ObjectType::Image, "file", "The path of the image file."),
+    (
+        ObjectType::Controller,
+        "keyboard_movement",
+        "Whether the keyboard moves the camera.",
+    ),
+    (
+        ObjectType::Controller,
+        "mouse_movement",
+        "Whether the mouse turns the camera.",
+    ),
+    (
+        ObjectType::Controller,
+        "sensitivity",
+        "How fast the mouse turns the camera. Must be positive.",
+    ),
+    (
+        ObjectType::Controller,
+        "invert_y",
+        "Whether moving the mouse up looks down.",
+    ),
+    (
+        ObjectType::Controller,
+        "speed",
+        "How fast the camera moves. Must be positive.",
+    ),
+    (
+        ObjectType::Controller,
+        "bindings",
+        "The action of each key, as `{ W: forward, Space: jump }`.",
+    ),
+    (
+        ObjectType::Controller,
+        "actions",
+        "The custom actions keys and buttons can trigger, as `[jump, pause]`. Each is handled by a function of a top level ZIG block, `fn onJump() void` for `jump`.",
+    ),
+    (
+        ObjectType::Physics,
+        "bodies",
+        "The objects simulated, as `[ball, ground]`.",
+    ),
+    (
+        ObjectType::Physics,
+        "object",
+        "The one object simulated, instead of `.bodies`.",
+    ),
+    (
+        ObjectType::Physics,
+        "gravity",
+        "The acceleration of gravity, as `(x, y, z)`, or `false` for none.",
+    ),
+    (
+        ObjectType::Physics,
+        "timestep",
+        "The seconds simulated per step. Must be positive.",
+    ),
+    (
+        ObjectType::Active,
+        "camera",
+        "The CAMERA the scene is seen through.",
+    ),
+    (
+        ObjectType::Animation,
+        "target",
+        "The vector animated, as `object.property`.",
+    ),
+    (
+        ObjectType::Animation,
+        "keys",
+        "The keyframes, as `[(seconds, (x, y, z)), ...]`.",
+    ),
+    (
+        ObjectType::Animation,
+        "easing",
+        "Between keyframes: `linear`, `ease_in`, `ease_out`, `ease_in_out` or `step`.",
+    ),
+    (
+        ObjectType::Animation,
+        "loop",
+        "What happens after the last keyframe: `once`, `repeat` or `ping_pong`.",
+    ),
+    (
+        ObjectType::Render,
+        "fov",
+        "The field of view in degrees, between 0 and 180. Defaults to 90.",
+    ),
+    (
+        ObjectType::Render,
+        "scale",
+        "The ray-traced resolution relative to the window. Defaults to 1.",
+    ),
+];
+
+const WINDOW_PROPERTIES: [(&str, &str); 8] = [
+    ("title", "The title of the window. Defaults to `\"Z3D\"`."),
+    (
+        "width",
+        "The width of the window in pixels, equal to its height. Defaults to 400.",
+    ),
+    (
+        "height",
+        "The height of the window in pixels, equal to its width. Defaults to 400.",
+    ),
+    (
+        "x",
+        "The distance of the window from the left of the screen.",
+    ),
+    (
+        "y",
+        "The distance of the window from the top of the screen.",
+    ),
+    ("resizable", "Whether the window can be resized."),
+    ("fullscreen", "Whether the window fills the screen."),
+    ("vsync", "Whether frames wait for the display to refresh."),
+];
+
+/// The properties GUI elements can have, which of them each element has
+/// being up to the transpiler
+const GUI_PROPERTIES: [(&str, &str); 10] = [
+    (
+        "position",
+        "The offset from the anchor in pixels, as `(x, y)`.",
+    ),
+    (
+        "anchor",
+        "The point of the screen the element is placed from, such as `top_left` or `center`.",
+    ),
+    (
+        "size",
+        "The width and height in pixels, as `(width, height)`.",
+    ),
+    ("text", "The text shown."),
+    ("font", "The path of the font file."),
+    ("font_size", "The height of the text in pixels."),
+    (
+        "color",
+        "The colour, as `#rrggbb` or `(r, g, b)` from 0 to 255.",
+    ),
+    (
+        "text_color",
+        "The colour of the text, as `#rrggbb` or `(r, g, b)` from 0 to 255.",
+    ),
+    (
+        "on_click",
+        "The action triggered by clicking, declared in a CONTROLLER's `.actions`.",
+    ),
+    ("image", "The IMAGE shown, declared before the element."),
+];
+
+/// Physics settings, given on the bodies themselves or as defaults on PHYSICS
+const BODY_PROPERTIES: [(&str, &str); 5] = [
+    ("mass", "The mass of the body."),
+    (
+        "restitution",
+        "How much of its speed the body keeps when it bounces, from 0 to 1.",
+    ),
+    ("friction", "How much the body resists sliding."),
+    (
+        "velocity",
+        "The starting velocity of the body, as `(x, y, z)`.",
+    ),
+    ("static", "Whether the body is fixed in place."),
+];
+
+const ON_UPDATE: (&str, &str) = ("on_update", "Zig run every frame, as `ZIG { ... }`.");
+
+/// The properties of `obj_type`, with their documentation.
+pub fn properties(obj_type: ObjectType) -> Vec<(&'static str, &'static str)> {
+    let mut properties: Vec<(&str, &str)> = match obj_type {
+        ObjectType::Window => WINDOW_PROPERTIES.to_vec(),
+        ObjectType::Text | ObjectType::Panel | ObjectType::Button | ObjectType::Sprite => {
+            transpiler::gui_properties(obj_type)
+                .iter()
+                .filter_map(|name| GUI_PROPERTIES.iter().find(|(gui, _)| gui == name).copied())
+                .collect()
+        }
+        _ => PROPERTIES
+            .iter()
+            .filter(|(of, _, _)| *of == obj_type)
+            .map(|(_, name, documentation)| (*name, *documentation))
+            .collect(),
+    };
+    if matches!(
+        obj_type,
+        ObjectType::Sphere | ObjectType::Rectangle | ObjectType::Physics
+    ) {
+        properties.extend(BODY_PROPERTIES);
+    }
+    if !matches!(
+        obj_type,
+        ObjectType::Window | ObjectType::Render | ObjectType::Zig
+    ) {
+        properties.push(ON_UPDATE);
+    }
+    properties
+}
+
+#[derive(Debug, Clone)]
 pub struct Property {
     pub name: String,
     pub value: Expression,
+    /// Where the property (or CONST) starts, or (0, 0) if it was not parsed
+    pub position: tokeniser::Position,
 }
 
 #[derive(Debug, Clone)]
@@ -82,9 +605,70 @@ pub enum Expression {
     Identifier(String),
     String(String),
     Group(Vec<Expression>),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+    Call(String, Vec<Expression>),
+    Member(Box<Expression>, String),
+    Color(String),
+    List(Vec<Expression>),
+    /// `{ W: forward, Space: jump }`
+    Map(Vec<(String, Expression)>),
+    Zig(Code),
+    Range(Box<Expression>, Box<Expression>),
     Empty,
 }
 
+/// The contents of a `ZIG { ... }` block, and where it was written
+#[derive(Debug, Clone)]
+pub struct Code {
+    pub code: String,
+    pub file: String,
+    pub position: tokeniser::Position,
+}
+
+impl Code {
+    /// Where the block was written, as `file:line:column`.
+    pub fn span(&self) -> String {
+        format!("{}:{}:{}", self.file, self.position.0, self.position.1)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Subtract => write!(f, "-"),
+            Operator::Multiply => write!(f, "*"),
+            Operator::Divide => write!(f, "/"),
+            Operator::Equal => write!(f, "=="),
+            Operator::NotEqual => write!(f, "!="),
+            Operator::Less => write!(f, "<"),
+            Operator::LessEqual => write!(f, "<="),
+            Operator::Greater => write!(f, ">"),
+            Operator::GreaterEqual => write!(f, ">="),
+            Operator::And => write!(f, "&&"),
+            Operator::Or => write!(f, "||"),
+        }
+    }
+}
+
 // display for expression
 impl std::fmt::Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -99,6 +683,43 @@ impl std::fmt::Display for Expression {
                 }
                 write!(f, ")")
             }
+            Expression::Negate(expr) => write!(f, "-{}", expr),
+            Expression::Not(expr) => write!(f, "!{}", expr),
+            Expression::Binary(lhs, op, rhs) => write!(f, "({} {} {})", lhs, op, rhs),
+            Expression::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expression::Member(expr, member) => write!(f, "{}.{}", expr, member),
+            Expression::Color(hex) => write!(f, "#{}", hex),
+            Expression::Zig(code) => write!(f, "{} {{{}}}", ZIG, code.code),
+            Expression::List(exprs) => {
+                write!(f, "[")?;
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, "]")
+            }
+            Expression::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}: {}", key, value)?;
+                }
+                write!(f, " }}")
+            }
+            Expression::Range(start, end) => write!(f, "{}..{}", start, end),
             Expression::Empty => write!(f, ""),
         }
     }
@@ -116,20 +737,143 @@ pub enum Step {
 }
 
 impl Constructor {
-    pub fn new(tokens: VecDeque<tokeniser::Token>) -> Self {
+    pub fn new(file: String, text: String) -> Self {
+        let cst = cst::Cst::parse(&file, &text);
+        let (tokens, positions) = cst.tokens();
         Self {
+            file,
             tokens,
+            positions,
+            unknown: cst.unknown(),
+            position: (1, 1),
             engine: Engine {
-                scene: Scene {
-                    name: "scene".to_string(),
-                    objects: Vec::new(),
-                },
+                body: Vec::new(),
+                scenes: Vec::new(),
+                start: String::new(),
+                window: None,
+                render: None,
+                zig: Vec::new(),
             },
             step: Step::Start,
+            frames: Vec::new(),
+            property_conditional: None,
+            defines: Vec::new(),
+            in_scene: false,
+            start: None,
+            objects: Vec::new(),
+            iterations: 0,
+            prefabs: HashMap::new(),
+            prefix: String::new(),
+            instances: Vec::new(),
+            naming: false,
         }
     }
 
+    pub fn define(&mut self, name: String, value: Expression) {
+        self.defines.push((name, value));
+    }
+
     pub fn construct(&mut self) {
+        self.parse();
+        if self.engine.scenes.is_empty() {
+            self.error(format!("Expected {}", SCENE));
+        }
+        match self.start {
+            None => self.engine.start = self.engine.scenes[0].name.clone(),
+            Some(position) => {
+                if !self
+                    .engine
+                    .scenes
+                    .iter()
+                    .any(|s| s.name == self.engine.start)
+                {
+                    report(
+                        &self.file,
+                        position,
+                        format!("Unknown start scene `{}`", self.engine.start),
+                    );
+                }
+            }
+        }
+
+        let mut stack = vec![canonical(&self.file)];
+        let mut imported = HashSet::new();
+        let body = self.engine.body.clone();
+        self.engine.body = resolve_imports(&body, &mut stack, &mut imported);
+        // Scenes are expanded apart, so each has to import what the top level hasn't
+        for i in 0..self.engine.scenes.len() {
+            let body = self.engine.scenes[i].body.clone();
+            let mut imported = imported.clone();
+            self.engine.scenes[i].body = resolve_imports(&body, &mut stack, &mut imported);
+        }
+
+        // Every scene gets its own copy of the shared statements
+        for i in 0..self.engine.scenes.len() {
+            let body = [self.engine.body.clone(), self.engine.scenes[i].body.clone()].concat();
+            self.prefabs.clear();
+            self.iterations = 0;
+            self.expand(&body, &mut self.defines.clone());
+            self.engine.scenes[i].objects = std::mem::take(&mut self.objects);
+        }
+        self.inherit();
+        self.configure();
+    }
+
+    /// Moves the WINDOW and RENDER blocks and top level ZIG blocks out of the
+    /// scenes into the engine. They are declared outside of the scenes, so
+    /// every scene has the same copy.
+    pub fn configure(&mut self) {
+        let top_level = |o: &Object| {
+            o.obj_type == ObjectType::Zig
+                && o.properties
+                    .iter()
+                    .any(|p| p.name == "top_level" && p.value.to_string() == "true")
+        };
+        for (i, scene) in self.engine.scenes.iter_mut().enumerate() {
+            let (zig, objects): (Vec<Object>, Vec<Object>) = std::mem::take(&mut scene.objects)
+                .into_iter()
+                .partition(top_level);
+            scene.objects = objects;
+            if i == 0 {
+                self.engine.zig = zig;
+            }
+        }
+
+        // Settings are only declared at the top level, so they are configured
+        // once from the first scene's copy
+        for (i, scene) in self.engine.scenes.iter_mut().enumerate() {
+            let (settings, objects): (Vec<Object>, Vec<Object>) =
+                std::mem::take(&mut scene.objects)
+                    .into_iter()
+                    .partition(|o| matches!(o.obj_type, ObjectType::Window | ObjectType::Render));
+            scene.objects = objects;
+            if i > 0 {
+                continue;
+            }
+
+            for object in settings {
+                let setting = match object.obj_type {
+                    ObjectType::Window => &mut self.engine.window,
+                    _ => &mut self.engine.render,
+                };
+                if setting.is_some() {
+                    report(
+                        &object.file,
+                        object.position,
+                        format!("Only one {} is allowed", object.obj_type.keyword()),
+                    );
+                }
+                *setting = Some(object);
+            }
+        }
+    }
+
+    /// Parses the file into `engine.body` and each scene's body, without
+    /// resolving imports or expanding anything.
+    pub fn parse(&mut self) {
+        if let Some(position) = self.unknown {
+            report(&self.file, position, "Unexpected character".to_string());
+        }
         let mut curr = self.step;
         while curr != Step::End {
             curr = match curr {
@@ -144,7 +888,66 @@ impl Constructor {
         }
     }
 
+    /// Reports a parse error at the last token read.
+    pub fn error(&self, message: String) -> ! {
+        report(&self.file, self.position, message)
+    }
+
+    /// Resolves `EXTENDS`, copying the base object's properties into every
+    /// derived object before its own properties override them.
+    pub fn inherit(&mut self) {
+        for scene in &mut self.engine.scenes {
+            let properties: Vec<Vec<Property>> = scene
+                .objects
+                .iter()
+                .map(|object| inherited_properties(object, &scene.objects, &mut Vec::new()))
+                .collect();
+
+            for (object, properties) in scene.objects.iter_mut().zip(properties) {
+                object.base = None;
+                object.properties = properties;
+            }
+        }
+    }
+
+    /// The statement list new objects are added to: the body of the
+    /// innermost open block, the scene, or the top level of the file.
+    pub fn statements(&mut self) -> &mut Vec<Statement> {
+        match self.frames.last_mut() {
+            Some(Frame::For(for_loop)) => &mut for_loop.body,
+            Some(Frame::If(conditional, false)) => &mut conditional.branches.last_mut().unwrap().1,
+            Some(Frame::If(conditional, true)) => &mut conditional.otherwise,
+            Some(Frame::Prefab(prefab)) => &mut prefab.body,
+            None if self.in_scene => &mut self.engine.scenes.last_mut().unwrap().body,
+            None => &mut self.engine.body,
+        }
+    }
+
+    /// The property list new properties are added to: the open IF block of
+    /// the current object, or the object itself.
+    pub fn properties(&mut self) -> &mut Vec<Property> {
+        match self.property_conditional {
+            Some((ref mut conditional, false)) => &mut conditional.branches.last_mut().unwrap().1,
+            Some((ref mut conditional, true)) => &mut conditional.otherwise,
+            None => self.current_object().0,
+        }
+    }
+
+    /// The properties and IF blocks of the object (or prefab instance) being parsed.
+    pub fn current_object(&mut self) -> (&mut Vec<Property>, &mut Vec<Conditional<Property>>) {
+        match self.statements().last_mut() {
+            Some(Statement::Object(object)) => (&mut object.properties, &mut object.conditionals),
+            Some(Statement::Instance(instance)) => {
+                (&mut instance.arguments, &mut instance.conditionals)
+            }
+            _ => panic!("Expected object"),
+        }
+    }
+
     pub fn pop_front(&mut self) -> tokeniser::Token {
+        if let Some(position) = self.positions.pop_front() {
+            self.position = position;
+        }
         match self.tokens.pop_front() {
             Some(token) => token.clone(),
             None => tokeniser::Token::Unknown,
@@ -161,79 +964,399 @@ impl Constructor {
     pub fn ensure(&mut self, token: tokeniser::Token) {
         let t = self.pop_front();
         if t != token {
-            panic!("Expected {:?}, got {:?}", token, t);
+            self.error(format!("Expected {:?}, got {:?}", token, t));
         }
     }
 
+    /// Anything outside of the scenes, such as constants, prefabs, imports and
+    /// shared objects, is parsed like scene content into `engine.body`.
     pub fn start(&mut self) -> Step {
+        match self.peek() {
+            tokeniser::Token::Identifier(keyword) if keyword == SCENE => {}
+            tokeniser::Token::Identifier(keyword) if keyword == START => {
+                self.ensure(tokeniser::Token::Identifier(START.to_string()));
+                if self.start.is_some() {
+                    self.error(format!("Only one {} is allowed per file", START));
+                }
+                self.start = Some(self.position);
+                self.engine.start = match self.pop_front() {
+                    tokeniser::Token::Identifier(name) => name,
+                    token => self.error(format!("Expected scene name, got {:?}", token)),
+                };
+                return Step::Start;
+            }
+            tokeniser::Token::Unknown => return Step::End,
+            _ => return Step::Object,
+        }
+
         self.ensure(tokeniser::Token::Identifier(SCENE.to_string()));
-        if let tokeniser::Token::Identifier(name) = self.pop_front() {
-            self.engine.scene.name = name;
+        let position = self.position;
+        let name = match self.pop_front() {
+            tokeniser::Token::Identifier(name) => name,
+            token => self.error(format!("Expected scene name, got {:?}", token)),
+        };
+        if self.engine.scenes.iter().any(|scene| scene.name == name) {
+            self.error(format!("Duplicate scene name: {}", name));
         }
+        self.engine.scenes.push(Scene {
+            name,
+            position,
+            body: Vec::new(),
+            objects: Vec::new(),
+        });
 
         Step::Scene
     }
 
     pub fn scene(&mut self) -> Step {
         self.ensure(tokeniser::Token::LBrace);
-        Step::Object
+        self.in_scene = true;
+        Step::ObjectEnd
     }
 
     pub fn object(&mut self) -> Step {
         let obj_type = self.pop_front();
+        let position = self.position;
         let obj_string = match obj_type {
+            tokeniser::Token::Identifier(keyword) if keyword == FOR => {
+                return self.for_loop();
+            }
+            tokeniser::Token::Identifier(keyword) if keyword == IF => {
+                let position = self.position;
+                let condition = self.expression();
+                self.ensure(tokeniser::Token::LBrace);
+                self.frames.push(Frame::If(
+                    Conditional {
+                        branches: vec![(condition, Vec::new())],
+                        otherwise: Vec::new(),
+                        file: self.file.clone(),
+                        position,
+                    },
+                    false,
+                ));
+                return Step::ObjectEnd;
+            }
+            tokeniser::Token::Identifier(keyword) if keyword == CONST => {
+                let constant = self.constant();
+                self.statements().push(constant);
+                return Step::ObjectEnd;
+            }
+            tokeniser::Token::Identifier(keyword) if keyword == PREFAB => {
+                return self.prefab();
+            }
+            tokeniser::Token::Identifier(keyword) if keyword == IMPORT => {
+                if !self.frames.is_empty() {
+                    self.error(format!("{} is only allowed outside of blocks", IMPORT));
+                }
+                let import = self.import();
+                self.statements().push(import);
+                return Step::ObjectEnd;
+            }
+            tokeniser::Token::Zig(code) => {
+                // Outside of scenes and prefabs, the code goes at the top level of the output
+                let top_level =
+                    !self.in_scene && !self.frames.iter().any(|f| matches!(f, Frame::Prefab(_)));
+                let code = self.code(code);
+                let position = code.position;
+                let file = self.file.clone();
+                self.statements().push(Statement::Object(Object {
+                    name: format!("zig_{}_{}", position.0, position.1),
+                    obj_type: ObjectType::Zig,
+                    base: None,
+                    properties: vec![
+                        Property {
+                            name: "code".to_string(),
+                            value: Expression::Zig(code),
+                            position,
+                        },
+                        Property {
+                            name: "top_level".to_string(),
+                            value: Expression::Identifier(top_level.to_string()),
+                            position,
+                        },
+                    ],
+                    conditionals: Vec::new(),
+                    file,
+                    position,
+                }));
+                return Step::ObjectEnd;
+            }
             tokeniser::Token::Identifier(obj_string) => {
                 if !OBJECT_TYPES.contains(&obj_string.as_str()) {
-                    panic!("Unexpected object type: {}", obj_string);
+                    return self.instance(obj_string);
                 }
                 obj_string
             }
-            _ => panic!("Unexpected token: {:?}", obj_type),
+            _ => self.error(format!("Unexpected token: {:?}", obj_type)),
         };
 
-        if let tokeniser::Token::Identifier(name) = self.pop_front() {
-            self.engine.scene.objects.push(Object {
-                name,
-                obj_type: match obj_string.as_str() {
-                    CAMERA => ObjectType::Camera,
-                    LIGHT => ObjectType::Light,
-                    PHYSICS => ObjectType::Physics,
-                    MATERIAL => ObjectType::Material,
-                    CONTROLLER => ObjectType::Controller,
-                    SPHERE => ObjectType::Sphere,
-                    RECTANGLE => ObjectType::Rectangle,
-                    IMAGE => ObjectType::Image,
-                    ACTIVE => ObjectType::Active,
-                    _ => panic!("Unexpected object type: {}", obj_string),
-                },
-                properties: Vec::new(),
-            });
+        // ZIG blocks are tokenised whole, so a bare ZIG here has no block
+        let obj_type = match ObjectType::from_keyword(&obj_string) {
+            Some(obj_type) if obj_type != ObjectType::Zig => obj_type,
+            _ => self.error(format!("Unexpected object type: {}", obj_string)),
+        };
+
+        // WINDOW and RENDER configure the whole game, so they need no name
+        let settings = matches!(obj_type, ObjectType::Window | ObjectType::Render);
+        let prefab = self.frames.iter().any(|f| matches!(f, Frame::Prefab(_)));
+        if settings && (self.in_scene || prefab) {
+            self.error(format!(
+                "{} must be declared outside of scenes and prefabs",
+                obj_string
+            ));
+        }
+        let name = match self.peek() {
+            tokeniser::Token::LBrace if settings => obj_string.to_lowercase(),
+            _ => match self.pop_front() {
+                tokeniser::Token::Identifier(name) => name,
+                token => self.error(format!("Expected object name, got {:?}", token)),
+            },
+        };
+
+        let file = self.file.clone();
+        self.statements().push(Statement::Object(Object {
+            name,
+            obj_type,
+            base: None,
+            properties: Vec::new(),
+            conditionals: Vec::new(),
+            file,
+            position,
+        }));
+
+        if self.peek() == tokeniser::Token::Identifier(EXTENDS.to_string()) {
+            self.ensure(tokeniser::Token::Identifier(EXTENDS.to_string()));
+            let base = match self.pop_front() {
+                tokeniser::Token::Identifier(base) => base,
+                token => self.error(format!(
+                    "Expected object name after {}, got {:?}",
+                    EXTENDS, token
+                )),
+            };
+            if let Some(Statement::Object(object)) = self.statements().last_mut() {
+                object.base = Some(base);
+            }
         }
         self.ensure(tokeniser::Token::LBrace);
 
         Step::PropertyName
     }
 
+    pub fn for_loop(&mut self) -> Step {
+        let position = self.position;
+        let variable = match self.pop_front() {
+            tokeniser::Token::Identifier(variable) => variable,
+            token => self.error(format!("Expected loop variable, got {:?}", token)),
+        };
+        self.ensure(tokeniser::Token::Identifier(IN.to_string()));
+
+        let mut iterable = self.expression();
+        if self.peek() == tokeniser::Token::DotDot {
+            self.ensure(tokeniser::Token::DotDot);
+            iterable = Expression::Range(Box::new(iterable), Box::new(self.expression()));
+        }
+        self.ensure(tokeniser::Token::LBrace);
+
+        self.frames.push(Frame::For(ForLoop {
+            variable,
+            iterable,
+            body: Vec::new(),
+            file: self.file.clone(),
+            position,
+        }));
+
+        Step::ObjectEnd
+    }
+
+    pub fn prefab(&mut self) -> Step {
+        let position = self.position;
+        let name = match self.pop_front() {
+            tokeniser::Token::Identifier(name) => name,
+            token => self.error(format!("Expected prefab name, got {:?}", token)),
+        };
+        if OBJECT_TYPES.contains(&name.as_str()) {
+            self.error(format!(
+                "Prefab `{}` cannot share its name with an object type",
+                name
+            ));
+        }
+
+        let mut parameters = Vec::new();
+        self.ensure(tokeniser::Token::LParen);
+        while self.peek() != tokeniser::Token::RParen {
+            let parameter = match self.pop_front() {
+                tokeniser::Token::Identifier(parameter) => parameter,
+                token => self.error(format!("Expected parameter name, got {:?}", token)),
+            };
+            let default = if self.peek() == tokeniser::Token::Equal {
+                self.ensure(tokeniser::Token::Equal);
+                Some(self.expression())
+            } else {
+                None
+            };
+            parameters.push((parameter, default));
+
+            if self.peek() != tokeniser::Token::RParen {
+                self.ensure(tokeniser::Token::Comma);
+            }
+        }
+        self.ensure(tokeniser::Token::RParen);
+        self.ensure(tokeniser::Token::LBrace);
+
+        self.frames.push(Frame::Prefab(Prefab {
+            name,
+            parameters,
+            body: Vec::new(),
+            file: self.file.clone(),
+            position,
+        }));
+
+        Step::ObjectEnd
+    }
+
+    pub fn instance(&mut self, prefab: String) -> Step {
+        let position = self.position;
+        let name = match self.pop_front() {
+            tokeniser::Token::Identifier(name) => name,
+            token => self.error(format!(
+                "Expected name for instance of `{}`, got {:?}",
+                prefab, token
+            )),
+        };
+        self.ensure(tokeniser::Token::LBrace);
+
+        let file = self.file.clone();
+        self.statements().push(Statement::Instance(Instance {
+            prefab,
+            name,
+            arguments: Vec::new(),
+            conditionals: Vec::new(),
+            file,
+            position,
+        }));
+
+        Step::PropertyName
+    }
+
+    pub fn constant(&mut self) -> Statement {
+        let position = self.position;
+        let name = match self.pop_front() {
+            tokeniser::Token::Identifier(name) => name,
+            token => self.error(format!("Expected constant name, got {:?}", token)),
+        };
+        self.ensure(tokeniser::Token::Equal);
+
+        Statement::Const(Constant {
+            name,
+            value: self.expression(),
+            file: self.file.clone(),
+            position,
+        })
+    }
+
+    /// A ZIG block just read, spanning from its `ZIG` keyword.
+    pub fn code(&self, code: String) -> Code {
+        Code {
+            code,
+            file: self.file.clone(),
+            position: self.position,
+        }
+    }
+
+    pub fn import(&mut self) -> Statement {
+        let position = self.position;
+
+        let mut names = Vec::new();
+        if let tokeniser::Token::Identifier(_) = self.peek() {
+            loop {
+                match self.pop_front() {
+                    tokeniser::Token::Identifier(name) if name != FROM => names.push(name),
+                    token => self.error(format!("Expected name to import, got {:?}", token)),
+                }
+                if self.peek() != tokeniser::Token::Comma {
+                    break;
+                }
+                self.ensure(tokeniser::Token::Comma);
+            }
+            self.ensure(tokeniser::Token::Identifier(FROM.to_string()));
+        }
+
+        let path = match self.pop_front() {
+            tokeniser::Token::String(path) => path,
+            token => self.error(format!("Expected path to import, got {:?}", token)),
+        };
+
+        Statement::Import(Import {
+            names,
+            path,
+            file: self.file.clone(),
+            position,
+        })
+    }
+
+    /// Called after the `}` of an IF branch. Opens the following `ELSE IF` or
+    /// `ELSE` branch if there is one, returning whether it is the ELSE branch.
+    pub fn next_branch<T>(&mut self, conditional: &mut Conditional<T>) -> Option<bool> {
+        if self.peek() != tokeniser::Token::Identifier(ELSE.to_string()) {
+            return None;
+        }
+        self.ensure(tokeniser::Token::Identifier(ELSE.to_string()));
+
+        if self.peek() == tokeniser::Token::Identifier(IF.to_string()) {
+            self.ensure(tokeniser::Token::Identifier(IF.to_string()));
+            let condition = self.expression();
+            self.ensure(tokeniser::Token::LBrace);
+            conditional.branches.push((condition, Vec::new()));
+            return Some(false);
+        }
+
+        self.ensure(tokeniser::Token::LBrace);
+        Some(true)
+    }
+
     pub fn property_name(&mut self) -> Step {
         if self.peek() == tokeniser::Token::RBrace {
             self.ensure(tokeniser::Token::RBrace);
+            if let Some((mut conditional, _)) = self.property_conditional.take() {
+                match self.next_branch(&mut conditional) {
+                    Some(otherwise) => self.property_conditional = Some((conditional, otherwise)),
+                    None => self.current_object().1.push(conditional),
+                }
+                return Step::PropertyName;
+            }
             return Step::ObjectEnd;
         }
 
+        if self.peek() == tokeniser::Token::Identifier(IF.to_string()) {
+            if self.property_conditional.is_some() {
+                self.error("IF blocks inside an object cannot be nested".to_string());
+            }
+            self.ensure(tokeniser::Token::Identifier(IF.to_string()));
+            let position = self.position;
+            let condition = self.expression();
+            self.ensure(tokeniser::Token::LBrace);
+            self.property_conditional = Some((
+                Conditional {
+                    branches: vec![(condition, Vec::new())],
+                    otherwise: Vec::new(),
+                    file: self.file.clone(),
+                    position,
+                },
+                false,
+            ));
+            return Step::PropertyName;
+        }
+
         self.ensure(tokeniser::Token::Dot);
+        let position = self.position;
 
         let name = self.pop_front();
         if let tokeniser::Token::Identifier(name) = name {
-            self.engine
-                .scene
-                .objects
-                .last_mut()
-                .unwrap()
-                .properties
-                .push(Property {
-                    name,
-                    value: Expression::Empty,
-                });
+            self.properties().push(Property {
+                name,
+                value: Expression::Empty,
+                position,
+            });
         }
 
         self.ensure(tokeniser::Token::Equal);
@@ -242,8 +1365,7 @@ impl Constructor {
 
     pub fn property_value(&mut self) -> Step {
         let value = self.expression();
-        let obj = self.engine.scene.objects.last_mut().unwrap();
-        let prop = obj.properties.last_mut().unwrap();
+        let prop = self.properties().last_mut().unwrap();
         prop.value = value;
 
         if self.peek() != tokeniser::Token::RBrace {
@@ -253,44 +1375,1059 @@ impl Constructor {
         Step::PropertyName
     }
 
-    pub fn make_expression(&mut self, token: &tokeniser::Token) -> Expression {
-        match token {
-            tokeniser::Token::Number(num) => Expression::Number(num.clone()),
-            tokeniser::Token::Identifier(idtfr) => Expression::Identifier(idtfr.clone()),
-            tokeniser::Token::String(str) => Expression::String(str.clone()),
-            _ => panic!("Unexpected token {:?}", token),
+    /// Parses the whole text as a single expression, such as the value of a
+    /// `-D name=value` definition.
+    pub fn definition(&mut self) -> Expression {
+        if let Some(position) = self.unknown {
+            report(&self.file, position, "Unexpected character".to_string());
+        }
+        let value = self.expression();
+        if !self.tokens.is_empty() {
+            let token = self.pop_front();
+            self.error(format!("Unexpected token {:?} after the value", token));
         }
+
+        value
     }
 
     pub fn expression(&mut self) -> Expression {
+        let mut lhs = self.conjunction();
+        while self.peek() == tokeniser::Token::OrOr {
+            self.ensure(tokeniser::Token::OrOr);
+            let rhs = self.conjunction();
+            lhs = Expression::Binary(Box::new(lhs), Operator::Or, Box::new(rhs));
+        }
+
+        lhs
+    }
+
+    pub fn conjunction(&mut self) -> Expression {
+        let mut lhs = self.comparison();
+        while self.peek() == tokeniser::Token::AndAnd {
+            self.ensure(tokeniser::Token::AndAnd);
+            let rhs = self.comparison();
+            lhs = Expression::Binary(Box::new(lhs), Operator::And, Box::new(rhs));
+        }
+
+        lhs
+    }
+
+    pub fn comparison(&mut self) -> Expression {
+        let lhs = self.sum();
+        let op = match self.peek() {
+            tokeniser::Token::EqualEqual => Operator::Equal,
+            tokeniser::Token::BangEqual => Operator::NotEqual,
+            tokeniser::Token::Less => Operator::Less,
+            tokeniser::Token::LessEqual => Operator::LessEqual,
+            tokeniser::Token::Greater => Operator::Greater,
+            tokeniser::Token::GreaterEqual => Operator::GreaterEqual,
+            _ => return lhs,
+        };
+        self.pop_front();
+        let rhs = self.sum();
+
+        Expression::Binary(Box::new(lhs), op, Box::new(rhs))
+    }
+
+    pub fn sum(&mut self) -> Expression {
+        let mut lhs = self.term();
+        loop {
+            let op = match self.peek() {
+                tokeniser::Token::Plus => Operator::Add,
+                tokeniser::Token::Minus => Operator::Subtract,
+                _ => return lhs,
+            };
+            self.pop_front();
+            let rhs = self.term();
+            lhs = Expression::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+    }
+
+    pub fn term(&mut self) -> Expression {
+        let mut lhs = self.unary();
+        loop {
+            let op = match self.peek() {
+                tokeniser::Token::Star => Operator::Multiply,
+                tokeniser::Token::Slash => Operator::Divide,
+                _ => return lhs,
+            };
+            self.pop_front();
+            let rhs = self.unary();
+            lhs = Expression::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+    }
+
+    pub fn unary(&mut self) -> Expression {
+        if self.peek() == tokeniser::Token::Bang {
+            self.ensure(tokeniser::Token::Bang);
+            return Expression::Not(Box::new(self.unary()));
+        }
+        if self.peek() != tokeniser::Token::Minus {
+            return self.primary();
+        }
+
+        self.ensure(tokeniser::Token::Minus);
+        match self.unary() {
+            // Keep negative literals as literals, so `-6` stays `-6` in the output
+            Expression::Number(num) if !num.starts_with('-') => {
+                Expression::Number(format!("-{}", num))
+            }
+            expr => Expression::Negate(Box::new(expr)),
+        }
+    }
+
+    pub fn primary(&mut self) -> Expression {
+        let mut expr = self.atom();
+        while self.peek() == tokeniser::Token::Dot {
+            self.ensure(tokeniser::Token::Dot);
+            match self.pop_front() {
+                tokeniser::Token::Identifier(member) => {
+                    expr = Expression::Member(Box::new(expr), member);
+                }
+                token => self.error(format!("Expected member name, got {:?}", token)),
+            }
+        }
+
+        expr
+    }
+
+    pub fn atom(&mut self) -> Expression {
         let front = self.pop_front();
         match front {
             tokeniser::Token::Number(num) => Expression::Number(num),
-            tokeniser::Token::Identifier(idtfr) => Expression::Identifier(idtfr),
+            tokeniser::Token::Identifier(idtfr) => {
+                if self.peek() == tokeniser::Token::LParen {
+                    Expression::Call(idtfr, self.arguments())
+                } else {
+                    Expression::Identifier(idtfr)
+                }
+            }
             tokeniser::Token::String(str) => Expression::String(str),
+            tokeniser::Token::Color(hex) => Expression::Color(hex),
+            tokeniser::Token::Zig(code) => Expression::Zig(self.code(code)),
             tokeniser::Token::LParen => {
-                let mut tokens = Vec::new();
-                loop {
-                    let token = self.pop_front();
-                    match token {
-                        tokeniser::Token::RParen => break,
-                        _ => tokens.push(token),
+                let mut values = Vec::new();
+                let mut trailing_comma = false;
+                while self.peek() != tokeniser::Token::RParen {
+                    values.push(self.expression());
+                    trailing_comma = self.peek() != tokeniser::Token::RParen;
+                    if trailing_comma {
+                        self.ensure(tokeniser::Token::Comma);
+                    }
+                }
+                self.ensure(tokeniser::Token::RParen);
+
+                // `(expr)` is plain grouping, `(a, b, c)` and `(a,)` are vectors
+                if values.len() == 1 && !trailing_comma {
+                    values.pop().unwrap()
+                } else {
+                    Expression::Group(values)
+                }
+            }
+            tokeniser::Token::LBracket => {
+                let mut values = Vec::new();
+                while self.peek() != tokeniser::Token::RBracket {
+                    values.push(self.expression());
+                    if self.peek() != tokeniser::Token::RBracket {
+                        self.ensure(tokeniser::Token::Comma);
                     }
-                    if self.peek() != tokeniser::Token::RParen {
+                }
+                self.ensure(tokeniser::Token::RBracket);
+
+                Expression::List(values)
+            }
+            tokeniser::Token::LBrace => {
+                let mut entries = Vec::new();
+                while self.peek() != tokeniser::Token::RBrace {
+                    let key = match self.pop_front() {
+                        tokeniser::Token::Identifier(key)
+                        | tokeniser::Token::Number(key)
+                        | tokeniser::Token::String(key) => key,
+                        token => self.error(format!("Expected key, got {:?}", token)),
+                    };
+                    if entries.iter().any(|(k, _)| *k == key) {
+                        self.error(format!("Duplicate key: {}", key));
+                    }
+                    self.ensure(tokeniser::Token::Colon);
+                    entries.push((key, self.expression()));
+                    if self.peek() != tokeniser::Token::RBrace {
                         self.ensure(tokeniser::Token::Comma);
                     }
                 }
-                Expression::Group(tokens.iter().map(|t| self.make_expression(t)).collect())
+                self.ensure(tokeniser::Token::RBrace);
+
+                Expression::Map(entries)
             }
-            _ => panic!("Unexpected token {:?}", front),
+            _ => self.error(format!("Unexpected token {:?}", front)),
         }
     }
 
+    pub fn arguments(&mut self) -> Vec<Expression> {
+        self.ensure(tokeniser::Token::LParen);
+        let mut args = Vec::new();
+        while self.peek() != tokeniser::Token::RParen {
+            args.push(self.expression());
+            if self.peek() != tokeniser::Token::RParen {
+                self.ensure(tokeniser::Token::Comma);
+            }
+        }
+        self.ensure(tokeniser::Token::RParen);
+
+        args
+    }
+
     pub fn object_end(&mut self) -> Step {
         if self.peek() == tokeniser::Token::RBrace {
             self.ensure(tokeniser::Token::RBrace);
-            return Step::End;
+            return match self.frames.pop() {
+                Some(Frame::For(for_loop)) => {
+                    self.statements().push(Statement::For(for_loop));
+                    Step::ObjectEnd
+                }
+                Some(Frame::If(mut conditional, _)) => {
+                    match self.next_branch(&mut conditional) {
+                        Some(otherwise) => self.frames.push(Frame::If(conditional, otherwise)),
+                        None => self.statements().push(Statement::If(conditional)),
+                    }
+                    Step::ObjectEnd
+                }
+                Some(Frame::Prefab(prefab)) => {
+                    self.statements().push(Statement::Prefab(prefab));
+                    Step::ObjectEnd
+                }
+                None => {
+                    self.in_scene = false;
+                    Step::Start
+                }
+            };
+        }
+
+        if self.frames.is_empty() && !self.in_scene {
+            return Step::Start;
         }
         Step::Object
     }
+
+    /// Expands loops into concrete objects, substituting loop variables into
+    /// property values and `{variable}` placeholders in names.
+    pub fn expand(&mut self, statements: &[Statement], bindings: &mut Vec<(String, Expression)>) {
+        // Constants are scoped to the block they are declared in
+        let scope = bindings.len();
+        for statement in statements {
+            match statement {
+                Statement::Object(object) => {
+                    if self.objects.len() >= MAX_GENERATED_OBJECTS {
+                        report(
+                            &object.file,
+                            object.position,
+                            format!(
+                                "Scene has more than {} objects, check your FOR loops",
+                                MAX_GENERATED_OBJECTS
+                            ),
+                        );
+                    }
+                    let mut properties = object.properties.clone();
+                    for conditional in &object.conditionals {
+                        properties.extend(self.branch(conditional, bindings).iter().cloned());
+                    }
+
+                    let name = interpolate(&object.name, bindings)
+                        .unwrap_or_else(|message| report(&object.file, object.position, message));
+                    self.objects.push(Object {
+                        name: format!("{}{}", self.prefix, name),
+                        obj_type: object.obj_type,
+                        base: object.base.as_ref().map(|base| {
+                            match substitute(&Expression::Identifier(base.clone()), bindings) {
+                                Ok(Expression::Identifier(base)) => base,
+                                Ok(other) => report(
+                                    &object.file,
+                                    object.position,
+                                    format!("Cannot extend {}", other),
+                                ),
+                                Err(message) => report(&object.file, object.position, message),
+                            }
+                        }),
+                        properties: properties
+                            .iter()
+                            .map(|prop| Property {
+                                name: prop.name.clone(),
+                                value: substitute(&prop.value, bindings).unwrap_or_else(
+                                    |message| report(&object.file, prop.position, message),
+                                ),
+                                position: prop.position,
+                            })
+                            .collect(),
+                        conditionals: Vec::new(),
+                        file: object.file.clone(),
+                        position: object.position,
+                    });
+                }
+                Statement::If(conditional) => {
+                    let body = self.branch(conditional, bindings).to_vec();
+                    self.expand(&body, bindings);
+                }
+                Statement::Prefab(prefab) => {
+                    if self.prefabs.contains_key(&prefab.name) {
+                        report(
+                            &prefab.file,
+                            prefab.position,
+                            format!("Duplicate prefab name: {}", prefab.name),
+                        );
+                    }
+                    self.prefabs
+                        .insert(prefab.name.clone(), (prefab.clone(), bindings.clone()));
+                }
+                Statement::Instance(instance) => self.instantiate(instance, bindings),
+                Statement::Import(import) => report(
+                    &import.file,
+                    import.position,
+                    format!("Unresolved import of {}", import.path),
+                ),
+                Statement::Const(constant) => {
+                    if self.defines.iter().any(|(name, _)| *name == constant.name) {
+                        continue;
+                    }
+                    let value = substitute(&constant.value, bindings).unwrap_or_else(|message| {
+                        report(&constant.file, constant.position, message)
+                    });
+                    let value = evaluator::Evaluator::located(&constant.file, constant.position)
+                        .fold(&value);
+                    bindings.push((constant.name.clone(), value));
+                }
+                Statement::For(for_loop) => {
+                    for value in self.iterate(for_loop, bindings) {
+                        self.iterations += 1;
+                        if self.iterations > MAX_ITERATIONS {
+                            report(
+                                &for_loop.file,
+                                for_loop.position,
+                                format!(
+                                    "Scene runs more than {} loop iterations, check your FOR loops",
+                                    MAX_ITERATIONS
+                                ),
+                            );
+                        }
+                        bindings.push((for_loop.variable.clone(), value));
+                        self.expand(&for_loop.body, bindings);
+                        bindings.pop();
+                    }
+                }
+            }
+        }
+        bindings.truncate(scope);
+    }
+
+    /// Expands a prefab instance. The prefab body sees the bindings the prefab
+    /// was declared in plus its parameters, and every object it generates is
+    /// renamed to `<instance>_<object>`, references included.
+    pub fn instantiate(&mut self, instance: &Instance, bindings: &[(String, Expression)]) {
+        let (prefab, scope) = match self.prefabs.get(&instance.prefab) {
+            Some(prefab) => prefab.clone(),
+            None => report(
+                &instance.file,
+                instance.position,
+                format!("Unknown prefab {}", instance.prefab),
+            ),
+        };
+        if self.instances.contains(&prefab.name) {
+            report(
+                &instance.file,
+                instance.position,
+                format!("Prefab `{}` instantiates itself", prefab.name),
+            );
+        }
+
+        let name = interpolate(&instance.name, bindings)
+            .unwrap_or_else(|message| report(&instance.file, instance.position, message));
+        let name = format!("{}{}", self.prefix, name);
+
+        let mut arguments = instance.arguments.clone();
+        for conditional in &instance.conditionals {
+            arguments.extend(self.branch(conditional, bindings).iter().cloned());
+        }
+        for argument in &arguments {
+            if !prefab.parameters.iter().any(|(p, _)| *p == argument.name) {
+                report(
+                    &instance.file,
+                    argument.position,
+                    format!(
+                        "Prefab `{}` has no parameter `{}` (in `{}`)",
+                        prefab.name, argument.name, name
+                    ),
+                );
+            }
+        }
+
+        let prefix = std::mem::replace(&mut self.prefix, format!("{}_", name));
+        self.instances.push(prefab.name.clone());
+
+        // A first pass finds the names of the objects the prefab generates, so
+        // that references to them are renamed before the arguments are
+        // substituted, as the arguments may name objects of the caller. Names
+        // don't depend on the renames, so nested prefabs skip their own pass.
+        let mut renames = Vec::new();
+        if !self.naming {
+            let first = self.objects.len();
+            let prefabs = self.prefabs.clone();
+            let mut bound = parameters(&prefab, instance, &arguments, &name, bindings, &scope);
+            self.naming = true;
+            self.expand(&prefab.body, &mut bound);
+            self.naming = false;
+            renames = self
+                .objects
+                .drain(first..)
+                .map(|object| {
+                    let local = object.name[self.prefix.len()..].to_string();
+                    (local, Expression::Identifier(object.name))
+                })
+                .collect();
+            self.prefabs = prefabs;
+        }
+
+        // Behind the bindings, which come before object names
+        let scope = [renames, scope].concat();
+        let mut bound = parameters(&prefab, instance, &arguments, &name, bindings, &scope);
+        self.expand(&prefab.body, &mut bound);
+        self.instances.pop();
+        self.prefix = prefix;
+    }
+
+    /// The body of the first branch whose condition holds.
+    pub fn branch<'a, T>(
+        &self,
+        conditional: &'a Conditional<T>,
+        bindings: &[(String, Expression)],
+    ) -> &'a [T] {
+        let evaluator = evaluator::Evaluator::located(&conditional.file, conditional.position);
+        for (condition, body) in &conditional.branches {
+            let value = substitute(condition, bindings)
+                .unwrap_or_else(|message| report(&conditional.file, conditional.position, message));
+            match evaluator.evaluate(&value) {
+                evaluator::Value::Bool(true) => return body,
+                evaluator::Value::Bool(false) => {}
+                other => report(
+                    &conditional.file,
+                    conditional.position,
+                    format!(
+                        "IF condition must be a boolean, got {} ({})",
+                        other.type_name(),
+                        condition
+                    ),
+                ),
+            }
+        }
+
+        &conditional.otherwise
+    }
+
+    /// The values of the variable of `for_loop`.
+    pub fn iterate(
+        &self,
+        for_loop: &ForLoop,
+        bindings: &[(String, Expression)],
+    ) -> Vec<Expression> {
+        let iterable = substitute(&for_loop.iterable, bindings)
+            .unwrap_or_else(|message| report(&for_loop.file, for_loop.position, message));
+        match &iterable {
+            Expression::Range(start, end) => {
+                let evaluator = evaluator::Evaluator::located(&for_loop.file, for_loop.position);
+                let bound = |expr: &Expression| match evaluator.evaluate(expr) {
+                    evaluator::Value::Number(num) if num.fract() == 0.0 => num as i64,
+                    other => report(
+                        &for_loop.file,
+                        for_loop.position,
+                        format!("Range bounds must be integers, got {:?}", other),
+                    ),
+                };
+                let (start, end) = (bound(start), bound(end));
+                if end - start > MAX_GENERATED_OBJECTS as i64 {
+                    report(
+                        &for_loop.file,
+                        for_loop.position,
+                        format!(
+                            "Range {}..{} has more than {} values",
+                            start, end, MAX_GENERATED_OBJECTS
+                        ),
+                    );
+                }
+                (start..end)
+                    .map(|i| Expression::Number(i.to_string()))
+                    .collect()
+            }
+            Expression::List(values) => values.clone(),
+            _ => report(
+                &for_loop.file,
+                for_loop.position,
+                format!("Expected range or list, got {}", iterable),
+            ),
+        }
+    }
+}
+
+/// The properties of `object` after copying in those of its base objects.
+pub fn inherited_properties(
+    object: &Object,
+    objects: &[Object],
+    chain: &mut Vec<String>,
+) -> Vec<Property> {
+    let base = match &object.base {
+        Some(base) => base,
+        None => return object.properties.clone(),
+    };
+
+    chain.push(object.name.clone());
+    if chain.contains(base) {
+        report(
+            &object.file,
+            object.position,
+            format!("Inheritance cycle: {} -> {}", chain.join(" -> "), base),
+        );
+    }
+
+    let base = match objects.iter().find(|o| o.name == *base) {
+        Some(base) => base,
+        None => report(
+            &object.file,
+            object.position,
+            format!("`{}` extends unknown object `{}`", object.name, base),
+        ),
+    };
+    if base.obj_type != object.obj_type {
+        report(
+            &object.file,
+            object.position,
+            format!(
+                "{} `{}` cannot extend {} `{}`",
+                object.obj_type.keyword(),
+                object.name,
+                base.obj_type.keyword(),
+                base.name
+            ),
+        );
+    }
+
+    let mut properties = inherited_properties(base, objects, chain);
+    // `.direction` and `.look_at` both aim the object, so either replaces the other
+    let aim = |name: &str| name == "direction" || name == "look_at";
+    for prop in &object.properties {
+        match properties
+            .iter_mut()
+            .find(|p| p.name == prop.name || (aim(&p.name) && aim(&prop.name)))
+        {
+            Some(inherited) => *inherited = prop.clone(),
+            None => properties.push(prop.clone()),
+        }
+    }
+
+    properties
+}
+
+/// Panics with `message`, prefixed with where in `file` it is about.
+pub fn report(file: &str, position: tokeniser::Position, message: String) -> ! {
+    match position {
+        // Objects that were not parsed, such as those loaded from JSON
+        (0, 0) => panic!("{}: {}", file, message),
+        (line, column) => panic!("{}:{}:{}: {}", file, line, column, message),
+    }
+}
+
+fn canonical(file: &str) -> PathBuf {
+    std::fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file))
+}
+
+/// Replaces every IMPORT in `statements` with the statements of the imported
+/// file. `stack` holds the files currently being imported, innermost last, and
+/// `imported` the files already imported whole, which are only included once.
+pub fn resolve_imports(
+    statements: &[Statement],
+    stack: &mut Vec<PathBuf>,
+    imported: &mut HashSet<PathBuf>,
+) -> Vec<Statement> {
+    let mut resolved = Vec::new();
+    for statement in statements {
+        let import = match statement {
+            Statement::Import(import) => import,
+            _ => {
+                resolved.push(statement.clone());
+                continue;
+            }
+        };
+
+        let directory = Path::new(&import.file).parent().unwrap_or(Path::new(""));
+        let path = directory.join(&import.path);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) => report(
+                &import.file,
+                import.position,
+                format!("Could not import {}: {}", path.display(), error),
+            ),
+        };
+        let path = canonical(&path.to_string_lossy());
+
+        if stack.contains(&path) {
+            let cycle: Vec<String> = stack
+                .iter()
+                .chain([&path])
+                .map(|file| file.display().to_string())
+                .collect();
+            report(
+                &import.file,
+                import.position,
+                format!("Import cycle: {}", cycle.join(" -> ")),
+            );
+        }
+        if import.names.is_empty() && !imported.insert(path.clone()) {
+            continue;
+        }
+
+        let mut library = Constructor::new(path.to_string_lossy().to_string(), content);
+        library.parse();
+        if !library.engine.scenes.is_empty() {
+            report(
+                &import.file,
+                import.position,
+                format!(
+                    "{} cannot be imported, it contains a {}",
+                    import.path, SCENE
+                ),
+            );
+        }
+
+        stack.push(path);
+        let body = resolve_imports(&library.engine.body, stack, imported);
+        stack.pop();
+
+        if import.names.is_empty() {
+            resolved.extend(body);
+            continue;
+        }
+
+        // Constants are always brought along, since the imported names may use them
+        for name in &import.names {
+            let found = body.iter().any(|statement| match statement {
+                Statement::Object(object) => object.name == *name,
+                Statement::Prefab(prefab) => prefab.name == *name,
+                Statement::Instance(instance) => instance.name == *name,
+                Statement::Const(constant) => constant.name == *name,
+                _ => false,
+            });
+            if !found {
+                report(
+                    &import.file,
+                    import.position,
+                    format!("{} has no `{}` to import", import.path, name),
+                );
+            }
+        }
+
+        // The names bring along the prefabs they instantiate and the objects
+        // they extend, which they cannot be constructed without
+        let mut names = import.names.clone();
+        let mut next = 0;
+        while next < names.len() {
+            let declaration = body.iter().find(|statement| match statement {
+                Statement::Object(object) => object.name == names[next],
+                Statement::Prefab(prefab) => prefab.name == names[next],
+                Statement::Instance(instance) => instance.name == names[next],
+                _ => false,
+            });
+            let mut found = Vec::new();
+            dependencies(declaration.map_or(&[], std::slice::from_ref), &mut found);
+            for name in found {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            next += 1;
+        }
+        resolved.extend(body.into_iter().filter(|statement| match statement {
+            Statement::Object(object) => names.contains(&object.name),
+            Statement::Prefab(prefab) => names.contains(&prefab.name),
+            Statement::Instance(instance) => names.contains(&instance.name),
+            Statement::Const(_) => true,
+            _ => false,
+        }));
+    }
+
+    resolved
+}
+
+/// Adds the names that `statements` need declared to be constructed: the
+/// bases of their objects and the prefabs of their instances.
+fn dependencies(statements: &[Statement], names: &mut Vec<String>) {
+    for statement in statements {
+        match statement {
+            Statement::Object(object) => names.extend(object.base.clone()),
+            Statement::Instance(instance) => names.push(instance.prefab.clone()),
+            Statement::Prefab(prefab) => dependencies(&prefab.body, names),
+            Statement::For(for_loop) => dependencies(&for_loop.body, names),
+            Statement::If(conditional) => {
+                for (_, body) in &conditional.branches {
+                    dependencies(body, names);
+                }
+                dependencies(&conditional.otherwise, names);
+            }
+            Statement::Const(_) | Statement::Import(_) => {}
+        }
+    }
+}
+
+fn lookup<'a>(name: &str, bindings: &'a [(String, Expression)]) -> Option<&'a Expression> {
+    bindings
+        .iter()
+        .rev()
+        .find(|(variable, _)| variable == name)
+        .map(|(_, value)| value)
+}
+
+/// `scope` with the value of each parameter of `prefab` for the instance
+/// `name`: its argument, substituted in the caller's `bindings`, or else its
+/// default, substituted in `scope` and the parameters before it.
+fn parameters(
+    prefab: &Prefab,
+    instance: &Instance,
+    arguments: &[Property],
+    name: &str,
+    bindings: &[(String, Expression)],
+    scope: &[(String, Expression)],
+) -> Vec<(String, Expression)> {
+    let mut scope = scope.to_vec();
+    for (parameter, default) in &prefab.parameters {
+        let value = match arguments.iter().rev().find(|a| a.name == *parameter) {
+            Some(argument) => substitute(&argument.value, bindings)
+                .unwrap_or_else(|message| report(&instance.file, argument.position, message)),
+            None => match default {
+                Some(default) => substitute(default, &scope)
+                    .unwrap_or_else(|message| report(&prefab.file, prefab.position, message)),
+                None => report(
+                    &instance.file,
+                    instance.position,
+                    format!(
+                        "Missing argument `{}` for `{}`, an instance of prefab `{}`",
+                        parameter, name, prefab.name
+                    ),
+                ),
+            },
+        };
+        scope.push((parameter.clone(), value));
+    }
+    scope
+}
+
+/// Replaces every `{variable}` in `name` with the variable's value.
+pub fn interpolate(name: &str, bindings: &[(String, Expression)]) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = name;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("Unterminated {{ in the name {}", name)),
+        };
+        let variable = &rest[start + 1..end];
+        output.push_str(&rest[..start]);
+        match lookup(variable, bindings) {
+            Some(Expression::Number(num)) if !num.contains('.') => {
+                output.push_str(&num.replace('-', "neg"))
+            }
+            Some(Expression::Identifier(idtfr)) => output.push_str(idtfr),
+            Some(value) => return Err(format!("Cannot use {} in the name {}", value, name)),
+            None => {
+                return Err(format!(
+                    "Unknown variable {} in the name {}",
+                    variable, name
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// Replaces loop variables in `expression` with their current values.
+pub fn substitute(
+    expression: &Expression,
+    bindings: &[(String, Expression)],
+) -> Result<Expression, String> {
+    let all = |exprs: &[Expression]| -> Result<Vec<Expression>, String> {
+        exprs.iter().map(|e| substitute(e, bindings)).collect()
+    };
+    let boxed = |expr: &Expression| substitute(expr, bindings).map(Box::new);
+    Ok(match expression {
+        Expression::Identifier(idtfr) => match lookup(idtfr, bindings) {
+            Some(value) => value.clone(),
+            None => Expression::Identifier(interpolate(idtfr, bindings)?),
+        },
+        Expression::Group(values) => Expression::Group(all(values)?),
+        Expression::List(values) => Expression::List(all(values)?),
+        Expression::Map(entries) => Expression::Map(
+            entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), substitute(value, bindings)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        Expression::Negate(expr) => Expression::Negate(boxed(expr)?),
+        Expression::Not(expr) => Expression::Not(boxed(expr)?),
+        Expression::Binary(lhs, op, rhs) => Expression::Binary(boxed(lhs)?, *op, boxed(rhs)?),
+        Expression::Call(name, args) => Expression::Call(name.clone(), all(args)?),
+        Expression::Member(base, member) => Expression::Member(boxed(base)?, member.clone()),
+        Expression::Range(start, end) => Expression::Range(boxed(start)?, boxed(end)?),
+        _ => expression.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "test.zest:5:1: Unexpected character")]
+    fn stray_character_between_scenes() {
+        let text = "SCENE first {\n}\nSTART first\n\n$\n\nSCENE second {\n}\n";
+        Constructor::new("test.zest".to_string(), text.to_string()).construct();
+    }
+
+    #[test]
+    fn arguments_are_not_renamed_as_objects_of_the_prefab() {
+        let text = "PREFAB lamp(target) {
+    SPHERE bulb {
+        .position = (0, 3, 0),
+    }
+    LIGHT light {
+        .position = bulb.position,
+        .look_at  = target,
+    }
+}
+SCENE s {
+    SPHERE bulb {
+        .position = (0, 0, 0),
+    }
+    lamp lamp {
+        .target = bulb,
+    }
+}
+";
+        let mut constructor = Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        let light = &constructor.engine.scenes[0].objects[2];
+        assert_eq!(light.name, "lamp_light");
+        let value = |name: &str| {
+            let property = light.properties.iter().find(|p| p.name == name).unwrap();
+            property.value.to_string()
+        };
+        assert_eq!(value("position"), "lamp_bulb.position");
+        assert_eq!(value("look_at"), "bulb");
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:5: Unknown prefab lamp")]
+    fn unknown_prefab() {
+        let text = "SCENE s {\n    lamp lamp {\n    }\n}\n";
+        Constructor::new("test.zest".to_string(), text.to_string()).construct();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "test.zest:2:5: WINDOW must be declared outside of scenes and prefabs"
+    )]
+    fn settings_are_not_declared_in_prefabs() {
+        let text = "PREFAB small() {\n    WINDOW {\n        .width = 200,\n    }\n}\nSCENE s {\n    small small {\n    }\n}\n";
+        Constructor::new("test.zest".to_string(), text.to_string()).construct();
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:1: Only one RENDER is allowed")]
+    fn settings_are_declared_once() {
+        let text = "RENDER {\n}\nRENDER {\n}\nSCENE s {\n}\n";
+        Constructor::new("test.zest".to_string(), text.to_string()).construct();
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:5: Scene runs more than 100000 loop iterations")]
+    fn loops_that_generate_nothing_are_bounded() {
+        let text = "SCENE s {\n    FOR i IN 0..1000 {\n    FOR j IN 0..1000 {\n    }\n    }\n}\n";
+        Constructor::new("test.zest".to_string(), text.to_string()).construct();
+    }
+
+    #[test]
+    fn unterminated_placeholders_are_errors() {
+        let bindings = [("i".to_string(), Expression::Number("1".to_string()))];
+        assert_eq!(interpolate("ball_{i}", &bindings), Ok("ball_1".to_string()));
+        assert_eq!(
+            interpolate("ball_{i", &bindings),
+            Err("Unterminated { in the name ball_{i".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "errors_in_imports/lib.zest:3:9: Division by zero")]
+    fn errors_in_imports_point_into_the_imported_file() {
+        let directory = std::env::temp_dir().join("errors_in_imports");
+        std::fs::create_dir_all(&directory).unwrap();
+        let library =
+            "PREFAB ball(size) {\n    SPHERE ball {\n        .radius = size / 0,\n    }\n}\n";
+        std::fs::write(directory.join("lib.zest"), library).unwrap();
+        let text =
+            "IMPORT \"lib.zest\"\nSCENE s {\n    ball ball {\n        .size = 1,\n    }\n}\n";
+        let file = directory.join("test.zest").to_string_lossy().to_string();
+        let mut constructor = Constructor::new(file, text.to_string());
+        constructor.construct();
+        evaluator::Evaluator::new().fold_engine(&mut constructor.engine);
+    }
+
+    #[test]
+    fn nested_prefabs_are_expanded_once() {
+        // Expanding each prefab twice per level would take 2^30 expansions
+        let mut text =
+            "PREFAB p0() {\n    SPHERE s {\n        .radius = 1,\n    }\n}\n".to_string();
+        for level in 1..30 {
+            text += &format!(
+                "PREFAB p{}() {{\n    p{} inner {{\n    }}\n    SPHERE s {{\n        .radius = inner_s.radius,\n    }}\n}}\n",
+                level,
+                level - 1
+            );
+        }
+        text += "SCENE main {\n    p29 top {\n    }\n}\n";
+        let mut constructor = Constructor::new("test.zest".to_string(), text);
+        constructor.construct();
+
+        let objects = &constructor.engine.scenes[0].objects;
+        assert_eq!(objects.len(), 30);
+        assert_eq!(objects[0].name, format!("top{}_s", "_inner".repeat(29)));
+        let top = objects.last().unwrap();
+        assert_eq!(top.name, "top_s");
+        assert_eq!(top.properties[0].value.to_string(), "top_inner_s.radius");
+    }
+
+    #[test]
+    fn imported_names_bring_their_dependencies() {
+        let directory = std::env::temp_dir().join("imported_names_bring_their_dependencies");
+        std::fs::create_dir_all(&directory).unwrap();
+        let library = "MATERIAL red {
+    .color = (255, 0, 0),
+}
+
+MATERIAL shiny EXTENDS red {
+    .reflectivity = 0.5,
+}
+
+MATERIAL blue {
+    .color = (0, 0, 255),
+}
+
+PREFAB bulb() {
+    SPHERE glass {
+        .radius = 1,
+    }
+}
+
+PREFAB lamp() {
+    bulb bulb {
+    }
+}
+
+lamp big {
+}
+";
+        std::fs::write(directory.join("lib.zest"), library).unwrap();
+        let text = "SCENE s {\n    IMPORT shiny, big FROM \"lib.zest\"\n}\n";
+        let file = directory.join("test.zest").to_string_lossy().to_string();
+        let mut constructor = Constructor::new(file, text.to_string());
+        constructor.construct();
+        let names: Vec<&str> = constructor.engine.scenes[0]
+            .objects
+            .iter()
+            .map(|o| o.name.as_str())
+            .collect();
+        assert_eq!(names, ["red", "shiny", "big_bulb_glass"]);
+    }
+
+    #[test]
+    fn every_scene_gets_its_imports() {
+        let directory = std::env::temp_dir().join("every_scene_gets_its_imports");
+        std::fs::create_dir_all(&directory).unwrap();
+        let library = "MATERIAL red {\n    .color = (255, 0, 0),\n}\n";
+        std::fs::write(directory.join("lib.zest"), library).unwrap();
+        let text =
+            "SCENE a {\n    IMPORT \"lib.zest\"\n}\n\nSCENE b {\n    IMPORT \"lib.zest\"\n}\n";
+        let file = directory.join("test.zest").to_string_lossy().to_string();
+        let mut constructor = Constructor::new(file, text.to_string());
+        constructor.construct();
+        for scene in &constructor.engine.scenes {
+            let names: Vec<&str> = scene.objects.iter().map(|o| o.name.as_str()).collect();
+            assert_eq!(names, ["red"], "{}", scene.name);
+        }
+    }
+
+    const BRANCHES: &str = "CONST mode = 2
+SCENE s {
+    IF mode == 1 {
+        SPHERE one {
+        }
+    } ELSE IF mode == 2 {
+        SPHERE two {
+        }
+    } ELSE {
+        SPHERE other {
+        }
+    }
+    SPHERE ball {
+        .radius = 1,
+        IF mode > 2 {
+            .radius = 2,
+        } ELSE {
+            .radius = 3,
+        }
+    }
+}
+";
+
+    /// The names of the objects of the only scene, and the radius `ball` ends up with.
+    fn branches(constructor: &mut Constructor) -> (Vec<String>, String) {
+        constructor.construct();
+        let objects = &constructor.engine.scenes[0].objects;
+        let ball = objects.iter().find(|o| o.name == "ball").unwrap();
+        let radius = ball.properties.iter().rev().find(|p| p.name == "radius");
+        (
+            objects.iter().map(|o| o.name.clone()).collect(),
+            radius.unwrap().value.to_string(),
+        )
+    }
+
+    #[test]
+    fn if_else_takes_one_branch() {
+        let mut constructor = Constructor::new("test.zest".to_string(), BRANCHES.to_string());
+        assert_eq!(
+            branches(&mut constructor),
+            (vec!["two".to_string(), "ball".to_string()], "3".to_string())
+        );
+    }
+
+    #[test]
+    fn definitions_override_constants() {
+        let mut constructor = Constructor::new("test.zest".to_string(), BRANCHES.to_string());
+        let value = Constructor::new("-Dmode".to_string(), "1 + 2".to_string()).definition();
+        constructor.define("mode".to_string(), value);
+        assert_eq!(
+            branches(&mut constructor),
+            (
+                vec!["other".to_string(), "ball".to_string()],
+                "2".to_string()
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "-Dmode:1:3: Unexpected token Number(\"4\") after the value")]
+    fn definitions_are_single_expressions() {
+        Constructor::new("-Dmode".to_string(), "3 4".to_string()).definition();
+    }
+
+    #[test]
+    #[should_panic(expected = "-Dmode:1:2: Unexpected token RParen after the value")]
+    fn definitions_have_no_stray_tokens() {
+        Constructor::new("-Dmode".to_string(), "3)".to_string()).definition();
+    }
 }
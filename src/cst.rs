@@ -0,0 +1,331 @@
+use crate::constructor;
+use crate::scope::Symbol;
+use crate::tokeniser::{Position, Token, Tokeniser, Trivia};
+use std::collections::VecDeque;
+
+/// A token as written, with the whitespace and comments before it
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub token: Token,
+    /// The token's text, exactly as written
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub position: Position,
+}
+
+/// A `// comment`, which runs to the end of its line
+#[derive(Debug, Clone)]
+pub struct Comment {
+    /// The position of its first slash
+    pub position: Position,
+    /// The comment from its slashes on, without trailing whitespace
+    pub text: String,
+    /// Whether code precedes it on its line
+    pub trailing: bool,
+}
+
+/// The lossless concrete syntax tree of a Zest file: every token with the
+/// whitespace and comments before it, its brackets matched on demand. Writing
+/// it out gives back the file byte for byte, and the constructor parses its
+/// tokens, so edits to it change nothing but what they touch.
+#[derive(Debug, Clone)]
+pub struct Cst {
+    pub elements: Vec<Element>,
+    /// The trivia after the last token
+    pub trailing: Vec<Trivia>,
+}
+
+impl Cst {
+    /// Parses `text`, read from `file`.
+    pub fn parse(file: &str, text: &str) -> Cst {
+        let mut tokeniser = Tokeniser::new(file.to_string(), text.to_string());
+        let mut elements = Vec::new();
+
+        let trailing = loop {
+            let leading = tokeniser.trivia();
+            let start = tokeniser.current;
+            let token = tokeniser.tokenise();
+            let text = match token {
+                Token::EoF => break leading,
+                // Nothing after an unknown character is tokenised, so it is kept whole
+                Token::Unknown => {
+                    tokeniser.current = tokeniser.text.len();
+                    tokeniser.slice(start, tokeniser.current)
+                }
+                _ => tokeniser.slice(start, tokeniser.current),
+            };
+            elements.push(Element {
+                token,
+                text,
+                leading,
+                position: (0, 0),
+            });
+        };
+
+        let mut cst = Cst { elements, trailing };
+        cst.reposition();
+        cst
+    }
+
+    /// Works out the position of every token from the text before it.
+    fn reposition(&mut self) {
+        let mut position = (1, 1);
+        let advance = |position: &mut Position, text: &str| {
+            for c in text.chars() {
+                *position = match c {
+                    '\n' => (position.0 + 1, 1),
+                    _ => (position.0, position.1 + 1),
+                };
+            }
+        };
+
+        for element in &mut self.elements {
+            for trivia in &element.leading {
+                match trivia {
+                    Trivia::Whitespace(text) | Trivia::Comment(text) => {
+                        advance(&mut position, text)
+                    }
+                }
+            }
+            element.position = position;
+            advance(&mut position, &element.text);
+        }
+    }
+
+    /// The tokens for the constructor, up to the first unknown character.
+    pub fn tokens(&self) -> (VecDeque<Token>, VecDeque<Position>) {
+        self.elements
+            .iter()
+            .take_while(|element| element.token != Token::Unknown)
+            .map(|element| (element.token.clone(), element.position))
+            .unzip()
+    }
+
+    /// Where the first unknown character is, if there is one.
+    pub fn unknown(&self) -> Option<Position> {
+        self.elements
+            .iter()
+            .find(|element| element.token == Token::Unknown)
+            .map(|element| element.position)
+    }
+
+    pub fn comments(&self) -> Vec<Comment> {
+        let mut comments = Vec::new();
+        let mut position = (1, 1);
+        // Whether a token has been seen on the current line
+        let mut code = false;
+
+        let trivia = self
+            .elements
+            .iter()
+            .map(|element| (&element.leading, Some(element)))
+            .chain([(&self.trailing, None)]);
+        for (leading, element) in trivia {
+            for trivia in leading {
+                match trivia {
+                    Trivia::Comment(text) => {
+                        comments.push(Comment {
+                            position,
+                            text: text.trim_end().to_string(),
+                            trailing: code,
+                        });
+                        position.1 += text.chars().count();
+                    }
+                    Trivia::Whitespace(text) => {
+                        let lines = text.matches('\n').count();
+                        if lines > 0 {
+                            code = false;
+                            position = (position.0 + lines, 1);
+                        }
+                        let last = text.rsplit('\n').next().unwrap_or("");
+                        position.1 += last.chars().count();
+                    }
+                }
+            }
+            if let Some(element) = element {
+                let lines = element.text.matches('\n').count();
+                position = match lines {
+                    0 => (element.position.0, element.position.1),
+                    _ => (element.position.0 + lines, 1),
+                };
+                let last = element.text.rsplit('\n').next().unwrap_or("");
+                position.1 += last.chars().count();
+                code = true;
+            }
+        }
+
+        comments
+    }
+
+    /// The index of the token at `position`.
+    pub fn index(&self, position: Position) -> Option<usize> {
+        self.elements
+            .binary_search_by_key(&position, |element| element.position)
+            .ok()
+    }
+
+    /// The index of the token at `position`, or of the one ending there, so
+    /// a cursor just after a name is still on it.
+    pub fn at(&self, position: Position) -> Option<usize> {
+        let index = self
+            .elements
+            .partition_point(|element| element.position <= position)
+            .checked_sub(1)?;
+        let element = &self.elements[index];
+        let end = element.position.1 + element.text.chars().count();
+        (element.position.0 == position.0 && position.1 <= end).then_some(index)
+    }
+
+    /// The index of the bracket closing the one at `open`.
+    pub fn matching(&self, open: usize) -> Option<usize> {
+        let mut depth = 0;
+        for (i, element) in self.elements.iter().enumerate().skip(open) {
+            match element.token {
+                Token::LBrace | Token::LParen | Token::LBracket => depth += 1,
+                Token::RBrace | Token::RParen | Token::RBracket => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Replaces the token at `index` with `text`, which must be a single
+    /// token, leaving the rest of the file untouched. Positions are left for
+    /// the caller to work out again.
+    fn replace(&mut self, index: usize, text: &str) {
+        let replacement = Cst::parse("replacement", text);
+        match &replacement.elements[..] {
+            [element] if element.leading.is_empty() && replacement.trailing.is_empty() => {
+                self.elements[index].token = element.token.clone();
+                self.elements[index].text = element.text.clone();
+            }
+            _ => panic!("`{}` is not a single token", text),
+        }
+    }
+
+    /// Whether the identifier `name` appears anywhere, whole or as a
+    /// `{name}` placeholder.
+    fn mentions(&self, name: &str) -> bool {
+        let placeholder = format!("{{{}}}", name);
+        self.elements.iter().any(|element| match &element.token {
+            Token::Identifier(identifier) => {
+                identifier == name || identifier.contains(&placeholder)
+            }
+            _ => false,
+        })
+    }
+
+    /// Renames `symbol` to `new` where it is declared and everywhere it is
+    /// referred to, returning how many names were changed. Files importing
+    /// it are not changed.
+    pub fn rename(&mut self, symbol: &Symbol, new: &str) -> usize {
+        let cst = Cst::parse("new name", new);
+        let identifier = match &cst.elements[..] {
+            [element] => element.text == new && matches!(element.token, Token::Identifier(_)),
+            _ => false,
+        };
+        if !identifier || new.contains('{') || constructor::is_keyword(new) {
+            panic!("`{}` is not a name", new);
+        }
+        if symbol.imported {
+            panic!("`{}` is declared in another file", symbol.name);
+        }
+        // A name in use anywhere could be captured, or capture
+        if self.mentions(new) {
+            panic!("`{}` is already used", new);
+        }
+
+        let mut occurrences: Vec<(usize, usize)> = symbol.occurrences().collect();
+        occurrences.sort();
+        // From the end, so the columns of earlier occurrences in a token hold
+        for &(i, column) in occurrences.iter().rev() {
+            let mut text = self.elements[i].text.clone();
+            text.replace_range(column..column + symbol.name.len(), new);
+            self.replace(i, &text);
+        }
+        self.reposition();
+        occurrences.len()
+    }
+}
+
+impl std::fmt::Display for Cst {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let trivia = |f: &mut std::fmt::Formatter, trivia: &[Trivia]| {
+            for trivia in trivia {
+                match trivia {
+                    Trivia::Whitespace(text) | Trivia::Comment(text) => write!(f, "{}", text)?,
+                }
+            }
+            Ok(())
+        };
+
+        for element in &self.elements {
+            trivia(f, &element.leading)?;
+            write!(f, "{}", element.text)?;
+        }
+        trivia(f, &self.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_round_trip() {
+        let mut directories = vec![std::path::PathBuf::from("examples")];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    directories.push(path);
+                } else if path
+                    .extension()
+                    .is_some_and(|extension| extension == "zest")
+                {
+                    let text = std::fs::read_to_string(&path).unwrap();
+                    assert_eq!(
+                        Cst::parse("test.zest", &text).to_string(),
+                        text,
+                        "{}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:14: Unterminated string")]
+    fn unterminated_strings_are_located() {
+        Cst::parse("test.zest", "WINDOW window {\n    .title = \"Demo,\n}\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:15: Unterminated literal in ZIG block")]
+    fn unterminated_zig_literals_are_located() {
+        Cst::parse(
+            "test.zest",
+            "SCENE s {\n    ZIG {\n        print(\"oops);\n    }\n}\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:5: Unterminated ZIG block")]
+    fn unterminated_zig_blocks_are_located() {
+        Cst::parse("test.zest", "SCENE s {\n    ZIG {\n        x();\n");
+    }
+
+    #[test]
+    fn placeholders_belong_to_names() {
+        let cst = Cst::parse("test.zest", "SPHERE ball_{i} {}\nSPHERE b{1}");
+        let texts: Vec<&str> = cst.elements.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            ["SPHERE", "ball_{i}", "{", "}", "SPHERE", "b", "{", "1", "}"]
+        );
+    }
+}
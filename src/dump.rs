@@ -0,0 +1,633 @@
+use crate::constructor::{
+    self, Code, Engine, Expression, Object, ObjectType, Operator, Property, Scene,
+};
+use crate::evaluator;
+use crate::json::{self, Json};
+
+/// The version of the JSON layout, bumped whenever it changes in a way
+/// older readers would misread.
+pub const VERSION: u64 = 1;
+
+const OPERATORS: [Operator; 12] = [
+    Operator::Add,
+    Operator::Subtract,
+    Operator::Multiply,
+    Operator::Divide,
+    Operator::Equal,
+    Operator::NotEqual,
+    Operator::Less,
+    Operator::LessEqual,
+    Operator::Greater,
+    Operator::GreaterEqual,
+    Operator::And,
+    Operator::Or,
+];
+
+/// The properties the constructor gives ZIG blocks, which aren't written in
+/// Zest and so have no documentation.
+const ZIG: [&str; 2] = ["code", "top_level"];
+
+/// The engine as JSON, with one entry per concrete object of each scene.
+pub fn dump(engine: &Engine) -> Json {
+    let scenes = engine
+        .scenes
+        .iter()
+        .map(|scene| {
+            json::object(vec![
+                ("name", Json::String(scene.name.clone())),
+                (
+                    "objects",
+                    Json::Array(scene.objects.iter().map(object).collect()),
+                ),
+            ])
+        })
+        .collect();
+
+    json::object(vec![
+        ("format", Json::String("zest".to_string())),
+        ("version", Json::Number(VERSION.to_string())),
+        ("start", Json::String(engine.start.clone())),
+        ("window", engine.window.as_ref().map_or(Json::Null, object)),
+        ("render", engine.render.as_ref().map_or(Json::Null, object)),
+        ("zig", Json::Array(engine.zig.iter().map(object).collect())),
+        ("scenes", Json::Array(scenes)),
+    ])
+}
+
+fn object(object: &Object) -> Json {
+    let properties = object
+        .properties
+        .iter()
+        .map(|prop| (prop.name.clone(), expression(&prop.value)))
+        .collect();
+
+    json::object(vec![
+        ("name", Json::String(object.name.clone())),
+        ("type", Json::String(object.obj_type.keyword().to_string())),
+        ("properties", Json::Object(properties)),
+    ])
+}
+
+/// Literals map onto their JSON counterparts, with groups as arrays, and
+/// everything else onto an object tagged by its first key.
+fn expression(expression: &Expression) -> Json {
+    match expression {
+        Expression::Number(num) if json::is_number(num) => Json::Number(num.clone()),
+        Expression::Number(num) => match num.parse::<f64>() {
+            Ok(value) if value.is_finite() => Json::Number(evaluator::format_number(value)),
+            _ => panic!("Cannot write {} as a JSON number", num),
+        },
+        Expression::Identifier(bool) if bool == "true" || bool == "false" => {
+            Json::Bool(bool == "true")
+        }
+        Expression::Identifier(name) => {
+            json::object(vec![("identifier", Json::String(name.clone()))])
+        }
+        Expression::String(str) => Json::String(str.clone()),
+        Expression::Group(values) => Json::Array(values.iter().map(self::expression).collect()),
+        Expression::Negate(value) => json::object(vec![("negate", self::expression(value))]),
+        Expression::Not(value) => json::object(vec![("not", self::expression(value))]),
+        Expression::Binary(lhs, op, rhs) => json::object(vec![
+            ("binary", Json::String(op.to_string())),
+            ("left", self::expression(lhs)),
+            ("right", self::expression(rhs)),
+        ]),
+        Expression::Call(name, args) => json::object(vec![
+            ("call", Json::String(name.clone())),
+            (
+                "arguments",
+                Json::Array(args.iter().map(self::expression).collect()),
+            ),
+        ]),
+        Expression::Member(value, member) => json::object(vec![
+            ("member", Json::String(member.clone())),
+            ("of", self::expression(value)),
+        ]),
+        Expression::Color(hex) => json::object(vec![("color", Json::String(hex.clone()))]),
+        Expression::List(values) => json::object(vec![(
+            "list",
+            Json::Array(values.iter().map(self::expression).collect()),
+        )]),
+        Expression::Map(map) => json::object(vec![(
+            "map",
+            Json::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), self::expression(value)))
+                    .collect(),
+            ),
+        )]),
+        Expression::Zig(code) => json::object(vec![
+            ("zig", Json::String(code.code.clone())),
+            ("file", Json::String(code.file.clone())),
+            ("line", Json::Number(code.position.0.to_string())),
+            ("column", Json::Number(code.position.1.to_string())),
+        ]),
+        Expression::Range(from, to) => json::object(vec![(
+            "range",
+            Json::Array(vec![self::expression(from), self::expression(to)]),
+        )]),
+        Expression::Empty => Json::Null,
+    }
+}
+
+/// Reads an engine written by `dump`, panicking with the path to the first
+/// value that doesn't fit the layout.
+pub fn load(file: &str, json: &Json) -> Engine {
+    let loader = Loader { file };
+    loader.keys(
+        json,
+        &[
+            "format", "version", "start", "window", "render", "zig", "scenes",
+        ],
+        "",
+    );
+
+    if loader.string(loader.field(json, "format", ""), "format") != "zest" {
+        loader.error("format", "Expected \"zest\"");
+    }
+    let version = loader.field(json, "version", "");
+    if *version != Json::Number(VERSION.to_string()) {
+        loader.error(
+            "version",
+            &format!(
+                "Unsupported version {}, expected {}",
+                version.pretty().trim_end(),
+                VERSION
+            ),
+        );
+    }
+
+    let mut scenes = Vec::new();
+    for (i, scene) in loader
+        .array(loader.field(json, "scenes", ""), "scenes")
+        .iter()
+        .enumerate()
+    {
+        let path = format!("scenes[{}]", i);
+        loader.keys(scene, &["name", "objects"], &path);
+
+        let name = loader.name(
+            loader.field(scene, "name", &path),
+            &format!("{}.name", path),
+        );
+        if scenes.iter().any(|s: &Scene| s.name == name) {
+            loader.error(&path, &format!("Duplicate scene name: {}", name));
+        }
+        let objects = loader
+            .array(
+                loader.field(scene, "objects", &path),
+                &format!("{}.objects", path),
+            )
+            .iter()
+            .enumerate()
+            .map(|(j, object)| loader.object(object, &format!("{}.objects[{}]", path, j)))
+            .collect();
+
+        // JSON keeps no positions
+        scenes.push(Scene {
+            name,
+            position: (0, 0),
+            body: Vec::new(),
+            objects,
+        });
+    }
+    if scenes.is_empty() {
+        loader.error("scenes", "Expected at least one scene");
+    }
+
+    let start = loader.string(loader.field(json, "start", ""), "start");
+    if !scenes.iter().any(|scene| scene.name == start) {
+        loader.error("start", &format!("Unknown start scene: {}", start));
+    }
+
+    let settings = |key: &str, obj_type: ObjectType| match json.get(key) {
+        None | Some(Json::Null) => None,
+        Some(value) => {
+            let object = loader.object(value, key);
+            if object.obj_type != obj_type {
+                loader.error(key, &format!("Expected {} object", obj_type.keyword()));
+            }
+            Some(object)
+        }
+    };
+    let window = settings("window", ObjectType::Window);
+    let render = settings("render", ObjectType::Render);
+
+    let zig = match json.get("zig") {
+        None => Vec::new(),
+        Some(zig) => loader
+            .array(zig, "zig")
+            .iter()
+            .enumerate()
+            .map(|(i, object)| {
+                let path = format!("zig[{}]", i);
+                let object = loader.object(object, &path);
+                if object.obj_type != ObjectType::Zig {
+                    loader.error(&path, "Expected ZIG object");
+                }
+                object
+            })
+            .collect(),
+    };
+
+    Engine {
+        body: Vec::new(),
+        scenes,
+        start,
+        window,
+        render,
+        zig,
+    }
+}
+
+struct Loader<'a> {
+    file: &'a str,
+}
+
+impl Loader<'_> {
+    fn error(&self, path: &str, message: &str) -> ! {
+        if path.is_empty() {
+            panic!("{}: {}", self.file, message)
+        }
+        panic!("{}: {}: {}", self.file, path, message)
+    }
+
+    fn field<'j>(&self, json: &'j Json, key: &str, path: &str) -> &'j Json {
+        match json.get(key) {
+            Some(value) => value,
+            None => self.error(path, &format!("Missing \"{}\"", key)),
+        }
+    }
+
+    /// Checks that `json` is an object with no keys beyond `allowed`, so
+    /// misspelt keys aren't silently dropped.
+    fn keys(&self, json: &Json, allowed: &[&str], path: &str) {
+        let entries = match json {
+            Json::Object(entries) => entries,
+            other => self.error(path, &format!("Expected object, got {}", other.type_name())),
+        };
+        if let Some((key, _)) = entries
+            .iter()
+            .find(|(key, _)| !allowed.contains(&key.as_str()))
+        {
+            self.error(path, &format!("Unknown key \"{}\"", key));
+        }
+    }
+
+    fn string(&self, json: &Json, path: &str) -> String {
+        match json {
+            Json::String(str) => str.clone(),
+            other => self.error(path, &format!("Expected string, got {}", other.type_name())),
+        }
+    }
+
+    fn array<'j>(&self, json: &'j Json, path: &str) -> &'j [Json] {
+        match json {
+            Json::Array(values) => values,
+            other => self.error(path, &format!("Expected array, got {}", other.type_name())),
+        }
+    }
+
+    /// A string that is a Zest identifier, since names are spliced into the
+    /// Zig as they are.
+    fn name(&self, json: &Json, path: &str) -> String {
+        let name = self.string(json, path);
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            self.error(path, &format!("Invalid identifier \"{}\"", name));
+        }
+        name
+    }
+
+    fn usize(&self, json: &Json, path: &str) -> usize {
+        match json {
+            Json::Number(num) => match num.parse() {
+                Ok(num) => num,
+                Err(_) => self.error(path, &format!("Expected whole number, got {}", num)),
+            },
+            other => self.error(path, &format!("Expected number, got {}", other.type_name())),
+        }
+    }
+
+    fn object(&self, json: &Json, path: &str) -> Object {
+        self.keys(json, &["name", "type", "properties"], path);
+
+        let name = self.name(self.field(json, "name", path), &format!("{}.name", path));
+        let keyword = self.string(self.field(json, "type", path), &format!("{}.type", path));
+        let obj_type = match ObjectType::from_keyword(&keyword) {
+            Some(obj_type) => obj_type,
+            None => self.error(
+                &format!("{}.type", path),
+                &format!("Unknown object type: {}", keyword),
+            ),
+        };
+
+        let properties = match json.get("properties") {
+            None => Vec::new(),
+            Some(Json::Object(entries)) => {
+                let known = constructor::properties(obj_type);
+                entries
+                    .iter()
+                    .map(|(name, value)| {
+                        let path = format!("{}.properties.{}", path, name);
+                        let zig = obj_type == ObjectType::Zig && ZIG.contains(&name.as_str());
+                        if !zig && !known.iter().any(|(known, _)| known == name) {
+                            self.error(
+                                &path,
+                                &format!("Unknown property of {}: {}", keyword, name),
+                            );
+                        }
+                        Property {
+                            name: name.clone(),
+                            value: self.expression(value, &path),
+                            position: (0, 0),
+                        }
+                    })
+                    .collect()
+            }
+            Some(other) => self.error(
+                &format!("{}.properties", path),
+                &format!("Expected object, got {}", other.type_name()),
+            ),
+        };
+
+        Object {
+            name,
+            obj_type,
+            base: None,
+            properties,
+            conditionals: Vec::new(),
+            file: self.file.to_string(),
+            position: (0, 0),
+        }
+    }
+
+    fn expression(&self, json: &Json, path: &str) -> Expression {
+        let entries = match json {
+            Json::Null => return Expression::Empty,
+            Json::Bool(b) => return Expression::Identifier(b.to_string()),
+            Json::Number(num) => return Expression::Number(num.clone()),
+            Json::String(str) if str.contains(['"', '\n', '\r']) => {
+                self.error(path, "Strings cannot contain `\"` or line breaks")
+            }
+            Json::String(str) => return Expression::String(str.clone()),
+            Json::Array(values) => {
+                return Expression::Group(
+                    values
+                        .iter()
+                        .enumerate()
+                        .map(|(i, value)| self.expression(value, &format!("{}[{}]", path, i)))
+                        .collect(),
+                )
+            }
+            Json::Object(entries) => entries,
+        };
+
+        let tag = match entries.first() {
+            Some((tag, _)) => tag.as_str(),
+            None => self.error(path, "Expected tagged expression, got empty object"),
+        };
+        let value = &entries[0].1;
+        let field = |key: &str| self.field(json, key, path);
+        let sub = |key: &str, json: &Json| self.expression(json, &format!("{}.{}", path, key));
+        let list = |key: &str, json: &Json| -> Vec<Expression> {
+            self.array(json, &format!("{}.{}", path, key))
+                .iter()
+                .enumerate()
+                .map(|(i, value)| self.expression(value, &format!("{}.{}[{}]", path, key, i)))
+                .collect()
+        };
+
+        let (expression, keys): (Expression, &[&str]) = match tag {
+            "identifier" => (Expression::Identifier(self.name(value, path)), &[]),
+            "color" => {
+                let hex = self.string(value, path);
+                if !matches!(hex.len(), 3 | 6) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    self.error(path, &format!("Invalid colour: #{}", hex));
+                }
+                (Expression::Color(hex), &[])
+            }
+            "negate" => (Expression::Negate(Box::new(sub(tag, value))), &[]),
+            "not" => (Expression::Not(Box::new(sub(tag, value))), &[]),
+            "list" => (Expression::List(list(tag, value)), &[]),
+            "binary" => {
+                let symbol = self.string(value, path);
+                let op = match OPERATORS.iter().find(|op| op.to_string() == symbol) {
+                    Some(op) => *op,
+                    None => self.error(path, &format!("Unknown operator: {}", symbol)),
+                };
+                let lhs = sub("left", field("left"));
+                let rhs = sub("right", field("right"));
+                (
+                    Expression::Binary(Box::new(lhs), op, Box::new(rhs)),
+                    &["left", "right"],
+                )
+            }
+            "call" => (
+                Expression::Call(
+                    self.name(value, path),
+                    list("arguments", field("arguments")),
+                ),
+                &["arguments"],
+            ),
+            "member" => (
+                Expression::Member(Box::new(sub("of", field("of"))), self.name(value, path)),
+                &["of"],
+            ),
+            "map" => {
+                let map = match value {
+                    Json::Object(map) => map
+                        .iter()
+                        .map(|(key, value)| {
+                            // Zest also takes strings as keys, though only key
+                            // names such as `W` or `Space` are ever bound
+                            if key.is_empty()
+                                || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                            {
+                                self.error(path, &format!("Invalid key \"{}\"", key));
+                            }
+                            (key.clone(), sub(key, value))
+                        })
+                        .collect(),
+                    other => {
+                        self.error(path, &format!("Expected object, got {}", other.type_name()))
+                    }
+                };
+                (Expression::Map(map), &[])
+            }
+            "zig" => {
+                let code = Code {
+                    code: self.string(value, path),
+                    file: match self.string(field("file"), &format!("{}.file", path)) {
+                        file if file.contains(['\n', '\r']) => self.error(
+                            &format!("{}.file", path),
+                            "File names cannot contain line breaks",
+                        ),
+                        file => file,
+                    },
+                    position: (
+                        self.usize(field("line"), &format!("{}.line", path)),
+                        self.usize(field("column"), &format!("{}.column", path)),
+                    ),
+                };
+                (Expression::Zig(code), &["file", "line", "column"])
+            }
+            "range" => match &list(tag, value)[..] {
+                [from, to] => (
+                    Expression::Range(Box::new(from.clone()), Box::new(to.clone())),
+                    &[],
+                ),
+                _ => self.error(path, "Expected range of two values"),
+            },
+            tag => self.error(path, &format!("Unknown expression \"{}\"", tag)),
+        };
+
+        let allowed: Vec<&str> = std::iter::once(tag).chain(keys.iter().copied()).collect();
+        self.keys(json, &allowed, path);
+        expression
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructor::Constructor;
+    use crate::transpiler::Transpiler;
+
+    fn engine(file: &str, text: &str) -> Engine {
+        let mut constructor = Constructor::new(file.to_string(), text.to_string());
+        constructor.construct();
+        evaluator::Evaluator::new().fold_engine(&mut constructor.engine);
+        constructor.engine
+    }
+
+    /// Loads `json` as the file `test.json`.
+    fn load_text(json: &str) -> Engine {
+        load("test.json", &Json::parse("test.json", json))
+    }
+
+    /// A scene holding `object`.
+    fn scene(object: &str) -> String {
+        format!(
+            r#"{{"format": "zest", "version": 1, "start": "main",
+                "scenes": [{{"name": "main", "objects": [{}]}}]}}"#,
+            object
+        )
+    }
+
+    #[test]
+    fn examples_round_trip() {
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "zest") {
+                continue;
+            }
+            let file = path.to_string_lossy().to_string();
+            let engine = engine(&file, &std::fs::read_to_string(&path).unwrap());
+            let json = dump(&engine).pretty();
+
+            let mut loaded = load("test.json", &Json::parse("test.json", &json));
+            evaluator::Evaluator::new().fold_engine(&mut loaded);
+            assert_eq!(dump(&loaded).pretty(), json, "{}", file);
+            assert_eq!(
+                Transpiler::new(loaded).transpile(),
+                Transpiler::new(engine).transpile(),
+                "{}",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn objects_are_loaded() {
+        let engine = load_text(&scene(
+            r#"{"name": "ball", "type": "SPHERE", "properties": {
+                "position": [0, 1, {"negate": 2}],
+                "radius": {"binary": "*", "left": 2, "right": {"identifier": "size"}}
+            }}"#,
+        ));
+        let ball = &engine.scenes[0].objects[0];
+        assert_eq!(ball.obj_type, ObjectType::Sphere);
+        assert!(
+            matches!(&ball.properties[0].value, Expression::Group(values) if values.len() == 3)
+        );
+        assert!(matches!(
+            &ball.properties[1].value,
+            Expression::Binary(_, Operator::Multiply, rhs)
+                if matches!(&**rhs, Expression::Identifier(name) if name == "size")
+        ));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "test.json: scenes[0].objects[0].name: Invalid identifier \"ball); @panic(\"\""
+    )]
+    fn names_are_identifiers() {
+        load_text(&scene(
+            r#"{"name": "ball); @panic(\"", "type": "SPHERE", "properties": {}}"#,
+        ));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "test.json: scenes[0].objects[0].properties.colour: Unknown property of SPHERE: colour"
+    )]
+    fn properties_are_known() {
+        load_text(&scene(
+            r#"{"name": "ball", "type": "SPHERE", "properties": {"colour": 1}}"#,
+        ));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "test.json: scenes[0].objects[0].properties.radius: Invalid identifier \"std.os.exit\""
+    )]
+    fn calls_are_to_identifiers() {
+        load_text(&scene(
+            r#"{"name": "ball", "type": "SPHERE", "properties": {
+                "radius": {"call": "std.os.exit", "arguments": [1]}
+            }}"#,
+        ));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "test.json: scenes[0].objects[0].properties.bindings: Invalid key \"W = .{}\""
+    )]
+    fn keys_are_names() {
+        load_text(&scene(
+            r#"{"name": "controller", "type": "CONTROLLER", "properties": {
+                "bindings": {"map": {"W = .{}": {"identifier": "forward"}}}
+            }}"#,
+        ));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "test.json: scenes[0].objects[0].properties.text: Strings cannot contain `\"` or line breaks"
+    )]
+    fn strings_are_quoted() {
+        load_text(&scene(
+            r#"{"name": "hint", "type": "TEXT", "properties": {"text": "\"; @panic(\""}}"#,
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.json: start: Unknown start scene: menu")]
+    fn start_is_a_scene() {
+        load_text(
+            r#"{"format": "zest", "version": 1, "start": "menu",
+                "scenes": [{"name": "main", "objects": []}]}"#,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.json: version: Unsupported version 2, expected 1")]
+    fn versions_are_checked() {
+        load_text(
+            r#"{"format": "zest", "version": 2, "start": "main",
+                "scenes": [{"name": "main", "objects": []}]}"#,
+        );
+    }
+}
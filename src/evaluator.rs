@@ -0,0 +1,909 @@
+use crate::constructor::{self, Expression, ObjectType, Operator};
+use crate::tokeniser;
+use std::collections::{HashMap, HashSet};
+
+const PI: &str = "PI";
+const TAU: &str = "TAU";
+
+// (name, arity) of every built-in function
+const BUILTINS: [(&str, usize); 14] = [
+    ("sin", 1),
+    ("cos", 1),
+    ("sqrt", 1),
+    ("abs", 1),
+    ("min", 2),
+    ("max", 2),
+    ("clamp", 3),
+    ("lerp", 3),
+    ("normalize", 1),
+    ("cross", 2),
+    ("dot", 2),
+    ("length", 1),
+    ("deg", 1),
+    ("rad", 1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Vector(Vec<f64>),
+    Bool(bool),
+    String(String),
+    // An identifier that is not a number, such as an object name
+    Symbol(String),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Vector(_) => "vector",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Symbol(_) => "identifier",
+        }
+    }
+
+    pub fn to_expression(&self) -> Expression {
+        match self {
+            Value::Number(num) => Expression::Number(format_number(*num)),
+            Value::Vector(values) => Expression::Group(
+                values
+                    .iter()
+                    .map(|v| Expression::Number(format_number(*v)))
+                    .collect(),
+            ),
+            Value::Bool(b) => Expression::Identifier(b.to_string()),
+            Value::String(str) => Expression::String(str.clone()),
+            Value::Symbol(idtfr) => Expression::Identifier(idtfr.clone()),
+        }
+    }
+}
+
+pub fn format_number(num: f64) -> String {
+    // Avoid emitting `-0`
+    if num == 0.0 {
+        return "0".to_string();
+    }
+    format!("{}", num)
+}
+
+pub struct Evaluator {
+    // Every object declared in the scene
+    names: HashSet<String>,
+    // Objects whose properties have already been folded
+    objects: HashMap<String, constructor::Object>,
+    // Where the expression being evaluated is written, for diagnostics
+    location: Option<(String, tokeniser::Position)>,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Self {
+            names: HashSet::new(),
+            objects: HashMap::new(),
+            location: None,
+        }
+    }
+
+    /// An evaluator that reports errors at `position` in `file`.
+    pub fn located(file: &str, position: tokeniser::Position) -> Self {
+        Self {
+            location: Some((file.to_string(), position)),
+            ..Self::new()
+        }
+    }
+
+    fn error(&self, message: String) -> ! {
+        match &self.location {
+            Some((file, position)) => constructor::report(file, *position, message),
+            None => panic!("{}", message),
+        }
+    }
+
+    /// Folds every constant expression of the engine into literals, so the
+    /// transpiler only ever sees numbers, identifiers, strings and groups.
+    pub fn fold_engine(&mut self, engine: &mut constructor::Engine) {
+        for object in engine.window.iter_mut().chain(engine.render.iter_mut()) {
+            for prop in &mut object.properties {
+                self.location = Some((object.file.clone(), prop.position));
+                prop.value = self.fold(&prop.value);
+            }
+        }
+
+        for scene in &mut engine.scenes {
+            self.names.clear();
+            self.objects.clear();
+            self.fold_scene(scene);
+        }
+    }
+
+    /// Objects are folded in declaration order, so member accesses such as
+    /// `sphere.position` can only refer to objects declared earlier in the
+    /// same scene.
+    pub fn fold_scene(&mut self, scene: &mut constructor::Scene) {
+        // ZIG blocks are named after where they are written, which repeats in loops
+        for object in scene
+            .objects
+            .iter()
+            .filter(|o| o.obj_type != ObjectType::Zig)
+        {
+            if !self.names.insert(object.name.clone()) {
+                self.location = Some((object.file.clone(), object.position));
+                self.error(format!("Duplicate object name: {}", object.name));
+            }
+        }
+
+        for object in &mut scene.objects {
+            for prop in &mut object.properties {
+                // The animated property is a reference, not a value
+                if object.obj_type == ObjectType::Animation && prop.name == "target" {
+                    continue;
+                }
+                self.location = Some((object.file.clone(), prop.position));
+                prop.value = self.fold(&prop.value);
+            }
+            if matches!(object.obj_type, ObjectType::Camera | ObjectType::Light) {
+                self.resolve_direction(object);
+            }
+            self.objects.insert(object.name.clone(), object.clone());
+        }
+    }
+
+    pub fn fold(&self, expression: &Expression) -> Expression {
+        match expression {
+            Expression::Group(values) => {
+                Expression::Group(values.iter().map(|v| self.fold(v)).collect())
+            }
+            Expression::List(values) => {
+                Expression::List(values.iter().map(|v| self.fold(v)).collect())
+            }
+            Expression::Map(entries) => Expression::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.fold(value)))
+                    .collect(),
+            ),
+            Expression::Identifier(idtfr) if [PI, TAU].contains(&idtfr.as_str()) => {
+                self.evaluate(expression).to_expression()
+            }
+            Expression::Negate(_)
+            | Expression::Not(_)
+            | Expression::Color(_)
+            | Expression::Binary(_, _, _)
+            | Expression::Call(_, _) => self.evaluate(expression).to_expression(),
+            Expression::Member(base, member) => self.member(base, member),
+            _ => expression.clone(),
+        }
+    }
+
+    pub fn evaluate(&self, expression: &Expression) -> Value {
+        match expression {
+            Expression::Number(num) => match num.parse::<f64>() {
+                Ok(num) => Value::Number(num),
+                Err(_) => self.error(format!("Invalid number: {}", num)),
+            },
+            Expression::Identifier(idtfr) => match idtfr.as_str() {
+                PI => Value::Number(std::f64::consts::PI),
+                TAU => Value::Number(std::f64::consts::TAU),
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => Value::Symbol(idtfr.clone()),
+            },
+            Expression::Group(values) => Value::Vector(
+                values
+                    .iter()
+                    .map(|v| match self.evaluate(v) {
+                        Value::Number(num) => num,
+                        other => self.error(format!(
+                            "Expected number in vector, got {}",
+                            other.type_name()
+                        )),
+                    })
+                    .collect(),
+            ),
+            Expression::Negate(expr) => match self.evaluate(expr) {
+                Value::Number(num) => Value::Number(-num),
+                Value::Vector(values) => Value::Vector(values.iter().map(|v| -v).collect()),
+                other => self.error(format!("Cannot negate {}", other.type_name())),
+            },
+            Expression::Not(expr) => match self.evaluate(expr) {
+                Value::Bool(b) => Value::Bool(!b),
+                other => self.error(format!(
+                    "Expected bool after `!`, got {}",
+                    other.type_name()
+                )),
+            },
+            Expression::Binary(lhs, op @ (Operator::And | Operator::Or), rhs) => {
+                let short_circuit = *op == Operator::Or;
+                for side in [lhs, rhs] {
+                    match self.evaluate(side) {
+                        Value::Bool(b) if b == short_circuit => return Value::Bool(b),
+                        Value::Bool(_) => {}
+                        other => self.error(format!(
+                            "Expected bool around `{}`, got {}",
+                            op,
+                            other.type_name()
+                        )),
+                    }
+                }
+                Value::Bool(!short_circuit)
+            }
+            Expression::Binary(lhs, op, rhs) => {
+                self.binary(self.evaluate(lhs), *op, self.evaluate(rhs))
+            }
+            Expression::Call(name, args) => {
+                let args = args.iter().map(|a| self.evaluate(a)).collect();
+                self.call(name, args)
+            }
+            Expression::Member(base, member) => self.evaluate(&self.member(base, member)),
+            Expression::String(str) => Value::String(str.clone()),
+            Expression::Color(hex) => {
+                let digits: Vec<u32> = hex.chars().filter_map(|c| c.to_digit(16)).collect();
+                let channels: Vec<f64> = match (hex.len(), digits.len()) {
+                    (6, 6) => digits
+                        .chunks(2)
+                        .map(|c| (c[0] * 16 + c[1]) as f64)
+                        .collect(),
+                    (3, 3) => digits.iter().map(|c| (c * 17) as f64).collect(),
+                    _ => self.error(format!("Invalid colour: #{}", hex)),
+                };
+                Value::Vector(channels)
+            }
+            Expression::Zig(code) => {
+                panic!("{}: ZIG blocks cannot be used in expressions", code.span())
+            }
+            Expression::List(_) | Expression::Map(_) | Expression::Range(_, _) => self.error(
+                format!("Cannot use {} in an arithmetic expression", expression),
+            ),
+            Expression::Empty => self.error("Expected expression".to_string()),
+        }
+    }
+
+    /// Resolves `base.member`, either as a property (or derived value) of a
+    /// previously declared object, or as a component of a vector.
+    pub fn member(&self, base: &Expression, member: &str) -> Expression {
+        if let Expression::Identifier(name) = base {
+            if self.names.contains(name) {
+                return self.object_member(name, member);
+            }
+            if ![PI, TAU].contains(&name.as_str()) {
+                self.error(format!("Unknown object: {}", name));
+            }
+        }
+
+        let values = match self.evaluate(base) {
+            Value::Vector(values) => values,
+            other => self.error(format!(
+                "Cannot access `{}` of a {}",
+                member,
+                other.type_name()
+            )),
+        };
+        let index = match member {
+            "x" => 0,
+            "y" => 1,
+            "z" => 2,
+            _ => self.error(format!("Unknown vector component: {}", member)),
+        };
+        match values.get(index) {
+            Some(value) => Value::Number(*value).to_expression(),
+            None => self.error(format!(
+                "Vector of length {} has no component `{}`",
+                values.len(),
+                member
+            )),
+        }
+    }
+
+    pub fn object_member(&self, name: &str, member: &str) -> Expression {
+        let object = match self.objects.get(name) {
+            Some(object) => object,
+            None => self.error(format!(
+                "Object `{}` is referenced before it is declared",
+                name
+            )),
+        };
+
+        if let Some(prop) = object.properties.iter().rev().find(|p| p.name == member) {
+            return prop.value.clone();
+        }
+
+        match (object.obj_type, member) {
+            (ObjectType::Sphere, "center") => self.object_member(name, "position"),
+            (ObjectType::Rectangle, "center") => {
+                let v0 = self.vector_member(name, "v0");
+                let v1 = self.vector_member(name, "v1");
+                Value::Vector(
+                    v0.iter()
+                        .zip(v1.iter())
+                        .map(|(a, b)| (a + b) / 2.0)
+                        .collect(),
+                )
+                .to_expression()
+            }
+            (ObjectType::Rectangle, "normal") => {
+                let corners = rectangle_corners(
+                    &self.vector_member(name, "v0"),
+                    &self.vector_member(name, "v1"),
+                );
+                let edge = |i: usize| -> Value {
+                    Value::Vector(
+                        corners[i]
+                            .iter()
+                            .zip(corners[0].iter())
+                            .map(|(a, b)| a - b)
+                            .collect(),
+                    )
+                };
+                let normal = self.call("cross", vec![edge(1), edge(3)]);
+                if magnitude(&self.vector("normal", &normal)) == 0.0 {
+                    self.error(format!(
+                        "Rectangle `{}` is degenerate and has no normal",
+                        name
+                    ));
+                }
+                let normal = self.call("normalize", vec![normal]);
+
+                let inverted = object
+                    .properties
+                    .iter()
+                    .rev()
+                    .find(|p| p.name == "inverted")
+                    .is_some_and(|p| matches!(&p.value, Expression::Identifier(b) if b == "true"));
+                if inverted {
+                    self.binary(normal, Operator::Multiply, Value::Number(-1.0))
+                        .to_expression()
+                } else {
+                    normal.to_expression()
+                }
+            }
+            _ => self.error(format!("`{}` has no property `{}`", name, member)),
+        }
+    }
+
+    /// Replaces `.look_at` with the normalized `.direction` from the object's
+    /// position towards the target, and rejects zero-length directions.
+    /// IF blocks add their properties after the object's own, so the last of
+    /// `.direction` and `.look_at` wins and the others are dropped.
+    pub fn resolve_direction(&mut self, object: &mut constructor::Object) {
+        let aim = |p: &constructor::Property| p.name == "direction" || p.name == "look_at";
+        if let Some(last) = object.properties.iter().rposition(aim) {
+            let mut index = 0;
+            object.properties.retain(|p| {
+                index += 1;
+                index - 1 == last || !aim(p)
+            });
+        }
+
+        if let Some(index) = object.properties.iter().position(|p| p.name == "look_at") {
+            self.location = Some((object.file.clone(), object.properties[index].position));
+            let target = match &object.properties[index].value {
+                Expression::Identifier(target) if *target == object.name => {
+                    self.error(format!("`{}` cannot look at itself", object.name))
+                }
+                Expression::Identifier(target) if self.names.contains(target) => {
+                    let center = match self.objects.get(target).map(|o| o.obj_type) {
+                        Some(ObjectType::Sphere | ObjectType::Rectangle) => "center",
+                        _ => "position",
+                    };
+                    self.vector_member(target, center)
+                }
+                target => self.vector3("look_at", &self.evaluate(target)),
+            };
+            let position = match object
+                .properties
+                .iter()
+                .rev()
+                .find(|p| p.name == "position")
+            {
+                Some(prop) => self.vector3("position", &self.evaluate(&prop.value)),
+                None => self.error(format!(
+                    "`{}` needs a .position to use .look_at",
+                    object.name
+                )),
+            };
+
+            let direction = Value::Vector(
+                target
+                    .iter()
+                    .zip(position.iter())
+                    .map(|(t, p)| t - p)
+                    .collect(),
+            );
+            if magnitude(&self.vector("look_at", &direction)) == 0.0 {
+                self.error(format!("`{}` cannot look at its own position", object.name));
+            }
+
+            object.properties[index] = constructor::Property {
+                name: "direction".to_string(),
+                value: self.call("normalize", vec![direction]).to_expression(),
+                position: object.properties[index].position,
+            };
+        }
+
+        if let Some(prop) = object.properties.iter().find(|p| p.name == "direction") {
+            self.location = Some((object.file.clone(), prop.position));
+            let direction = self.vector3("direction", &self.evaluate(&prop.value));
+            if magnitude(&direction) == 0.0 {
+                self.error(format!("`{}` has a zero-length direction", object.name));
+            }
+        }
+    }
+
+    pub fn vector_member(&self, name: &str, member: &str) -> Vec<f64> {
+        match self.evaluate(&self.object_member(name, member)) {
+            Value::Vector(values) if values.len() == 3 => values,
+            other => self.error(format!(
+                "Expected `{}.{}` to be a 3-component vector, got {}",
+                name,
+                member,
+                other.type_name()
+            )),
+        }
+    }
+
+    pub fn binary(&self, lhs: Value, op: Operator, rhs: Value) -> Value {
+        match (lhs, op, rhs) {
+            (lhs, Operator::Equal, rhs) => Value::Bool(lhs == rhs),
+            (lhs, Operator::NotEqual, rhs) => Value::Bool(lhs != rhs),
+            (Value::Number(a), Operator::Less, Value::Number(b)) => Value::Bool(a < b),
+            (Value::Number(a), Operator::LessEqual, Value::Number(b)) => Value::Bool(a <= b),
+            (Value::Number(a), Operator::Greater, Value::Number(b)) => Value::Bool(a > b),
+            (Value::Number(a), Operator::GreaterEqual, Value::Number(b)) => Value::Bool(a >= b),
+            (Value::Symbol(idtfr), _, _) | (_, _, Value::Symbol(idtfr)) => self.error(format!(
+                "Cannot use `{}` in an arithmetic expression",
+                idtfr
+            )),
+            (_, Operator::Divide, Value::Number(0.0)) => self.error("Division by zero".to_string()),
+            (Value::Number(a), op, Value::Number(b)) => Value::Number(apply(a, op, b)),
+            (Value::Vector(a), Operator::Add | Operator::Subtract, Value::Vector(b)) => {
+                if a.len() != b.len() {
+                    self.error(format!(
+                        "Cannot {} vectors of length {} and {}",
+                        if op == Operator::Add {
+                            "add"
+                        } else {
+                            "subtract"
+                        },
+                        a.len(),
+                        b.len()
+                    ));
+                }
+                Value::Vector(
+                    a.iter()
+                        .zip(b.iter())
+                        .map(|(x, y)| apply(*x, op, *y))
+                        .collect(),
+                )
+            }
+            (Value::Vector(a), Operator::Multiply | Operator::Divide, Value::Number(b)) => {
+                Value::Vector(a.iter().map(|x| apply(*x, op, b)).collect())
+            }
+            (Value::Number(a), Operator::Multiply, Value::Vector(b)) => {
+                Value::Vector(b.iter().map(|y| a * y).collect())
+            }
+            (lhs, op, rhs) => self.error(format!(
+                "Unsupported operation: {} {} {}",
+                lhs.type_name(),
+                op,
+                rhs.type_name()
+            )),
+        }
+    }
+
+    pub fn call(&self, name: &str, args: Vec<Value>) -> Value {
+        let arity = match BUILTINS.iter().find(|(builtin, _)| *builtin == name) {
+            Some((_, arity)) => *arity,
+            None => self.error(format!("Unknown function: {}", name)),
+        };
+        if args.len() != arity {
+            self.error(format!(
+                "{} expects {} argument{}, got {}",
+                name,
+                arity,
+                if arity == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+
+        match name {
+            "sin" => Value::Number(self.number(name, &args[0]).sin()),
+            "cos" => Value::Number(self.number(name, &args[0]).cos()),
+            "sqrt" => {
+                let x = self.number(name, &args[0]);
+                if x < 0.0 {
+                    self.error(format!("sqrt of negative number: {}", x));
+                }
+                Value::Number(x.sqrt())
+            }
+            "abs" => Value::Number(self.number(name, &args[0]).abs()),
+            "min" => Value::Number(self.number(name, &args[0]).min(self.number(name, &args[1]))),
+            "max" => Value::Number(self.number(name, &args[0]).max(self.number(name, &args[1]))),
+            "clamp" => {
+                let (x, lo, hi) = (
+                    self.number(name, &args[0]),
+                    self.number(name, &args[1]),
+                    self.number(name, &args[2]),
+                );
+                if lo > hi {
+                    self.error(format!(
+                        "clamp lower bound {} is greater than upper bound {}",
+                        lo, hi
+                    ));
+                }
+                Value::Number(x.clamp(lo, hi))
+            }
+            "lerp" => {
+                let t = self.number(name, &args[2]);
+                match (&args[0], &args[1]) {
+                    (Value::Number(a), Value::Number(b)) => Value::Number(a + (b - a) * t),
+                    (Value::Vector(a), Value::Vector(b)) if a.len() == b.len() => Value::Vector(
+                        a.iter()
+                            .zip(b.iter())
+                            .map(|(a, b)| a + (b - a) * t)
+                            .collect(),
+                    ),
+                    (a, b) => self.error(format!(
+                        "lerp expects two numbers or two vectors of equal length, got {} and {}",
+                        a.type_name(),
+                        b.type_name()
+                    )),
+                }
+            }
+            "normalize" => {
+                let v = self.vector(name, &args[0]);
+                let len = magnitude(&v);
+                if len == 0.0 {
+                    self.error("Cannot normalize a zero-length vector".to_string());
+                }
+                Value::Vector(v.iter().map(|x| x / len).collect())
+            }
+            "cross" => {
+                let (a, b) = (self.vector3(name, &args[0]), self.vector3(name, &args[1]));
+                Value::Vector(vec![
+                    a[1] * b[2] - a[2] * b[1],
+                    a[2] * b[0] - a[0] * b[2],
+                    a[0] * b[1] - a[1] * b[0],
+                ])
+            }
+            "dot" => {
+                let (a, b) = (self.vector(name, &args[0]), self.vector(name, &args[1]));
+                if a.len() != b.len() {
+                    self.error(format!(
+                        "dot expects vectors of equal length, got {} and {}",
+                        a.len(),
+                        b.len()
+                    ));
+                }
+                Value::Number(a.iter().zip(b.iter()).map(|(a, b)| a * b).sum())
+            }
+            "length" => Value::Number(magnitude(&self.vector(name, &args[0]))),
+            "deg" => Value::Number(self.number(name, &args[0]).to_degrees()),
+            "rad" => Value::Number(self.number(name, &args[0]).to_radians()),
+            _ => unreachable!(),
+        }
+    }
+
+    fn number(&self, function: &str, value: &Value) -> f64 {
+        match value {
+            Value::Number(num) => *num,
+            other => self.error(format!(
+                "{} expects a number, got {}",
+                function,
+                other.type_name()
+            )),
+        }
+    }
+
+    fn vector(&self, function: &str, value: &Value) -> Vec<f64> {
+        match value {
+            Value::Vector(values) => values.clone(),
+            other => self.error(format!(
+                "{} expects a vector, got {}",
+                function,
+                other.type_name()
+            )),
+        }
+    }
+
+    fn vector3(&self, function: &str, value: &Value) -> Vec<f64> {
+        let values = self.vector(function, value);
+        if values.len() != 3 {
+            self.error(format!(
+                "{} expects a 3-component vector, got {} components",
+                function,
+                values.len()
+            ));
+        }
+        values
+    }
+}
+
+/// The four corners of the rectangle spanned by `v0` and `v1`, in the order
+/// the transpiler passes them to `Rectangle.init`.
+pub fn rectangle_corners(v0: &[f64], v1: &[f64]) -> [Vec<f64>; 4] {
+    [
+        vec![v0[0], v0[1], v0[2]],
+        vec![v0[0], v1[1], v1[2]],
+        vec![v1[0], v1[1], v1[2]],
+        vec![v1[0], v1[1], v0[2]],
+    ]
+}
+
+fn apply(a: f64, op: Operator, b: f64) -> f64 {
+    match op {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide => a / b,
+        _ => unreachable!(),
+    }
+}
+
+pub fn magnitude(v: &[f64]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructor::Constructor;
+
+    /// The engine of `text`, with every scene folded.
+    fn folded(text: &str) -> constructor::Engine {
+        let mut constructor = Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        Evaluator::new().fold_engine(&mut constructor.engine);
+        constructor.engine
+    }
+
+    /// The properties of `name` in the first scene.
+    fn properties(engine: &constructor::Engine, name: &str) -> Vec<(String, Expression)> {
+        let object = engine.scenes[0]
+            .objects
+            .iter()
+            .find(|o| o.name == name)
+            .unwrap();
+        object
+            .properties
+            .iter()
+            .map(|p| (p.name.clone(), p.value.clone()))
+            .collect()
+    }
+
+    /// The values of the `.direction` properties left on `name`, which
+    /// must no longer have a `.look_at`.
+    fn directions(engine: &constructor::Engine, name: &str) -> Vec<Value> {
+        let properties = properties(engine, name);
+        assert!(properties.iter().all(|(name, _)| name != "look_at"));
+        properties
+            .iter()
+            .filter(|(name, _)| name == "direction")
+            .map(|(_, value)| Evaluator::new().evaluate(value))
+            .collect()
+    }
+
+    #[test]
+    fn derived_look_at_overrides_direction() {
+        let engine = folded(
+            "SCENE s {
+    LIGHT base {
+        .position  = (0, 0, 0),
+        .direction = (0, 0, 1),
+    }
+    LIGHT child EXTENDS base {
+        .look_at = (2, 0, 0),
+    }
+    LIGHT grandchild EXTENDS child {
+        .direction = (0, 1, 0),
+    }
+}
+",
+        );
+        assert_eq!(
+            directions(&engine, "base"),
+            [Value::Vector(vec![0.0, 0.0, 1.0])]
+        );
+        assert_eq!(
+            directions(&engine, "child"),
+            [Value::Vector(vec![1.0, 0.0, 0.0])]
+        );
+        assert_eq!(
+            directions(&engine, "grandchild"),
+            [Value::Vector(vec![0.0, 1.0, 0.0])]
+        );
+    }
+
+    #[test]
+    fn last_look_at_wins() {
+        let engine = folded(
+            "SCENE s {
+    SPHERE ball {
+        .position = (0, 3, 0),
+    }
+    CAMERA cam {
+        .position = (0, 0, 0),
+        .look_at  = (0, 0, 5),
+        IF true {
+            .look_at = ball,
+        }
+    }
+}
+",
+        );
+        assert_eq!(
+            directions(&engine, "cam"),
+            [Value::Vector(vec![0.0, 1.0, 0.0])]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:4:9: `cam` cannot look at its own position")]
+    fn zero_length_look_at() {
+        folded(
+            "SCENE s {
+    CAMERA cam {
+        .position = (1, 2, 3),
+        .look_at  = (1, 2, 3),
+    }
+}
+",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:4:9: `lamp` cannot look at itself")]
+    fn self_targeted_look_at() {
+        folded(
+            "SCENE s {
+    LIGHT lamp {
+        .position = (1, 2, 3),
+        .look_at  = lamp,
+    }
+}
+",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:9: `lamp` has a zero-length direction")]
+    fn zero_length_direction() {
+        folded("SCENE s {\n    LIGHT lamp {\n        .direction = (0, 0, 0),\n    }\n}\n");
+    }
+
+    /// The value of the expression `text`.
+    fn value(text: &str) -> Value {
+        let expression = Constructor::new("test.zest".to_string(), text.to_string()).expression();
+        Evaluator::new().evaluate(&expression)
+    }
+
+    #[test]
+    fn builtins_evaluate() {
+        let number = |text: &str| match value(text) {
+            Value::Number(number) => number,
+            other => panic!("Expected a number from {}, got {:?}", text, other),
+        };
+        assert_eq!(number("sin(0)"), 0.0);
+        assert_eq!(number("cos(0)"), 1.0);
+        assert_eq!(number("sqrt(16)"), 4.0);
+        assert_eq!(number("abs(-3)"), 3.0);
+        assert_eq!(number("min(2, 5)"), 2.0);
+        assert_eq!(number("max(2, 5)"), 5.0);
+        assert_eq!(number("clamp(7, 0, 5)"), 5.0);
+        assert_eq!(number("lerp(0, 10, 0.25)"), 2.5);
+        assert_eq!(number("dot((1, 2, 3), (4, 5, 6))"), 32.0);
+        assert_eq!(number("length((3, 4, 0))"), 5.0);
+        assert_eq!(number("deg(PI)"), 180.0);
+        assert_eq!(number("rad(180)"), std::f64::consts::PI);
+
+        assert_eq!(
+            value("lerp((0, 0, 0), (2, 4, 6), 0.5)"),
+            Value::Vector(vec![1.0, 2.0, 3.0])
+        );
+        assert_eq!(
+            value("normalize((3, 0, 4))"),
+            Value::Vector(vec![0.6, 0.0, 0.8])
+        );
+        assert_eq!(
+            value("cross((1, 0, 0), (0, 1, 0))"),
+            Value::Vector(vec![0.0, 0.0, 1.0])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "min expects 2 arguments, got 1")]
+    fn builtins_check_their_arity() {
+        value("min(1)");
+    }
+
+    #[test]
+    #[should_panic(expected = "sqrt expects 1 argument, got 2")]
+    fn builtins_check_a_single_argument() {
+        value("sqrt(4, 9)");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown function: tan")]
+    fn unknown_functions() {
+        value("tan(1)");
+    }
+
+    #[test]
+    #[should_panic(expected = "sin expects a number, got vector")]
+    fn builtins_expect_numbers() {
+        value("sin((1, 2, 3))");
+    }
+
+    #[test]
+    #[should_panic(expected = "cross expects a 3-component vector, got 2 components")]
+    fn cross_expects_3_components() {
+        value("cross((1, 0), (0, 1, 0))");
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "lerp expects two numbers or two vectors of equal length, got number and vector"
+    )]
+    fn lerp_expects_matching_ends() {
+        value("lerp(0, (1, 1, 1), 0.5)");
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot normalize a zero-length vector")]
+    fn zero_length_vectors_cannot_be_normalized() {
+        value("normalize((0, 0, 0))");
+    }
+
+    #[test]
+    fn shapes_have_centers_and_normals() {
+        let engine = folded(
+            "SCENE s {
+    SPHERE ball {
+        .position = (1, 2, 3),
+    }
+    RECTANGLE floor {
+        .v0 = (-5, 0, -5),
+        .v1 = (5, 0, 5),
+    }
+    RECTANGLE ceiling {
+        .v0       = (-5, 4, -5),
+        .v1       = (5, 4, 5),
+        .inverted = true,
+    }
+    CAMERA ball_cam {
+        .position  = ball.center,
+        .direction = floor.normal,
+    }
+    CAMERA floor_cam {
+        .position  = floor.center,
+        .direction = ceiling.normal,
+    }
+}
+",
+        );
+        let vector = |name: &str, property: &str| {
+            let (_, value) = properties(&engine, name)
+                .into_iter()
+                .find(|(name, _)| name == property)
+                .unwrap();
+            Evaluator::new().evaluate(&value)
+        };
+        assert_eq!(
+            vector("ball_cam", "position"),
+            Value::Vector(vec![1.0, 2.0, 3.0])
+        );
+        assert_eq!(
+            vector("ball_cam", "direction"),
+            Value::Vector(vec![0.0, 1.0, 0.0])
+        );
+        assert_eq!(
+            vector("floor_cam", "position"),
+            Value::Vector(vec![0.0, 0.0, 0.0])
+        );
+        assert_eq!(
+            vector("floor_cam", "direction"),
+            Value::Vector(vec![0.0, -1.0, 0.0])
+        );
+    }
+}
@@ -0,0 +1,708 @@
+use crate::constructor::{
+    Conditional, Constructor, Expression, Object, ObjectType, Operator, Property, Statement,
+};
+use crate::cst::{Comment, Cst};
+use crate::tokeniser::{Position, Token};
+use std::collections::VecDeque;
+
+const INDENT: &str = "    ";
+
+// Stands for the end of the file where a position is expected
+const END: Position = (usize::MAX, 0);
+
+/// Formats Zest `text` in the canonical layout: four space indents, aligned
+/// `=` signs, trailing commas and a blank line between objects. Comments are
+/// kept on the line they were written on, or above the code that follows them.
+pub fn format(file: &str, text: &str) -> String {
+    let cst = Cst::parse(file, text);
+    // The rest of the file would be lost, so refuse to format it
+    if let Some((line, column)) = cst.unknown() {
+        panic!("{}:{}:{}: Unexpected character", file, line, column);
+    }
+
+    let mut constructor = Constructor::new(file.to_string(), text.to_string());
+    constructor.parse();
+    let engine = &constructor.engine;
+
+    let mut items: Vec<(Position, Item)> = engine
+        .body
+        .iter()
+        .map(|statement| (position(statement), Item::Statement(statement)))
+        .collect();
+    items.extend(
+        engine
+            .scenes
+            .iter()
+            .map(|scene| (scene.position, Item::Scene(&scene.name, &scene.body))),
+    );
+    if let Some(start) = constructor.start {
+        items.push((start, Item::Start(&engine.start)));
+    }
+    items.sort_by_key(|(position, _)| *position);
+
+    let mut formatter = Formatter {
+        comments: cst.comments().into(),
+        cst,
+        lines: Vec::new(),
+        last_line: 0,
+    };
+    for (i, (position, item)) in items.iter().enumerate() {
+        let next = items.get(i + 1).map_or(END, |(next, _)| *next);
+        if i > 0 {
+            formatter.separate(compact(&items[i - 1].1) && compact(item), *position);
+        }
+        formatter.leading(*position, 0, true);
+        match item {
+            Item::Statement(statement) => formatter.statement(statement, 0, next),
+            Item::Scene(name, body) => {
+                let (open, close) = formatter.block(*position);
+                formatter.line(0, format!("SCENE {} {{", name));
+                formatter.trailing(position.0, open);
+                formatter.statements(body, 1, close);
+                formatter.close(0, close);
+            }
+            Item::Start(name) => {
+                formatter.line(0, format!("START {}", name));
+                let end = formatter.end_line(next);
+                formatter.trailing(position.0, end);
+            }
+        }
+    }
+    formatter.leading(END, 0, true);
+
+    if formatter.lines.is_empty() {
+        return String::new();
+    }
+    formatter.lines.join("\n") + "\n"
+}
+
+/// A top level declaration, in the order they are written.
+enum Item<'a> {
+    Statement(&'a Statement),
+    Scene(&'a String, &'a Vec<Statement>),
+    Start(&'a String),
+}
+
+/// Whether a run of items like this needs no blank lines between them.
+fn compact(item: &Item) -> bool {
+    matches!(
+        item,
+        Item::Statement(Statement::Const(_) | Statement::Import(_))
+    )
+}
+
+enum Member<'a> {
+    Property(&'a Property),
+    Conditional(&'a Conditional<Property>),
+}
+
+fn position(statement: &Statement) -> Position {
+    match statement {
+        Statement::Object(object) => object.position,
+        Statement::For(for_loop) => for_loop.position,
+        Statement::If(conditional) => conditional.position,
+        Statement::Const(constant) => constant.position,
+        Statement::Prefab(prefab) => prefab.position,
+        Statement::Instance(instance) => instance.position,
+        Statement::Import(import) => import.position,
+    }
+}
+
+struct Formatter {
+    cst: Cst,
+    comments: VecDeque<Comment>,
+    lines: Vec<String>,
+    // The source line of the last code formatted
+    last_line: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, indent: usize, text: String) {
+        self.lines
+            .push(format!("{}{}", INDENT.repeat(indent), text));
+    }
+
+    /// A blank line, unless one would open a block or follow another.
+    fn blank(&mut self) {
+        match self.lines.last() {
+            Some(line) if !line.is_empty() && !line.ends_with('{') => {
+                self.lines.push(String::new())
+            }
+            _ => {}
+        }
+    }
+
+    /// Separates statements by a blank line, or compact ones only if they
+    /// were separated by one.
+    fn separate(&mut self, compact: bool, next: Position) {
+        if !compact || next.0 > self.last_line + 1 {
+            self.blank();
+        }
+    }
+
+    /// Writes the comments before `before` on lines of their own. Where `gap`
+    /// is set, blank lines around them are kept.
+    fn leading(&mut self, before: Position, indent: usize, gap: bool) {
+        let mut written = false;
+        while self
+            .comments
+            .front()
+            .is_some_and(|comment| comment.position < before)
+        {
+            let comment = self.comments.pop_front().unwrap();
+            if gap && comment.position.0 > self.last_line + 1 {
+                self.blank();
+            }
+            self.line(indent, comment.text);
+            self.last_line = comment.position.0;
+            written = true;
+        }
+        if written && gap && before != END && before.0 > self.last_line + 1 {
+            self.blank();
+        }
+    }
+
+    /// Appends the comments trailing code on lines `first` to `last` to the
+    /// last line written.
+    fn trailing(&mut self, first: usize, last: usize) {
+        while let Some(comment) = self.comments.front() {
+            let line = comment.position.0;
+            if !comment.trailing || line < first || line > last {
+                break;
+            }
+            let comment = self.comments.pop_front().unwrap();
+            let text = self.lines.pop().unwrap_or_default();
+            self.lines.push(format!("{} {}", text, comment.text));
+        }
+        self.last_line = last;
+    }
+
+    /// The index of the token at `position`, or the number of tokens for `END`.
+    fn index(&self, position: Position) -> usize {
+        if position == END {
+            return self.cst.elements.len();
+        }
+        match self.cst.index(position) {
+            Some(index) => index,
+            None => panic!("No token at {}:{}", position.0, position.1),
+        }
+    }
+
+    /// The last line of the token at `index`, which strings and ZIG blocks
+    /// can run past.
+    fn token_end_line(&self, index: usize) -> usize {
+        let element = &self.cst.elements[index];
+        element.position.0 + element.text.matches('\n').count()
+    }
+
+    /// The last line of the code before `next`.
+    fn end_line(&self, next: Position) -> usize {
+        match self.index(next) {
+            0 => 1,
+            index => self.token_end_line(index - 1),
+        }
+    }
+
+    /// The lines of the braces of the block whose header starts at `start`.
+    fn block(&self, start: Position) -> (usize, Position) {
+        let (open, close) = self.braces(self.index(start));
+        (self.position(open).0, self.position(close))
+    }
+
+    /// The indices of the first brace after `start` outside of brackets and
+    /// its closing brace.
+    fn braces(&self, start: usize) -> (usize, usize) {
+        let mut i = start;
+        while let Some(element) = self.cst.elements.get(i) {
+            match element.token {
+                Token::LBrace => match self.cst.matching(i) {
+                    Some(close) => return (i, close),
+                    None => break,
+                },
+                Token::LParen | Token::LBracket => match self.cst.matching(i) {
+                    Some(close) => i = close + 1,
+                    None => break,
+                },
+                _ => i += 1,
+            }
+        }
+        panic!("Unterminated block")
+    }
+
+    fn position(&self, index: usize) -> Position {
+        self.cst.elements[index].position
+    }
+
+    /// Writes the `}` at `close`, after the comments before it.
+    fn close(&mut self, indent: usize, close: Position) {
+        self.leading(close, indent + 1, false);
+        self.line(indent, "}".to_string());
+        self.trailing(close.0, close.0);
+    }
+
+    fn statements(&mut self, statements: &[Statement], indent: usize, close: Position) {
+        for (i, statement) in statements.iter().enumerate() {
+            let position = position(statement);
+            if i > 0 {
+                let compact =
+                    |s: &Statement| matches!(s, Statement::Const(_) | Statement::Import(_));
+                self.separate(compact(&statements[i - 1]) && compact(statement), position);
+            }
+            self.leading(position, indent, true);
+            let next = statements.get(i + 1).map_or(close, self::position);
+            self.statement(statement, indent, next);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, indent: usize, next: Position) {
+        match statement {
+            Statement::Object(object) if object.obj_type == ObjectType::Zig => {
+                let code = object.properties.iter().find(|p| p.name == "code");
+                if let Some(Property {
+                    value: Expression::Zig(code),
+                    ..
+                }) = code
+                {
+                    self.line(indent, zig(&code.code, indent));
+                }
+                let end = self.end_line(next);
+                self.trailing(object.position.0, end);
+            }
+            Statement::Object(object) => self.object(object, indent),
+            Statement::For(for_loop) => {
+                let (open, close) = self.block(for_loop.position);
+                self.line(
+                    indent,
+                    format!(
+                        "FOR {} IN {} {{",
+                        for_loop.variable,
+                        expression(&for_loop.iterable, indent)
+                    ),
+                );
+                self.trailing(for_loop.position.0, open);
+                self.statements(&for_loop.body, indent + 1, close);
+                self.close(indent, close);
+            }
+            Statement::If(conditional) => {
+                self.conditional(conditional, indent, Self::statements);
+            }
+            Statement::Const(constant) => {
+                self.line(
+                    indent,
+                    format!(
+                        "CONST {} = {}",
+                        constant.name,
+                        expression(&constant.value, indent)
+                    ),
+                );
+                let end = self.end_line(next);
+                self.trailing(constant.position.0, end);
+            }
+            Statement::Prefab(prefab) => {
+                let parameters: Vec<String> = prefab
+                    .parameters
+                    .iter()
+                    .map(|(name, default)| match default {
+                        Some(default) => format!("{} = {}", name, expression(default, indent)),
+                        None => name.clone(),
+                    })
+                    .collect();
+                let (open, close) = self.block(prefab.position);
+                self.line(
+                    indent,
+                    format!("PREFAB {}({}) {{", prefab.name, parameters.join(", ")),
+                );
+                self.trailing(prefab.position.0, open);
+                self.statements(&prefab.body, indent + 1, close);
+                self.close(indent, close);
+            }
+            Statement::Instance(instance) => {
+                let (open, close) = self.block(instance.position);
+                self.line(indent, format!("{} {} {{", instance.prefab, instance.name));
+                self.trailing(instance.position.0, open);
+                self.members(
+                    &instance.arguments,
+                    &instance.conditionals,
+                    indent + 1,
+                    close,
+                );
+                self.close(indent, close);
+            }
+            Statement::Import(import) => {
+                let line = match import.names.is_empty() {
+                    true => format!("IMPORT \"{}\"", import.path),
+                    false => format!(
+                        "IMPORT {} FROM \"{}\"",
+                        import.names.join(", "),
+                        import.path
+                    ),
+                };
+                self.line(indent, line);
+                let end = self.end_line(next);
+                self.trailing(import.position.0, end);
+            }
+        }
+    }
+
+    fn object(&mut self, object: &Object, indent: usize) {
+        let keyword = object.obj_type.keyword();
+        let mut header = keyword.to_string();
+        // WINDOW and RENDER are named after themselves unless named otherwise
+        let settings = matches!(object.obj_type, ObjectType::Window | ObjectType::Render);
+        if !settings || object.name != keyword.to_lowercase() {
+            header = format!("{} {}", header, object.name);
+        }
+        if let Some(base) = &object.base {
+            header = format!("{} EXTENDS {}", header, base);
+        }
+
+        let (open, close) = self.block(object.position);
+        let empty = object.properties.is_empty()
+            && object.conditionals.is_empty()
+            && !self.comments.iter().any(|c| c.position < close);
+        if empty {
+            self.line(indent, format!("{} {{}}", header));
+            self.trailing(object.position.0, close.0);
+            return;
+        }
+
+        self.line(indent, format!("{} {{", header));
+        self.trailing(object.position.0, open);
+        self.members(&object.properties, &object.conditionals, indent + 1, close);
+        self.close(indent, close);
+    }
+
+    /// The properties and IF blocks of an object, in the order they were
+    /// written, with the `=` of each run of properties aligned.
+    fn members(
+        &mut self,
+        properties: &[Property],
+        conditionals: &[Conditional<Property>],
+        indent: usize,
+        close: Position,
+    ) {
+        let mut members: Vec<(Position, Member)> = properties
+            .iter()
+            .map(|p| (p.position, Member::Property(p)))
+            .chain(
+                conditionals
+                    .iter()
+                    .map(|c| (c.position, Member::Conditional(c))),
+            )
+            .collect();
+        members.sort_by_key(|(position, _)| *position);
+        let name = |j: usize| match members[j].1 {
+            Member::Property(property) => Some(property.name.len()),
+            Member::Conditional(_) => None,
+        };
+
+        for (i, (position, member)) in members.iter().enumerate() {
+            let next = members.get(i + 1).map_or(close, |(next, _)| *next);
+            self.leading(*position, indent, false);
+            match member {
+                Member::Property(property) => {
+                    // The run of properties this one is in, between IF blocks
+                    let before = (0..i).rev().map_while(name).max().unwrap_or(0);
+                    let width = (i..members.len()).map_while(name).max().unwrap_or(0);
+                    self.line(
+                        indent,
+                        format!(
+                            ".{:width$} = {},",
+                            property.name,
+                            expression(&property.value, indent),
+                            width = width.max(before)
+                        ),
+                    );
+                    let end = self.end_line(next);
+                    self.trailing(position.0, end);
+                }
+                Member::Conditional(conditional) => self.conditional(
+                    conditional,
+                    indent,
+                    |formatter, properties, indent, close| {
+                        formatter.members(properties, &[], indent, close)
+                    },
+                ),
+            }
+        }
+    }
+
+    /// `IF a { ... } ELSE IF b { ... } ELSE { ... }`, with each branch written
+    /// by `body`.
+    fn conditional<T>(
+        &mut self,
+        conditional: &Conditional<T>,
+        indent: usize,
+        body: fn(&mut Self, &[T], usize, Position),
+    ) {
+        let mut start = self.index(conditional.position);
+        for (i, (condition, branch)) in conditional.branches.iter().enumerate() {
+            let (open, close) = self.braces(start);
+            let header = format!("IF {} {{", expression(condition, indent));
+            if i == 0 {
+                self.line(indent, header);
+            } else {
+                self.else_line(indent, format!("}} ELSE {}", header), start);
+            }
+            self.trailing(self.position(start).0, self.position(open).0);
+            body(self, branch, indent + 1, self.position(close));
+            start = close;
+        }
+
+        // An empty ELSE is kept as written
+        if self.cst.elements.get(start + 1).map(|e| &e.token)
+            == Some(&Token::Identifier("ELSE".to_string()))
+        {
+            let (open, close) = self.braces(start + 1);
+            self.else_line(indent, "} ELSE {".to_string(), start);
+            self.trailing(self.position(start).0, self.position(open).0);
+            body(
+                self,
+                &conditional.otherwise,
+                indent + 1,
+                self.position(close),
+            );
+            start = close;
+        }
+        self.close(indent, self.position(start));
+    }
+
+    /// Closes a branch at the `}` at `close` and opens the next one.
+    fn else_line(&mut self, indent: usize, line: String, close: usize) {
+        self.leading(self.position(close), indent + 1, false);
+        self.line(indent, line);
+    }
+}
+
+/// The binding strength of the operator at the top of `expression`.
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Binary(_, op, _) => match op {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Equal
+            | Operator::NotEqual
+            | Operator::Less
+            | Operator::LessEqual
+            | Operator::Greater
+            | Operator::GreaterEqual => 3,
+            Operator::Add | Operator::Subtract => 4,
+            Operator::Multiply | Operator::Divide => 5,
+        },
+        Expression::Range(_, _) => 0,
+        Expression::Negate(_) | Expression::Not(_) => 6,
+        Expression::Number(num) if num.starts_with('-') => 6,
+        _ => 7,
+    }
+}
+
+/// `expression` as Zest, with only the parentheses it needs. Maps and ZIG
+/// blocks span several lines, indented from `indent`.
+fn expression(expression: &Expression, indent: usize) -> String {
+    let wrap = |e: &Expression, parenthesise: bool| {
+        let text = self::expression(e, indent);
+        match parenthesise {
+            true => format!("({})", text),
+            false => text,
+        }
+    };
+    let list = |values: &[Expression]| {
+        values
+            .iter()
+            .map(|value| self::expression(value, indent))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match expression {
+        Expression::Number(num) => num.clone(),
+        Expression::Identifier(name) => name.clone(),
+        Expression::String(string) => format!("\"{}\"", string),
+        Expression::Color(hex) => format!("#{}", hex),
+        Expression::Group(values) if values.len() == 1 => format!("({},)", list(values)),
+        Expression::Group(values) => format!("({})", list(values)),
+        Expression::List(values) => format!("[{}]", list(values)),
+        Expression::Call(name, args) => format!("{}({})", name, list(args)),
+        Expression::Negate(e) => format!("-{}", wrap(e, precedence(e) < 6)),
+        Expression::Not(e) => format!("!{}", wrap(e, precedence(e) < 6)),
+        Expression::Member(e, member) => format!("{}.{}", wrap(e, precedence(e) < 7), member),
+        Expression::Binary(lhs, op, rhs) => {
+            let strength = precedence(expression);
+            // Comparisons don't chain, so neither side can be another one
+            let left = precedence(lhs) < strength || (strength == 3 && precedence(lhs) == 3);
+            format!(
+                "{} {} {}",
+                wrap(lhs, left),
+                op,
+                wrap(rhs, precedence(rhs) <= strength)
+            )
+        }
+        Expression::Range(start, end) => format!(
+            "{}..{}",
+            self::expression(start, indent),
+            self::expression(end, indent)
+        ),
+        Expression::Map(entries) if entries.is_empty() => "{}".to_string(),
+        Expression::Map(entries) => {
+            let mut map = "{\n".to_string();
+            for (key, value) in entries {
+                let plain = key.starts_with(|c: char| c.is_ascii_alphanumeric())
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                let key = match plain {
+                    true => key.clone(),
+                    false => format!("\"{}\"", key),
+                };
+                map.push_str(&format!(
+                    "{}{}: {},\n",
+                    INDENT.repeat(indent + 1),
+                    key,
+                    self::expression(value, indent + 1)
+                ));
+            }
+            map + &INDENT.repeat(indent) + "}"
+        }
+        Expression::Zig(code) => zig(&code.code, indent),
+        Expression::Empty => String::new(),
+    }
+}
+
+/// A ZIG block, with its lines indented one level past `indent` and the
+/// relative indentation of the Zig kept.
+fn zig(code: &str, indent: usize) -> String {
+    if !code.contains('\n') {
+        return match code.trim() {
+            "" => "ZIG {}".to_string(),
+            code => format!("ZIG {{ {} }}", code),
+        };
+    }
+
+    let lines: Vec<&str> = code.lines().collect();
+    let first = lines.iter().position(|l| !l.trim().is_empty());
+    let last = lines.iter().rposition(|l| !l.trim().is_empty());
+    let (Some(first), Some(last)) = (first, last) else {
+        return "ZIG {}".to_string();
+    };
+    let lines = &lines[first..=last];
+
+    let margin = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+    let mut block = "ZIG {\n".to_string();
+    for line in lines {
+        if !line.trim().is_empty() {
+            block.push_str(&INDENT.repeat(indent + 1));
+            block.extend(line.chars().skip(margin));
+        }
+        block.push('\n');
+    }
+    block + &INDENT.repeat(indent) + "}"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_are_formatted() {
+        for directory in ["examples", "examples/lib"] {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_none_or(|extension| extension != "zest") {
+                    continue;
+                }
+                let file = path.to_string_lossy().to_string();
+                let text = std::fs::read_to_string(&path).unwrap();
+                assert_eq!(format(&file, &text), text, "{}", file);
+            }
+        }
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let text = r#"// Lamps for the demo
+CONST n = 3 // how many
+IMPORT "lib/lamp.zest"
+
+PREFAB post(height) {
+  RECTANGLE post {
+  .v0 = (0, 0, 0), .v1=(1, height, 1)
+  }
+}
+
+SCENE main {
+  // The camera
+  CAMERA cam { .position = (0,1,-5),
+    .direction=(0,0,1) }   // looking ahead
+  FOR i IN 0..n {
+    SPHERE ball_{i} { .position = (i * 2, 1, 0), .radius = 0.5 }
+  }
+  IF n > 2 {
+     LIGHT extra { .position = (0, 5, 0) }
+  } ELSE {
+     LIGHT dim { .intensity = 0.2 }
+  }
+  // trailing comment
+}
+START main
+"#;
+        let formatted = r#"// Lamps for the demo
+CONST n = 3 // how many
+IMPORT "lib/lamp.zest"
+
+PREFAB post(height) {
+    RECTANGLE post {
+        .v0 = (0, 0, 0),
+        .v1 = (1, height, 1),
+    }
+}
+
+SCENE main {
+    // The camera
+    CAMERA cam {
+        .position  = (0, 1, -5),
+        .direction = (0, 0, 1), // looking ahead
+    }
+
+    FOR i IN 0..n {
+        SPHERE ball_{i} {
+            .position = (i * 2, 1, 0),
+            .radius   = 0.5,
+        }
+    }
+
+    IF n > 2 {
+        LIGHT extra {
+            .position = (0, 5, 0),
+        }
+    } ELSE {
+        LIGHT dim {
+            .intensity = 0.2,
+        }
+    }
+    // trailing comment
+}
+
+START main
+"#;
+        assert_eq!(format("test.zest", text), formatted);
+        assert_eq!(format("test.zest", formatted), formatted);
+    }
+
+    #[test]
+    fn zig_blocks_are_reindented() {
+        let text = "SCENE main {\nZIG {\n  const x = 1;\n    _ = x;\n}\n}\nSTART main\n";
+        let formatted =
+            "SCENE main {\n    ZIG {\n        const x = 1;\n          _ = x;\n    }\n}\n\nSTART main\n";
+        assert_eq!(format("test.zest", text), formatted);
+        assert_eq!(format("test.zest", formatted), formatted);
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:5: Unexpected character")]
+    fn unknown_characters_are_not_formatted() {
+        format("test.zest", "SCENE main {\n    $\n}\n");
+    }
+}
@@ -0,0 +1,297 @@
+use crate::constructor::{self, Expression, ObjectType};
+use crate::evaluator;
+
+pub type Vector = [f64; 3];
+
+// Used for objects without a solid colour material, such as image textures
+const DEFAULT_COLOR: Vector = [0.8, 0.8, 0.8];
+
+/// The number of segments around a tessellated sphere
+pub const SEGMENTS: usize = 32;
+
+pub struct Sphere {
+    pub name: String,
+    pub center: Vector,
+    pub radius: f64,
+    pub material: Option<String>,
+    pub color: Vector,
+}
+
+pub struct Rectangle {
+    pub name: String,
+    pub corners: [Vector; 4],
+    pub material: Option<String>,
+    pub color: Vector,
+}
+
+pub struct Light {
+    pub name: String,
+    pub position: Vector,
+    pub intensity: Vector,
+}
+
+pub struct Camera {
+    pub name: String,
+    pub position: Vector,
+    pub direction: Vector,
+}
+
+/// A triangle mesh. Each triangle's corners run counter-clockwise around
+/// its normal, so `cross(b - a, c - a)` points out of the surface.
+pub struct Mesh {
+    pub positions: Vec<Vector>,
+    pub normals: Vec<Vector>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// The mesh mirrored along z, from Z3D's left-handed coordinates into the
+    /// right-handed ones of most modelling tools.
+    pub fn right_handed(mut self) -> Self {
+        for v in self.positions.iter_mut().chain(self.normals.iter_mut()) {
+            *v = right_handed(*v);
+        }
+        // Mirroring flips the winding, so flip it back
+        for triangle in self.indices.chunks_mut(3) {
+            triangle.swap(1, 2);
+        }
+        self
+    }
+}
+
+impl Sphere {
+    /// A UV sphere with `segments` slices around and half as many stacks.
+    pub fn mesh(&self, segments: usize) -> Mesh {
+        let stacks = (segments / 2).max(2);
+        let segments = segments.max(3);
+
+        let mut mesh = Mesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        };
+        for i in 0..=stacks {
+            let theta = std::f64::consts::PI * i as f64 / stacks as f64;
+            for j in 0..=segments {
+                let phi = std::f64::consts::TAU * j as f64 / segments as f64;
+                let normal = [
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ];
+                mesh.positions
+                    .push(add(self.center, scale(normal, self.radius)));
+                mesh.normals.push(normal);
+            }
+        }
+
+        let row = segments as u32 + 1;
+        for i in 0..stacks as u32 {
+            for j in 0..segments as u32 {
+                let (top, bottom) = (i * row + j, (i + 1) * row + j);
+                // The poles would otherwise get triangles with no area
+                if i != 0 {
+                    mesh.indices.extend([top, top + 1, bottom]);
+                }
+                if i != stacks as u32 - 1 {
+                    mesh.indices.extend([top + 1, bottom + 1, bottom]);
+                }
+            }
+        }
+
+        mesh
+    }
+}
+
+impl Rectangle {
+    pub fn normal(&self) -> Vector {
+        let [a, b, c, _] = self.corners;
+        normalize(cross(sub(b, a), sub(c, a)))
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        Mesh {
+            positions: self.corners.to_vec(),
+            normals: vec![self.normal(); 4],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+}
+
+/// The geometry of a folded scene, for the backends that draw it in Rust
+/// rather than through Z3D.
+pub struct World {
+    pub spheres: Vec<Sphere>,
+    pub rectangles: Vec<Rectangle>,
+    pub lights: Vec<Light>,
+    pub cameras: Vec<Camera>,
+    /// The camera made active by ACTIVE, if any
+    pub active: Option<String>,
+}
+
+impl World {
+    pub fn new(scene: &constructor::Scene) -> Self {
+        let mut world = World {
+            spheres: Vec::new(),
+            rectangles: Vec::new(),
+            lights: Vec::new(),
+            cameras: Vec::new(),
+            active: None,
+        };
+
+        for object in &scene.objects {
+            match object.obj_type {
+                ObjectType::Sphere => world.spheres.push(Sphere {
+                    name: object.name.clone(),
+                    center: vector(object, "position"),
+                    radius: number(object, "radius"),
+                    material: material(object),
+                    color: color(scene, object),
+                }),
+                ObjectType::Rectangle => {
+                    let corners =
+                        evaluator::rectangle_corners(&vector(object, "v0"), &vector(object, "v1"));
+                    world.rectangles.push(Rectangle {
+                        name: object.name.clone(),
+                        corners: corners.map(|c| [c[0], c[1], c[2]]),
+                        material: material(object),
+                        color: color(scene, object),
+                    });
+                }
+                ObjectType::Light => world.lights.push(Light {
+                    name: object.name.clone(),
+                    position: vector(object, "position"),
+                    intensity: vector(object, "intensity"),
+                }),
+                ObjectType::Camera => world.cameras.push(Camera {
+                    name: object.name.clone(),
+                    position: vector(object, "position"),
+                    direction: normalize(vector(object, "direction")),
+                }),
+                ObjectType::Active => {
+                    world.active = property(object, "camera").map(|camera| camera.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        world
+    }
+
+    /// The camera made active by ACTIVE, or else the first camera.
+    pub fn camera(&self) -> Option<&Camera> {
+        self.cameras.iter().find(|camera| {
+            self.active
+                .as_ref()
+                .is_none_or(|active| camera.name == *active)
+        })
+    }
+}
+
+fn property<'a>(object: &'a constructor::Object, name: &str) -> Option<&'a Expression> {
+    object
+        .properties
+        .iter()
+        .rev()
+        .find(|p| p.name == name)
+        .map(|p| &p.value)
+}
+
+pub fn vector(object: &constructor::Object, name: &str) -> Vector {
+    let value = match property(object, name) {
+        Some(value) => evaluator::Evaluator::new().evaluate(value),
+        None => panic!("`{}` has no .{}", object.name, name),
+    };
+    match value {
+        evaluator::Value::Vector(v) if v.len() == 3 => [v[0], v[1], v[2]],
+        other => panic!(
+            "Expected vector for {}.{}, got {}",
+            object.name,
+            name,
+            other.type_name()
+        ),
+    }
+}
+
+pub fn number(object: &constructor::Object, name: &str) -> f64 {
+    let value = match property(object, name) {
+        Some(value) => evaluator::Evaluator::new().evaluate(value),
+        None => panic!("`{}` has no .{}", object.name, name),
+    };
+    match value {
+        evaluator::Value::Number(num) => num,
+        other => panic!(
+            "Expected number for {}.{}, got {}",
+            object.name,
+            name,
+            other.type_name()
+        ),
+    }
+}
+
+fn material(object: &constructor::Object) -> Option<String> {
+    match property(object, "material") {
+        Some(Expression::Identifier(material)) => Some(material.clone()),
+        _ => None,
+    }
+}
+
+/// The colour of an object's material, with channels between 0 and 1.
+fn color(scene: &constructor::Scene, object: &constructor::Object) -> Vector {
+    let material = match property(object, "material") {
+        Some(Expression::Identifier(material)) => material,
+        _ => return DEFAULT_COLOR,
+    };
+    let material = scene
+        .objects
+        .iter()
+        .find(|o| o.obj_type == ObjectType::Material && o.name == *material);
+
+    match material {
+        Some(material) if property(material, "color").is_some() => {
+            scale(vector(material, "color"), 1.0 / 255.0)
+        }
+        _ => DEFAULT_COLOR,
+    }
+}
+
+/// `v` in right-handed coordinates, the inverse of itself.
+pub fn right_handed(v: Vector) -> Vector {
+    [v[0], v[1], -v[2]]
+}
+
+pub fn add(a: Vector, b: Vector) -> Vector {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: Vector, b: Vector) -> Vector {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: Vector, s: f64) -> Vector {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn multiply(a: Vector, b: Vector) -> Vector {
+    [a[0] * b[0], a[1] * b[1], a[2] * b[2]]
+}
+
+pub fn dot(a: Vector, b: Vector) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: Vector, b: Vector) -> Vector {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn normalize(a: Vector) -> Vector {
+    let length = evaluator::magnitude(&a);
+    if length == 0.0 {
+        return a;
+    }
+    scale(a, 1.0 / length)
+}
@@ -0,0 +1,463 @@
+use crate::evaluator::format_number;
+use crate::geometry::{self, cross, normalize, right_handed, scale, Mesh, Vector, World};
+use crate::json::{object, Json};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+const LIGHTS: &str = "KHR_lights_punctual";
+
+/// The world as a glTF 2.0 document, and the buffer it points to at `bin`.
+/// Spheres are tessellated with `segments` slices.
+pub fn gltf(world: &World, fov: f64, segments: usize, bin: &str) -> (Json, Vec<u8>) {
+    let mut gltf = Gltf {
+        buffer: Vec::new(),
+        buffer_views: Vec::new(),
+        accessors: Vec::new(),
+        materials: Vec::new(),
+        meshes: Vec::new(),
+        nodes: Vec::new(),
+    };
+
+    for sphere in &world.spheres {
+        let material = gltf.material(&sphere.material, sphere.color);
+        gltf.mesh(&sphere.name, sphere.mesh(segments), material);
+    }
+    for rectangle in &world.rectangles {
+        let material = gltf.material(&rectangle.material, rectangle.color);
+        gltf.mesh(&rectangle.name, rectangle.mesh(), material);
+    }
+
+    let mut lights = Vec::new();
+    for light in &world.lights {
+        // Z3D intensities are per channel, so the brightest one sets the intensity
+        let intensity = light.intensity.iter().cloned().fold(0.0, f64::max);
+        let color = if intensity > 0.0 {
+            scale(light.intensity, 1.0 / intensity)
+        } else {
+            [1.0; 3]
+        };
+        gltf.nodes.push(object(vec![
+            ("name", string(&light.name)),
+            ("translation", vector(&right_handed(light.position))),
+            (
+                "extensions",
+                object(vec![(
+                    LIGHTS,
+                    object(vec![("light", number(lights.len()))]),
+                )]),
+            ),
+        ]));
+        lights.push(object(vec![
+            ("name", string(&light.name)),
+            ("type", string("point")),
+            ("color", vector(&color)),
+            ("intensity", Json::Number(format_number(intensity))),
+        ]));
+    }
+
+    let mut cameras = Vec::new();
+    if let Some(camera) = world.camera() {
+        gltf.nodes.push(object(vec![
+            ("name", string(&camera.name)),
+            ("camera", number(0)),
+            ("translation", vector(&right_handed(camera.position))),
+            ("rotation", rotation(right_handed(camera.direction))),
+        ]));
+        cameras.push(object(vec![
+            ("name", string(&camera.name)),
+            ("type", string("perspective")),
+            (
+                "perspective",
+                object(vec![
+                    ("aspectRatio", number(1)),
+                    ("yfov", Json::Number(format_number(fov.to_radians()))),
+                    ("znear", Json::Number("0.01".to_string())),
+                ]),
+            ),
+        ]));
+    }
+
+    let mut document = vec![
+        (
+            "asset",
+            object(vec![
+                ("version", string("2.0")),
+                ("generator", string("zest")),
+            ]),
+        ),
+        ("scene", number(0)),
+        (
+            "scenes",
+            Json::Array(vec![object(vec![(
+                "nodes",
+                Json::Array((0..gltf.nodes.len()).map(number).collect()),
+            )])]),
+        ),
+        ("nodes", Json::Array(gltf.nodes)),
+    ];
+    if !gltf.meshes.is_empty() {
+        document.extend([
+            ("meshes", Json::Array(gltf.meshes)),
+            (
+                "materials",
+                Json::Array(gltf.materials.into_iter().map(|m| m.2).collect()),
+            ),
+            ("accessors", Json::Array(gltf.accessors)),
+            ("bufferViews", Json::Array(gltf.buffer_views)),
+            (
+                "buffers",
+                Json::Array(vec![object(vec![
+                    ("uri", string(bin)),
+                    ("byteLength", number(gltf.buffer.len())),
+                ])]),
+            ),
+        ]);
+    }
+    if !cameras.is_empty() {
+        document.push(("cameras", Json::Array(cameras)));
+    }
+    if !lights.is_empty() {
+        document.push(("extensionsUsed", Json::Array(vec![string(LIGHTS)])));
+        document.push((
+            "extensions",
+            object(vec![(
+                LIGHTS,
+                object(vec![("lights", Json::Array(lights))]),
+            )]),
+        ));
+    }
+
+    (object(document), gltf.buffer)
+}
+
+struct Gltf {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Json>,
+    accessors: Vec<Json>,
+    // The Zest material and colour of each glTF material, to share them
+    materials: Vec<(Option<String>, Vector, Json)>,
+    meshes: Vec<Json>,
+    nodes: Vec<Json>,
+}
+
+impl Gltf {
+    /// The index of the glTF material for a Zest material of `color`.
+    fn material(&mut self, name: &Option<String>, color: Vector) -> usize {
+        if let Some(index) = self
+            .materials
+            .iter()
+            .position(|(n, c, _)| n == name && *c == color)
+        {
+            return index;
+        }
+
+        let [r, g, b] = color;
+        let material = object(vec![
+            ("name", string(name.as_deref().unwrap_or("default"))),
+            (
+                "pbrMetallicRoughness",
+                object(vec![
+                    ("baseColorFactor", vector(&[r, g, b, 1.0])),
+                    ("metallicFactor", number(0)),
+                    ("roughnessFactor", number(1)),
+                ]),
+            ),
+            // Rectangles are visible from both sides in Z3D
+            ("doubleSided", Json::Bool(true)),
+        ]);
+        self.materials.push((name.clone(), color, material));
+        self.materials.len() - 1
+    }
+
+    /// Adds `mesh` under a node named `name`.
+    fn mesh(&mut self, name: &str, mesh: Mesh, material: usize) {
+        let mesh = mesh.right_handed();
+
+        let positions: Vec<[f32; 3]> = mesh.positions.iter().map(|p| p.map(|c| c as f32)).collect();
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in &positions {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+
+        let position = self.accessor(
+            positions
+                .iter()
+                .flatten()
+                .flat_map(|c| c.to_le_bytes())
+                .collect(),
+            ARRAY_BUFFER,
+            FLOAT,
+            positions.len(),
+            "VEC3",
+            Some((min, max)),
+        );
+        let normal = self.accessor(
+            mesh.normals
+                .iter()
+                .flatten()
+                .flat_map(|c| (*c as f32).to_le_bytes())
+                .collect(),
+            ARRAY_BUFFER,
+            FLOAT,
+            mesh.normals.len(),
+            "VEC3",
+            None,
+        );
+        let indices = self.accessor(
+            mesh.indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+            ELEMENT_ARRAY_BUFFER,
+            UNSIGNED_INT,
+            mesh.indices.len(),
+            "SCALAR",
+            None,
+        );
+
+        self.nodes.push(object(vec![
+            ("name", string(name)),
+            ("mesh", number(self.meshes.len())),
+        ]));
+        self.meshes.push(object(vec![
+            ("name", string(name)),
+            (
+                "primitives",
+                Json::Array(vec![object(vec![
+                    (
+                        "attributes",
+                        object(vec![
+                            ("POSITION", number(position)),
+                            ("NORMAL", number(normal)),
+                        ]),
+                    ),
+                    ("indices", number(indices)),
+                    ("material", number(material)),
+                ])]),
+            ),
+        ]));
+    }
+
+    /// Appends `bytes` to the buffer in a view of its own, returning the index
+    /// of an accessor over them.
+    fn accessor(
+        &mut self,
+        bytes: Vec<u8>,
+        target: u32,
+        component_type: u32,
+        count: usize,
+        kind: &str,
+        bounds: Option<([f32; 3], [f32; 3])>,
+    ) -> usize {
+        self.buffer_views.push(object(vec![
+            ("buffer", number(0)),
+            ("byteOffset", number(self.buffer.len())),
+            ("byteLength", number(bytes.len())),
+            ("target", number(target as usize)),
+        ]));
+        self.buffer.extend(bytes);
+
+        let mut accessor = vec![
+            ("bufferView", number(self.buffer_views.len() - 1)),
+            ("componentType", number(component_type as usize)),
+            ("count", number(count)),
+            ("type", string(kind)),
+        ];
+        if let Some((min, max)) = bounds {
+            accessor.push(("min", vector(&min.map(|c| c as f64))));
+            accessor.push(("max", vector(&max.map(|c| c as f64))));
+        }
+        self.accessors.push(object(accessor));
+        self.accessors.len() - 1
+    }
+}
+
+/// The rotation turning a glTF camera, which looks down -z with y up, to look
+/// along `direction`.
+fn rotation(direction: Vector) -> Json {
+    let forward = normalize(direction);
+    let up = if cross(forward, [0.0, 1.0, 0.0]) == [0.0, 0.0, 0.0] {
+        [0.0, 0.0, -1.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    };
+    let right = normalize(cross(forward, up));
+    let up = cross(right, forward);
+    let back = geometry::scale(forward, -1.0);
+
+    // The quaternion of the matrix whose columns are right, up and back
+    let m = [
+        [right[0], up[0], back[0]],
+        [right[1], up[1], back[1]],
+        [right[2], up[2], back[2]],
+    ];
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    let (x, y, z, w) = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        (
+            (m[2][1] - m[1][2]) / s,
+            (m[0][2] - m[2][0]) / s,
+            (m[1][0] - m[0][1]) / s,
+            s / 4.0,
+        )
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+        (
+            s / 4.0,
+            (m[0][1] + m[1][0]) / s,
+            (m[0][2] + m[2][0]) / s,
+            (m[2][1] - m[1][2]) / s,
+        )
+    } else if m[1][1] > m[2][2] {
+        let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+        (
+            (m[0][1] + m[1][0]) / s,
+            s / 4.0,
+            (m[1][2] + m[2][1]) / s,
+            (m[0][2] - m[2][0]) / s,
+        )
+    } else {
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        (
+            (m[0][2] + m[2][0]) / s,
+            (m[1][2] + m[2][1]) / s,
+            s / 4.0,
+            (m[1][0] - m[0][1]) / s,
+        )
+    };
+
+    vector(&[x, y, z, w])
+}
+
+fn string(text: &str) -> Json {
+    Json::String(text.to_string())
+}
+
+fn number(num: usize) -> Json {
+    Json::Number(num.to_string())
+}
+
+fn vector(v: &[f64]) -> Json {
+    Json::Array(v.iter().map(|c| Json::Number(format_number(*c))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructor::Constructor;
+    use crate::evaluator::Evaluator;
+
+    const SCENE: &str = "SCENE s {
+    MATERIAL red {
+        .color = (255, 0, 0),
+    }
+    SPHERE ball {
+        .position = (0, 1, 0),
+        .radius   = 1,
+        .material = red,
+    }
+    RECTANGLE floor {
+        .v0       = (-5, 0, -5),
+        .v1       = (5, 0, 5),
+        .material = red,
+    }
+    LIGHT lamp {
+        .position  = (0, 4, 0),
+        .intensity = (0.5, 1, 0.5),
+    }
+    CAMERA cam {
+        .position  = (0, 1, -5),
+        .direction = (0, 0, 1),
+    }
+}
+";
+
+    /// The glTF document and buffer of the only scene of `text`.
+    fn export(text: &str, segments: usize) -> (Json, Vec<u8>) {
+        let mut constructor = Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        Evaluator::new().fold_engine(&mut constructor.engine);
+        gltf(
+            &World::new(&constructor.engine.scenes[0]),
+            60.0,
+            segments,
+            "scene.bin",
+        )
+    }
+
+    fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+        match json.get(key) {
+            Some(Json::Array(values)) => values,
+            other => panic!("Expected an array for {}, got {:?}", key, other),
+        }
+    }
+
+    fn whole(json: &Json, key: &str) -> usize {
+        match json.get(key) {
+            Some(Json::Number(number)) => number.parse().unwrap(),
+            other => panic!("Expected a number for {}, got {:?}", key, other),
+        }
+    }
+
+    #[test]
+    fn accessors_count_the_tessellated_meshes() {
+        let (gltf, _) = export(SCENE, 8);
+        let counts: Vec<usize> = array(&gltf, "accessors")
+            .iter()
+            .map(|accessor| whole(accessor, "count"))
+            .collect();
+        // 5 rings of 9 vertices and 48 triangles for the sphere, then the rectangle
+        assert_eq!(counts, [45, 45, 144, 4, 4, 6]);
+        assert_eq!(array(&gltf, "meshes").len(), 2);
+        assert_eq!(array(&gltf, "materials").len(), 1);
+    }
+
+    #[test]
+    fn buffer_views_cover_the_bin() {
+        let (gltf, bin) = export(SCENE, 8);
+        assert_eq!(bin.len(), (45 * 12 * 2 + 144 * 4) + (4 * 12 * 2 + 6 * 4));
+
+        let buffer = &array(&gltf, "buffers")[0];
+        assert_eq!(buffer.get("uri"), Some(&string("scene.bin")));
+        assert_eq!(whole(buffer, "byteLength"), bin.len());
+
+        let mut offset = 0;
+        for view in array(&gltf, "bufferViews") {
+            assert_eq!(whole(view, "byteOffset"), offset);
+            offset += whole(view, "byteLength");
+        }
+        assert_eq!(offset, bin.len());
+    }
+
+    #[test]
+    fn lights_and_cameras_are_nodes() {
+        let (gltf, _) = export(SCENE, 8);
+        let names: Vec<&Json> = array(&gltf, "nodes")
+            .iter()
+            .map(|node| node.get("name").unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                &string("ball"),
+                &string("floor"),
+                &string("lamp"),
+                &string("cam")
+            ]
+        );
+
+        let lights = array(
+            gltf.get("extensions").unwrap().get(LIGHTS).unwrap(),
+            "lights",
+        );
+        assert_eq!(lights[0].get("color"), Some(&vector(&[0.5, 1.0, 0.5])));
+        // Looking along +z in Z3D is looking down -z in glTF, the default
+        let camera = &array(&gltf, "nodes")[3];
+        assert_eq!(camera.get("rotation"), Some(&vector(&[0.0, 0.0, 0.0, 1.0])));
+        assert_eq!(camera.get("translation"), Some(&vector(&[0.0, 1.0, 5.0])));
+    }
+}
@@ -0,0 +1,214 @@
+use std::io::Write;
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Rows of RGB pixels, top to bottom
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0, 0, 0]; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: [u8; 3]) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    /// Writes the image as PNG if `file` ends in `.png`, and as PPM otherwise.
+    pub fn save(&self, file: &str) {
+        let bytes = if file.to_lowercase().ends_with(".png") {
+            self.png()
+        } else {
+            self.ppm()
+        };
+
+        let mut f = std::fs::File::create(file).expect("Could not create file");
+        f.write_all(&bytes).expect("Could not write to file");
+    }
+
+    /// The image as lines of ANSI truecolour half blocks, each character
+    /// showing one pixel in the foreground and the one below it in the
+    /// background.
+    pub fn ansi(&self) -> String {
+        let mut output = String::new();
+        for y in (0..self.height).step_by(2) {
+            for x in 0..self.width {
+                let [r, g, b] = self.get(x, y);
+                output.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+                if y + 1 < self.height {
+                    let [r, g, b] = self.get(x, y + 1);
+                    output.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b));
+                }
+                output.push('\u{2580}');
+            }
+            output.push_str("\x1b[0m\n");
+        }
+
+        output
+    }
+
+    pub fn ppm(&self) -> Vec<u8> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        bytes.extend(self.pixels.iter().flatten());
+        bytes
+    }
+
+    /// A PNG with the pixel data stored uncompressed, which needs no encoder
+    /// beyond the checksums.
+    pub fn png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            // Filter type 0: the row is stored as is
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+
+        // A zlib stream of stored deflate blocks, at most 65535 bytes each
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = raw.chunks(65535).peekable();
+        if blocks.peek().is_none() {
+            zlib.extend([1, 0, 0, 0xff, 0xff]);
+        }
+        while let Some(block) = blocks.next() {
+            let last = blocks.peek().is_none();
+            let length = block.len() as u16;
+            zlib.push(last as u8);
+            zlib.extend(length.to_le_bytes());
+            zlib.extend((!length).to_le_bytes());
+            zlib.extend(block);
+        }
+        zlib.extend(adler32(&raw).to_be_bytes());
+
+        let mut header = Vec::new();
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((self.height as u32).to_be_bytes());
+        // 8 bit RGB, default compression and filtering, no interlacing
+        header.extend([8, 2, 0, 0, 0]);
+
+        let mut bytes = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        chunk(&mut bytes, b"IHDR", &header);
+        chunk(&mut bytes, b"IDAT", &zlib);
+        chunk(&mut bytes, b"IEND", &[]);
+        bytes
+    }
+}
+
+fn chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend((data.len() as u32).to_be_bytes());
+    bytes.extend(kind);
+    bytes.extend(data);
+    bytes.extend(crc32(&[kind.as_slice(), data].concat()).to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The type and data of each chunk of `png`, checking the signature and
+    /// every chunk's CRC along the way.
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(
+            png[..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']
+        );
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + length]);
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+            assert_eq!(crc, crc32(&rest[4..8 + length]));
+            chunks.push((String::from_utf8(kind.to_vec()).unwrap(), data.to_vec()));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn ansi_pairs_rows_into_half_blocks() {
+        let mut image = Image::new(1, 3);
+        image.set(0, 0, [1, 2, 3]);
+        image.set(0, 1, [4, 5, 6]);
+        image.set(0, 2, [7, 8, 9]);
+        assert_eq!(
+            image.ansi(),
+            "\x1b[38;2;1;2;3m\x1b[48;2;4;5;6m\u{2580}\x1b[0m\n\
+             \x1b[38;2;7;8;9m\u{2580}\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn png_stores_rows_uncompressed() {
+        let mut image = Image::new(2, 1);
+        image.set(0, 0, [255, 0, 0]);
+        image.set(1, 0, [0, 0, 255]);
+        let chunks = chunks(&image.png());
+
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+
+        let raw = [0, 255, 0, 0, 0, 0, 255];
+        let mut zlib = vec![0x78, 0x01, 1, 7, 0, !7, !0];
+        zlib.extend(raw);
+        zlib.extend(adler32(&raw).to_be_bytes());
+        assert_eq!(chunks[1].1, zlib);
+        assert!(chunks[2].1.is_empty());
+    }
+
+    #[test]
+    fn png_splits_large_images_into_blocks() {
+        // 110 rows of 601 bytes, 575 more than fit in one block
+        let image = Image::new(200, 110);
+        let chunks = chunks(&image.png());
+        let zlib = &chunks[1].1;
+
+        assert_eq!(zlib[2..7], [0, 0xff, 0xff, 0, 0]);
+        let second = &zlib[7 + 65535..];
+        assert_eq!(second[0], 1);
+        let length = u16::from_le_bytes([second[1], second[2]]);
+        assert_eq!(length, 575);
+        assert_eq!(u16::from_le_bytes([second[3], second[4]]), !length);
+        assert_eq!(second.len(), 5 + length as usize + 4);
+    }
+}
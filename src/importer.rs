@@ -0,0 +1,1297 @@
+use crate::constructor::ObjectType;
+use crate::transpiler::{self, DEFAULT_GRAVITY, KEYS};
+
+// The comments the transpiler puts around the code of ZIG blocks
+const ZIG_START: &str = "// zest: ";
+const ZIG_END: &str = "// zest: end";
+
+// The defaults of the WINDOW and RENDER settings, which need not be written
+const DEFAULTS: [(&str, &str); 5] = [
+    ("title", "\"Z3D\""),
+    ("width", "400"),
+    ("height", "400"),
+    ("x", "0"),
+    ("y", "0"),
+];
+
+// Statements the transpiler emits around the objects of every scene
+const BOILERPLATE: [&str; 18] = [
+    "var arena ",
+    "defer arena.",
+    "const allocator ",
+    "var scene_objects ",
+    "defer scene_objects.",
+    "var lights ",
+    "defer lights.",
+    "var gui_layer ",
+    "defer gui_layer.",
+    "var engine ",
+    "defer engine.",
+    "try engine.mainloop()",
+    "try scene_objects.append(",
+    "try lights.append(",
+    "try gui_layer.append(",
+    "z3d.graphics.objects.assigned(",
+    "var clock ",
+    "_ = ",
+];
+
+/// A statement, or a block with the text before its brace, and the line it
+/// starts on.
+enum Item {
+    Statement(String, usize),
+    Block(String, usize, Vec<Item>),
+}
+
+impl Item {
+    fn line(&self) -> usize {
+        match self {
+            Item::Statement(_, line) | Item::Block(_, line, _) => *line,
+        }
+    }
+}
+
+struct Object {
+    keyword: &'static str,
+    name: String,
+    properties: Vec<(String, String)>,
+}
+
+struct Scene {
+    name: String,
+    objects: Vec<Object>,
+}
+
+/// A body of a PHYSICS block, from `var engine = PhysicsEngine.init(&obj_body, ...)`.
+struct Body {
+    engine: String,
+    physics: String,
+    gravity: Option<String>,
+    is_static: bool,
+}
+
+/// Converts Zig written against Z3D, by hand or by the transpiler, back into
+/// Zest. Returns the Zest and the line and text of every statement it could
+/// not translate.
+pub fn import(text: &str) -> (String, Vec<(usize, String)>) {
+    let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+    let mut importer = Importer {
+        window: Vec::new(),
+        render: Vec::new(),
+        start: None,
+        zig: Vec::new(),
+        scenes: Vec::new(),
+        vectors: Vec::new(),
+        bodies: Vec::new(),
+        updates: Vec::new(),
+        blocks: blocks(&lines),
+        lines,
+        skipped: Vec::new(),
+    };
+
+    let chars = strip_comments(text);
+    let items = block(&chars, &mut 0);
+    let scenes = items
+        .iter()
+        .any(|item| matches!(item, Item::Block(header, _, _) if scene_name(header).is_some()));
+    for item in &items {
+        if let Some(code) = importer.spliced(item.line()) {
+            importer
+                .zig
+                .extend((!code.is_empty()).then(|| zig(&code, 0)));
+            continue;
+        }
+        match item {
+            Item::Statement(statement, line) => importer.setting(statement, *line),
+            Item::Block(header, _, body) if header.starts_with("const WINDOW_FLAGS") => {
+                for item in body {
+                    importer.flag(item);
+                }
+            }
+            Item::Block(header, line, body) => match scene_name(header) {
+                Some(name) => importer.scene(name, body),
+                // Hand-written levels may set up everything in main
+                None if header.starts_with("pub fn main(") && !scenes => {
+                    importer.scene("scene".to_string(), body)
+                }
+                None if header.starts_with("pub fn main(") => importer.start(body),
+                // The helpers the transpiler emits for animations and actions
+                None if ["fn ease(", "fn animate(", "fn dispatch_action("]
+                    .iter()
+                    .any(|helper| header.starts_with(helper)) => {}
+                None => importer.skip(header, *line),
+            },
+        }
+    }
+
+    (importer.zest(), importer.skipped)
+}
+
+struct Importer {
+    window: Vec<(String, String)>,
+    render: Vec<(String, String)>,
+    start: Option<String>,
+    // Top level ZIG blocks
+    zig: Vec<String>,
+    scenes: Vec<Scene>,
+    // `var name = Vec3.init(...)` in the scene being read, for animated vectors
+    vectors: Vec<(String, String)>,
+    // The physics engines of the scene being read
+    bodies: Vec<Body>,
+    // The ZIG blocks of the scene's frame loop, from `.on_update`
+    updates: Vec<String>,
+    // The lines of the Zig, to read ZIG blocks back as they are
+    lines: Vec<String>,
+    // The lines of the comments around each ZIG block, and whether it was read
+    blocks: Vec<(usize, usize, bool)>,
+    skipped: Vec<(usize, String)>,
+}
+
+impl Importer {
+    fn skip(&mut self, text: &str, line: usize) {
+        self.skipped.push((line, text.to_string()));
+    }
+
+    /// A top level `const NAME = value;`.
+    fn setting(&mut self, statement: &str, line: usize) {
+        let (name, value) = match declaration(statement) {
+            Some(declaration) => declaration,
+            None => return self.skip(statement, line),
+        };
+
+        let known = match name {
+            "TITLE" if value.starts_with('"') => {
+                self.window.push(("title".to_string(), value.to_string()));
+                true
+            }
+            "WIDTH" | "HEIGHT" | "X" | "Y" => match number(value) {
+                Some(value) => {
+                    self.window.push((name.to_lowercase(), value));
+                    true
+                }
+                None => false,
+            },
+            "FOV" => match number(value) {
+                Some(value) => {
+                    self.render.push(("fov".to_string(), value));
+                    true
+                }
+                None => false,
+            },
+            "RENDER_WIDTH" => match number(value) {
+                Some(value) => {
+                    self.render.push(("resolution".to_string(), value));
+                    true
+                }
+                None => false,
+            },
+            "WINDOW_FLAGS" => value.ends_with("WindowFlags.default()"),
+            "z3d" | "std" | "Vec3" | "RENDER_HEIGHT" => true,
+            "SceneId" | "next_scene" | "Keyframe" | "Easing" | "LoopMode" => true,
+            _ => false,
+        };
+        if !known {
+            self.skip(statement, line);
+        }
+    }
+
+    /// A statement of the `WINDOW_FLAGS` block.
+    fn flag(&mut self, item: &Item) {
+        let (statement, line) = match item {
+            Item::Statement(statement, line) => (statement, *line),
+            Item::Block(header, line, _) => return self.skip(header, *line),
+        };
+        if statement.starts_with("var flags ") || statement.starts_with("break :blk") {
+            return;
+        }
+
+        let flag = statement
+            .strip_prefix("flags.")
+            .and_then(|s| s.split_once('='))
+            .map(|(flag, value)| (flag.trim(), value.trim()));
+        match flag {
+            Some((flag, value @ ("true" | "false"))) => {
+                self.window.push((flag.to_string(), value.to_string()))
+            }
+            _ => self.skip(statement, line),
+        }
+    }
+
+    /// The start scene, from `var current: ?SceneId = .name;` in main.
+    fn start(&mut self, body: &[Item]) {
+        for item in body {
+            match item {
+                Item::Statement(statement, _) => {
+                    if let Some(start) = statement.strip_prefix("var current: ?SceneId = .") {
+                        self.start = Some(start.trim().to_string());
+                    }
+                }
+                Item::Block(_, _, body) => self.start(body),
+            }
+        }
+    }
+
+    /// The code of the ZIG block `line` is in, or None if it is not in one.
+    /// Only the first line read of a block gives its code, later ones give
+    /// none, as the whole block has been read.
+    fn spliced(&mut self, line: usize) -> Option<Vec<String>> {
+        let (start, end, read) = self
+            .blocks
+            .iter_mut()
+            .find(|(start, end, _)| *start < line && line < *end)?;
+        if std::mem::replace(read, true) {
+            return Some(Vec::new());
+        }
+
+        let lines = &self.lines[*start..*end - 1];
+        let common = lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        let code = lines
+            .iter()
+            .map(|line| line.get(common..).unwrap_or("").trim_end().to_string())
+            .collect();
+        Some(code)
+    }
+
+    fn scene(&mut self, name: String, body: &[Item]) {
+        self.vectors.clear();
+        self.bodies.clear();
+        self.updates.clear();
+        self.scenes.push(Scene {
+            name,
+            objects: Vec::new(),
+        });
+        self.statements(body, false);
+        self.finish();
+    }
+
+    /// What is only known once the whole scene has been read: the gravity of
+    /// each PHYSICS block, and the code run every frame.
+    fn finish(&mut self) {
+        let default = format!(
+            "({}, {}, {})",
+            DEFAULT_GRAVITY.0, DEFAULT_GRAVITY.1, DEFAULT_GRAVITY.2
+        );
+        let mut physics: Vec<String> = Vec::new();
+        for body in &self.bodies {
+            if !physics.contains(&body.physics) {
+                physics.push(body.physics.clone());
+            }
+        }
+        for name in physics {
+            // Gravity is only applied to the bodies that move
+            let moving: Vec<&Body> = self
+                .bodies
+                .iter()
+                .filter(|body| body.physics == name && !body.is_static)
+                .collect();
+            let gravity = match moving.iter().find_map(|body| body.gravity.clone()) {
+                None if !moving.is_empty() => Some("false".to_string()),
+                Some(gravity) if gravity != default => Some(gravity),
+                _ => None,
+            };
+            if let Some(gravity) = gravity {
+                self.property(&name, "gravity", gravity);
+            }
+        }
+
+        // `.on_update` may be on any object, so it goes on ACTIVE
+        let updates = std::mem::take(&mut self.updates);
+        let scene = self.scenes.last_mut().unwrap();
+        let active = scene.objects.iter_mut().rfind(|o| o.keyword == "ACTIVE");
+        match active {
+            Some(active) => {
+                for update in updates {
+                    active.properties.push(("on_update".to_string(), update));
+                }
+            }
+            None => self
+                .skipped
+                .extend(updates.into_iter().map(|update| (0, update))),
+        }
+    }
+
+    fn statements(&mut self, body: &[Item], frame: bool) {
+        for item in body {
+            match self.spliced(item.line()) {
+                Some(code) if code.is_empty() => continue,
+                Some(code) if frame => {
+                    self.updates.push(zig(&code, 8));
+                    continue;
+                }
+                Some(code) => {
+                    self.object("ZIG", "", vec![("code", zig(&code, 4))]);
+                    continue;
+                }
+                None => {}
+            }
+            match item {
+                Item::Statement(statement, line) => {
+                    if !self.statement(statement) {
+                        self.skip(statement, *line);
+                    }
+                }
+                Item::Block(header, line, body) => {
+                    // The frame loop of animated scenes
+                    if header.starts_with("while (engine.running())") {
+                        self.statements(body, true);
+                    } else {
+                        self.skip(header, *line);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Translates one statement of a scene, returning false if it can't.
+    fn statement(&mut self, statement: &str) -> bool {
+        if BOILERPLATE.iter().any(|b| statement.starts_with(b))
+            || statement.starts_with("const time ")
+            || statement == "try engine.frame()"
+        {
+            return true;
+        }
+        if let Some(object) = statement.strip_prefix("var obj_") {
+            return object.contains("Object{");
+        }
+        if let Some(image) = statement.strip_prefix("defer ") {
+            return image.ends_with(".deinit()");
+        }
+
+        // Physics, which is stepped and animations, which are updated every frame
+        if let Some((engine, gravity)) = statement.split_once(".apply_gravity(") {
+            let gravity = gravity.strip_suffix(')').and_then(|g| self.vector(g));
+            return match (self.bodies.iter_mut().find(|b| b.engine == engine), gravity) {
+                (Some(body), Some(gravity)) => {
+                    body.gravity = Some(gravity);
+                    true
+                }
+                _ => false,
+            };
+        }
+        if let Some((engine, _)) = statement.split_once(".step(") {
+            return self.bodies.iter().any(|body| body.engine == engine);
+        }
+        if let Some(physics) = statement.strip_suffix("_time = time") {
+            return self.bodies.iter().any(|body| body.physics == physics);
+        }
+        if let Some((target, value)) = statement.split_once(" = ") {
+            if let Some(args) = call(value, "animate") {
+                return self.animate(target, &args);
+            }
+        }
+
+        let (name, value) = match declaration(statement) {
+            Some(declaration) => declaration,
+            None => return false,
+        };
+        if let Some(args) = call(value.trim_start_matches("try "), "Scene.init") {
+            let camera = match args.first().and_then(|camera| identifier(camera)) {
+                Some(camera) => camera,
+                None => return false,
+            };
+            self.object("ACTIVE", "active", vec![("camera", camera)]);
+            return true;
+        }
+        if let Some(vector) = call(value, "Vec3.init").and_then(|args| vector3(&args)) {
+            self.vectors.push((name.to_string(), vector));
+            return true;
+        }
+        if let Some(args) = call(value, "PhysicsEngine.init") {
+            return self.physics(name, &args);
+        }
+        if let Some(physics) = name.strip_suffix("_time") {
+            if self.bodies.iter().any(|body| body.physics == physics) {
+                return value == "0";
+            }
+        }
+        if let Some(animation) = name.strip_suffix("_keys") {
+            return match self.keys(value) {
+                Some(keys) => {
+                    self.object("ANIMATION", animation, vec![("keys", keys)]);
+                    true
+                }
+                None => false,
+            };
+        }
+
+        match self.translate(value) {
+            Some((keyword, properties)) => {
+                self.object(keyword, name, properties);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn object(
+        &mut self,
+        keyword: &'static str,
+        name: &str,
+        properties: Vec<(&'static str, String)>,
+    ) {
+        let scene = self.scenes.last_mut().unwrap();
+        scene.objects.push(Object {
+            keyword,
+            name: name.to_string(),
+            properties: properties
+                .into_iter()
+                .map(|(prop, value)| (prop.to_string(), value))
+                .collect(),
+        });
+    }
+
+    /// Sets `property` of the object `name` in the scene being read.
+    fn property(&mut self, name: &str, property: &str, value: String) -> bool {
+        let scene = self.scenes.last_mut().unwrap();
+        match scene.objects.iter_mut().find(|o| o.name == name) {
+            Some(object) => {
+                object.properties.push((property.to_string(), value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// `var engine = PhysicsEngine.init(&obj_body, .{ ... })`, where the
+    /// engine is named after the PHYSICS block and the body. The settings of
+    /// the body go on the body itself, which is what they would override.
+    fn physics(&mut self, engine: &str, args: &[String]) -> bool {
+        let [body, options] = args else {
+            return false;
+        };
+        let Some(body) = body.strip_prefix("&obj_") else {
+            return false;
+        };
+        let Some(physics) = engine.strip_suffix(&format!("_{}", body)) else {
+            return false;
+        };
+        let Some(options) = literal(options, "") else {
+            return false;
+        };
+
+        let mut settings = Vec::new();
+        let mut timestep = None;
+        for (option, value) in options {
+            let setting = match option.as_str() {
+                "mass" | "restitution" | "friction" => number(&value),
+                "velocity" => self.vector(&value),
+                "is_static" => boolean(&value),
+                "timestep" => {
+                    timestep = number(&value);
+                    continue;
+                }
+                _ => None,
+            };
+            match setting {
+                Some(setting) => settings.push((option, setting)),
+                None => return false,
+            }
+        }
+        let is_static = settings.contains(&("is_static".to_string(), "true".to_string()));
+        for (option, value) in settings {
+            let option = if option == "is_static" {
+                "static"
+            } else {
+                &option
+            };
+            if !self.property(body, option, value) {
+                return false;
+            }
+        }
+
+        let scene = self.scenes.last_mut().unwrap();
+        let block = scene
+            .objects
+            .iter_mut()
+            .find(|o| o.keyword == "PHYSICS" && o.name == physics);
+        match block {
+            Some(block) => {
+                let bodies = &mut block.properties[0].1;
+                *bodies = format!("{}, {}]", bodies.trim_end_matches(']'), body);
+            }
+            None => {
+                let mut properties = vec![("bodies", format!("[{}]", body))];
+                properties.extend(timestep.map(|timestep| ("timestep", timestep)));
+                self.object("PHYSICS", physics, properties);
+            }
+        }
+        self.bodies.push(Body {
+            engine: engine.to_string(),
+            physics: physics.to_string(),
+            gravity: None,
+            is_static,
+        });
+        true
+    }
+
+    /// `[_]Keyframe{ .{ .time = 0, .value = Vec3.init(0, 0, 8) }, ... }` as
+    /// `[(0, (0, 0, 8)), ...]`.
+    fn keys(&self, value: &str) -> Option<String> {
+        let inner = value.strip_prefix("[_]Keyframe{")?.strip_suffix('}')?;
+        let mut keys = Vec::new();
+        for key in split(inner) {
+            let fields = literal(&key, "")?;
+            let [(t, time), (v, value)] = &fields[..] else {
+                return None;
+            };
+            if (t.as_str(), v.as_str()) != ("time", "value") {
+                return None;
+            }
+            keys.push(format!("({}, {})", number(time)?, self.vector(value)?));
+        }
+        Some(format!("[{}]", keys.join(", ")))
+    }
+
+    /// `target = animate(&name_keys, time, .easing, .mode)` in the frame loop,
+    /// which completes the ANIMATION `name`.
+    fn animate(&mut self, destination: &str, args: &[String]) -> bool {
+        let [keys, time, easing, mode] = args else {
+            return false;
+        };
+        let name = keys
+            .strip_prefix('&')
+            .and_then(|keys| keys.strip_suffix("_keys"));
+        let easing = easing.strip_prefix('.').and_then(identifier);
+        let mode = mode.strip_prefix('.').and_then(identifier);
+        let target = self.target(destination);
+        let (Some(name), Some(easing), Some(mode), Some(target)) = (name, easing, mode, target)
+        else {
+            return false;
+        };
+        if time != "time" {
+            return false;
+        }
+
+        let scene = self.scenes.last_mut().unwrap();
+        let animation = scene
+            .objects
+            .iter_mut()
+            .find(|o| o.keyword == "ANIMATION" && o.name == name);
+        let Some(animation) = animation else {
+            return false;
+        };
+        animation
+            .properties
+            .insert(0, ("target".to_string(), target));
+        if easing != "linear" {
+            animation.properties.push(("easing".to_string(), easing));
+        }
+        if mode != "once" {
+            animation.properties.push(("loop".to_string(), mode));
+        }
+        true
+    }
+
+    /// The `object.property` an animation updates, from the variable declared
+    /// for it or the copy of a light in `lights`.
+    fn target(&self, destination: &str) -> Option<String> {
+        let objects = &self.scenes.last()?.objects;
+        if let Some(rest) = destination.strip_prefix("lights.items[") {
+            let (index, property) = rest.split_once("].")?;
+            let mut lights = objects.iter().filter(|o| o.keyword == "LIGHT");
+            let light = lights.nth(index.parse().ok()?)?;
+            return Some(format!("{}.{}", light.name, identifier(property)?));
+        }
+        ["position", "direction", "intensity"]
+            .iter()
+            .find_map(|property| {
+                let name = destination.strip_suffix(property)?.strip_suffix('_')?;
+                objects
+                    .iter()
+                    .any(|o| o.name == name)
+                    .then(|| format!("{}.{}", name, property))
+            })
+    }
+
+    /// The keyword and properties of the object `value` constructs.
+    fn translate(&self, value: &str) -> Option<(&'static str, Vec<(&'static str, String)>)> {
+        if let Some(args) = call(value, "Sphere.init") {
+            let [position, radius, material] = &args[..] else {
+                return None;
+            };
+            return Some((
+                "SPHERE",
+                vec![
+                    ("position", self.vector(position)?),
+                    ("radius", number(radius)?),
+                    ("material", reference(material)?),
+                ],
+            ));
+        }
+
+        if let Some(args) = call(value, "Rectangle.init") {
+            let [a, b, c, d, material, inverted] = &args[..] else {
+                return None;
+            };
+            let corners = [a, b, c, d].map(|corner| self.vector(corner));
+            let [Some(a), Some(b), Some(c), Some(d)] = corners else {
+                return None;
+            };
+            // Zest spans rectangles from v0 to v1, which fixes the other corners
+            let (v0, v1) = (components(&a)?, components(&c)?);
+            let expected = [[v0[0], v1[1], v1[2]], [v1[0], v1[1], v0[2]]];
+            if [components(&b)?, components(&d)?] != expected {
+                return None;
+            }
+            return Some((
+                "RECTANGLE",
+                vec![
+                    ("v0", a),
+                    ("v1", c),
+                    ("material", reference(material)?),
+                    ("inverted", boolean(inverted)?),
+                ],
+            ));
+        }
+
+        if let Some(fields) = literal(value, "Light") {
+            let mut properties = Vec::new();
+            for (field, value) in fields {
+                match field.as_str() {
+                    "position" => properties.push(("position", self.vector(&value)?)),
+                    "intensity" => properties.push(("intensity", self.vector(&value)?)),
+                    _ => return None,
+                }
+            }
+            return Some(("LIGHT", properties));
+        }
+
+        if let Some(fields) = literal(value, "Material") {
+            let (texture, reflectivity) = match &fields[..] {
+                [(field, texture)] if field == "texture" => (texture, None),
+                [(field, texture), (other, reflectivity)]
+                    if field == "texture" && other == "reflectivity" =>
+                {
+                    (texture, Some(number(reflectivity)?))
+                }
+                _ => return None,
+            };
+            let [(kind, value)] = &literal(texture, "Texture")?[..] else {
+                return None;
+            };
+            let mut properties = match kind.as_str() {
+                "SOLID_COLOR" => vec![("color", rgb(value)?)],
+                "TEXTURE_FILE" => vec![("image", identifier(value)?)],
+                _ => return None,
+            };
+            properties.extend(reflectivity.map(|reflectivity| ("reflectivity", reflectivity)));
+            return Some(("MATERIAL", properties));
+        }
+
+        let image = value
+            .strip_prefix("try ")
+            .and_then(|value| call(value.trim(), "Image.init"));
+        if let Some(args) = image {
+            let [file] = &args[..] else {
+                return None;
+            };
+            return file
+                .starts_with('"')
+                .then(|| ("IMAGE", vec![("file", file.clone())]));
+        }
+
+        if let Some(fields) = literal(value, "EventHandler") {
+            let mut properties = Vec::new();
+            let mut actions = Vec::new();
+            for (field, value) in fields {
+                match field.as_str() {
+                    "mouse_movement" => properties.push(("mouse_movement", boolean(&value)?)),
+                    "keyboard_movement" => properties.push(("keyboard_movement", boolean(&value)?)),
+                    "mouse_sensitivity" => properties.push(("sensitivity", number(&value)?)),
+                    "invert_y" => properties.push(("invert_y", boolean(&value)?)),
+                    "movement_speed" => properties.push(("speed", number(&value)?)),
+                    "bindings" => properties.push(("bindings", bindings(&value, &mut actions)?)),
+                    "width" | "height" | "on_action" => {}
+                    _ => return None,
+                }
+            }
+            if !actions.is_empty() {
+                properties.push(("actions", format!("[{}]", actions.join(", "))));
+            }
+            return Some(("CONTROLLER", properties));
+        }
+
+        if let Some(fields) = literal(value, "Camera") {
+            let mut properties = Vec::new();
+            for (field, value) in fields {
+                match field.as_str() {
+                    "position" => {
+                        let handler = literal(value.strip_prefix('&')?, "PositionHandler")?;
+                        let [(single, point)] = &handler[..] else {
+                            return None;
+                        };
+                        if single != "single" {
+                            return None;
+                        }
+                        for (field, value) in literal(point, "SinglePointHandler")? {
+                            match field.as_str() {
+                                "point" => properties.push(("position", self.vector(&value)?)),
+                                "direction" => properties.push(("direction", self.vector(&value)?)),
+                                _ => return None,
+                            }
+                        }
+                    }
+                    "event_handler" => properties.push(("event_handler", reference(&value)?)),
+                    _ => return None,
+                }
+            }
+            return Some(("CAMERA", properties));
+        }
+
+        gui(value)
+    }
+
+    /// `Vec3.init(x, y, z)` as `(x, y, z)`, or the vector of a variable,
+    /// behind any `@constCast(&...)` or `&`.
+    fn vector(&self, value: &str) -> Option<String> {
+        let value = value.trim();
+        let value = match value.strip_prefix("@constCast(") {
+            Some(inner) => inner.strip_suffix(')')?.trim(),
+            None => value,
+        };
+        let value = value.strip_prefix('&').unwrap_or(value).trim();
+
+        if let Some(args) = call(value, "Vec3.init") {
+            return vector3(&args);
+        }
+        self.vectors
+            .iter()
+            .find(|(name, _)| name == value)
+            .map(|(_, vector)| vector.clone())
+    }
+
+    fn zest(&self) -> String {
+        let mut output = String::new();
+
+        let window: Vec<_> = self
+            .window
+            .iter()
+            .filter(|(prop, value)| !DEFAULTS.contains(&(prop.as_str(), value.as_str())))
+            .collect();
+        if !window.is_empty() {
+            output.push_str("WINDOW {\n");
+            for (prop, value) in window {
+                output.push_str(&format!("    .{} = {},\n", prop, value));
+            }
+            output.push_str("}\n\n");
+        }
+
+        let width = self
+            .window
+            .iter()
+            .find(|(prop, _)| prop == "width")
+            .and_then(|(_, width)| width.parse::<f64>().ok())
+            .unwrap_or(400.0);
+        let mut render = Vec::new();
+        for (prop, value) in &self.render {
+            match prop.as_str() {
+                "fov" if value != "90" => render.push(("fov".to_string(), value.clone())),
+                // The transpiler derives the resolution from the window width
+                "resolution" => {
+                    let scale = value.parse::<f64>().unwrap_or(width) / width;
+                    if scale != 1.0 {
+                        render.push(("scale".to_string(), scale.to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !render.is_empty() {
+            output.push_str("RENDER {\n");
+            for (prop, value) in render {
+                output.push_str(&format!("    .{} = {},\n", prop, value));
+            }
+            output.push_str("}\n\n");
+        }
+
+        if let Some(start) = &self.start {
+            if self
+                .scenes
+                .first()
+                .is_some_and(|scene| scene.name != *start)
+            {
+                output.push_str(&format!("START {}\n\n", start));
+            }
+        }
+
+        for code in &self.zig {
+            output.push_str(code);
+            output.push_str("\n\n");
+        }
+
+        for (i, scene) in self.scenes.iter().enumerate() {
+            if i > 0 {
+                output.push('\n');
+            }
+            output.push_str(&format!("SCENE {} {{\n", scene.name));
+            for (j, object) in scene.objects.iter().enumerate() {
+                if j > 0 {
+                    output.push('\n');
+                }
+                if object.keyword == "ZIG" {
+                    output.push_str(&format!("    {}\n", object.properties[0].1));
+                    continue;
+                }
+                output.push_str(&format!("    {} {} {{\n", object.keyword, object.name));
+                for (prop, value) in &object.properties {
+                    output.push_str(&format!("        .{} = {},\n", prop, value));
+                }
+                output.push_str("    }\n");
+            }
+            output.push_str("}\n");
+        }
+
+        output
+    }
+}
+
+/// The first and last line of the comments around each ZIG block.
+fn blocks(lines: &[String]) -> Vec<(usize, usize, bool)> {
+    let mut blocks = Vec::new();
+    let mut start = None;
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line == ZIG_END {
+            if let Some(start) = start.take() {
+                blocks.push((start, i + 1, false));
+            }
+        } else if line.starts_with(ZIG_START) {
+            start = Some(i + 1);
+        }
+    }
+    blocks
+}
+
+/// `code` as a ZIG block, with its braces indented by `indent` spaces.
+fn zig(code: &[String], indent: usize) -> String {
+    let mut output = String::from("ZIG {\n");
+    for line in code {
+        if !line.is_empty() {
+            output.push_str(&" ".repeat(indent + 4));
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+    output.push_str(&" ".repeat(indent));
+    output.push('}');
+    output
+}
+
+/// The keyword and properties of the GUI element `value` constructs.
+fn gui(value: &str) -> Option<(&'static str, Vec<(&'static str, String)>)> {
+    let obj_type = [
+        ObjectType::Text,
+        ObjectType::Panel,
+        ObjectType::Button,
+        ObjectType::Sprite,
+    ]
+    .into_iter()
+    .find(|obj_type| literal(value, &obj_type.to_string()).is_some())?;
+
+    let mut properties = Vec::new();
+    for (field, value) in literal(value, &obj_type.to_string())? {
+        let property = transpiler::gui_properties(obj_type)
+            .iter()
+            .find(|property| **property == field)?;
+        let value = match *property {
+            "position" => pair(&value, ["x", "y"])?,
+            "size" => pair(&value, ["width", "height"])?,
+            "anchor" => identifier(value.strip_prefix('.')?)?,
+            "text" | "font" => value.starts_with('"').then(|| value.clone())?,
+            "font_size" => number(&value)?,
+            "color" | "text_color" => rgb(&value)?,
+            "image" => reference(&value)?,
+            _ => action(value.strip_prefix('&')?)?,
+        };
+        properties.push((*property, value));
+    }
+    Some((obj_type.keyword(), properties))
+}
+
+/// `.{ .x = x, .y = y }` as `(x, y)`, for the given field names.
+fn pair(value: &str, names: [&str; 2]) -> Option<String> {
+    let fields = literal(value, "")?;
+    let [(a, x), (b, y)] = &fields[..] else {
+        return None;
+    };
+    if [a.as_str(), b.as_str()] != names {
+        return None;
+    }
+    Some(format!("({}, {})", number(x)?, number(y)?))
+}
+
+/// The custom action a handler such as `onOpenMenu` is named after.
+fn action(handler: &str) -> Option<String> {
+    let mut action = String::new();
+    for c in handler.strip_prefix("on")?.chars() {
+        if c.is_ascii_uppercase() && !action.is_empty() {
+            action.push('_');
+        }
+        action.push(c.to_ascii_lowercase());
+    }
+    (transpiler::handler(&action) == handler).then_some(action)
+}
+
+/// The scene a function header declares, as the transpiler names them.
+fn scene_name(header: &str) -> Option<String> {
+    let name = header.strip_prefix("fn scene_")?.strip_suffix("() !void")?;
+    Some(name.to_string())
+}
+
+/// The name and value of `const name = value` or `var name = value`, with
+/// an optional type.
+fn declaration(statement: &str) -> Option<(&str, &str)> {
+    let rest = ["pub const ", "pub var ", "const ", "var "]
+        .iter()
+        .find_map(|prefix| statement.strip_prefix(prefix))?;
+    let (name, value) = rest.split_once('=')?;
+    let name = name.split(':').next()?.trim();
+    identifier(name)?;
+    Some((name, value.trim()))
+}
+
+/// The arguments of `value` if it calls a function whose path ends in `name`.
+fn call(value: &str, name: &str) -> Option<Vec<String>> {
+    let open = value.find('(')?;
+    let callee = value[..open].trim();
+    if !(callee == name || callee.ends_with(&format!(".{}", name))) {
+        return None;
+    }
+    let inner = value[open + 1..].strip_suffix(')')?;
+    Some(split(inner))
+}
+
+/// The fields of `value` if it is a struct literal of a type ending in `name`.
+fn literal(value: &str, name: &str) -> Option<Vec<(String, String)>> {
+    let open = value.find('{')?;
+    let kind = value[..open].trim();
+    if !(kind == name || kind.ends_with(&format!(".{}", name))) {
+        return None;
+    }
+    let inner = value[open + 1..].trim_end().strip_suffix('}')?;
+
+    split(inner)
+        .into_iter()
+        .map(|field| {
+            let (field, value) = field.strip_prefix('.')?.split_once('=')?;
+            Some((field.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Splits `text` at the commas outside of brackets and strings.
+fn split(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+fn vector3(args: &[String]) -> Option<String> {
+    let [x, y, z] = args else {
+        return None;
+    };
+    Some(format!("({}, {}, {})", number(x)?, number(y)?, number(z)?))
+}
+
+fn components(vector: &str) -> Option<[f64; 3]> {
+    let inner = vector.strip_prefix('(')?.strip_suffix(')')?;
+    let parts: Vec<f64> = inner
+        .split(',')
+        .map(|c| c.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    parts.try_into().ok()
+}
+
+/// `z3d.graphics.RGB{ .r = r, .g = g, .b = b }` as `(r, g, b)`.
+fn rgb(value: &str) -> Option<String> {
+    let fields = literal(value, "RGB")?;
+    let [(r, red), (g, green), (b, blue)] = &fields[..] else {
+        return None;
+    };
+    if (r.as_str(), g.as_str(), b.as_str()) != ("r", "g", "b") {
+        return None;
+    }
+    Some(format!(
+        "({}, {}, {})",
+        number(red)?,
+        number(green)?,
+        number(blue)?
+    ))
+}
+
+/// Bindings like `.{ .key = .w, .action = .{ .move = .forward } }` as a
+/// Zest map, collecting custom actions into `actions`.
+fn bindings(value: &str, actions: &mut Vec<String>) -> Option<String> {
+    let inner = value.strip_prefix('&')?.strip_prefix("[_]")?;
+    let inner = &inner[inner.find('{')? + 1..];
+    let inner = inner.trim_end().strip_suffix('}')?;
+
+    let mut entries = Vec::new();
+    for binding in split(inner) {
+        let fields = literal(&binding, "")?;
+        let [(k, key), (a, action)] = &fields[..] else {
+            return None;
+        };
+        if (k.as_str(), a.as_str()) != ("key", "action") {
+            return None;
+        }
+        let key = key.strip_prefix('.')?;
+        let key = KEYS.iter().find(|(_, z3d)| *z3d == key)?.0;
+
+        let [(kind, name)] = &literal(action, "")?[..] else {
+            return None;
+        };
+        let action = match kind.as_str() {
+            "move" => name.strip_prefix('.')?.to_string(),
+            "custom" => {
+                let action = identifier(name.strip_prefix('"')?.strip_suffix('"')?)?;
+                if !actions.contains(&action) {
+                    actions.push(action.clone());
+                }
+                action
+            }
+            _ => return None,
+        };
+        entries.push(format!("            {}: {},", key, action));
+    }
+    Some(format!("{{\n{}\n        }}", entries.join("\n")))
+}
+
+/// A number as Zest writes it.
+fn number(value: &str) -> Option<String> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+    let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    (valid(integer) && valid(fraction)).then(|| value.to_string())
+}
+
+fn boolean(value: &str) -> Option<String> {
+    matches!(value, "true" | "false").then(|| value.to_string())
+}
+
+fn identifier(value: &str) -> Option<String> {
+    let valid = value.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then(|| value.to_string())
+}
+
+/// `&name` as `name`.
+fn reference(value: &str) -> Option<String> {
+    identifier(value.strip_prefix('&')?.trim())
+}
+
+/// The text without comments, with the line each character is on.
+fn strip_comments(text: &str) -> Vec<(char, usize)> {
+    let mut chars = Vec::new();
+    let mut line = 1;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut comment = false;
+    let mut iter = text.chars().peekable();
+    while let Some(c) = iter.next() {
+        if c == '\n' {
+            line += 1;
+            comment = false;
+            quote = None;
+        } else if comment {
+            continue;
+        } else if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if c == '/' && iter.peek() == Some(&'/') {
+            comment = true;
+            continue;
+        }
+        chars.push((c, line));
+    }
+    chars
+}
+
+/// Reads statements up to the end of the current block, at `chars[*current]`.
+fn block(chars: &[(char, usize)], current: &mut usize) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut text = String::new();
+    let mut line = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    let flush = |text: &mut String, line: usize, items: &mut Vec<Item>| {
+        let statement = normalise(text);
+        if !statement.is_empty() {
+            items.push(Item::Statement(statement, line));
+        }
+        text.clear();
+    };
+
+    while *current < chars.len() {
+        let (c, l) = chars[*current];
+        *current += 1;
+        if text.trim().is_empty() {
+            line = l;
+        }
+
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            text.push(c);
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '{' if depth == 0 && opens_block(&text) => {
+                let header = normalise(&text);
+                text.clear();
+                items.push(Item::Block(header, line, block(chars, current)));
+                continue;
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                flush(&mut text, line, &mut items);
+                return items;
+            }
+            '}' => depth -= 1,
+            ';' if depth == 0 => {
+                flush(&mut text, line, &mut items);
+                continue;
+            }
+            _ => {}
+        }
+        text.push(c);
+    }
+
+    flush(&mut text, line, &mut items);
+    items
+}
+
+/// Whether a brace after `text` opens a block of statements rather than a
+/// struct or array literal.
+fn opens_block(text: &str) -> bool {
+    let text = text.trim();
+    let function = ["fn ", "pub fn ", "inline fn ", "export fn "]
+        .iter()
+        .any(|prefix| text.starts_with(prefix));
+    function
+        || text.is_empty()
+        || text.ends_with(')')
+        || text.ends_with('|')
+        || text.ends_with(':')
+        || text.ends_with("void")
+        || text.ends_with("else")
+}
+
+/// `text` on one line, with runs of whitespace outside strings collapsed.
+fn normalise(text: &str) -> String {
+    let mut output = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.trim().chars() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            output.push(c);
+        } else if c.is_whitespace() {
+            if !output.ends_with(' ') {
+                output.push(' ');
+            }
+        } else {
+            if c == '"' || c == '\'' {
+                quote = Some(c);
+            }
+            output.push(c);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constructor, evaluator};
+
+    fn transpile(file: &str, text: &str) -> String {
+        let mut constructor = constructor::Constructor::new(file.to_string(), text.to_string());
+        constructor.construct();
+        evaluator::Evaluator::new().fold_engine(&mut constructor.engine);
+        transpiler::Transpiler::new(constructor.engine).transpile()
+    }
+
+    /// The Zig without the comments saying where ZIG blocks were written.
+    fn code(zig: &str) -> Vec<&str> {
+        zig.lines()
+            .filter(|line| !line.trim().starts_with(ZIG_START) || line.trim() == ZIG_END)
+            .collect()
+    }
+
+    #[test]
+    fn examples_round_trip() {
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "zest") {
+                continue;
+            }
+            let file = path.to_string_lossy().to_string();
+            let zig = transpile(&file, &std::fs::read_to_string(&path).unwrap());
+
+            let (zest, skipped) = import(&zig);
+            assert!(skipped.is_empty(), "{}: {:?}", file, skipped);
+            assert_eq!(code(&transpile(&file, &zest)), code(&zig), "{}", file);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+/// A JSON value. Objects keep their keys in order, and numbers keep the text
+/// they were written with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses `text`, panicking with the position of the first syntax error.
+    pub fn parse(file: &str, text: &str) -> Json {
+        let mut parser = Parser {
+            file,
+            chars: text.chars().collect(),
+            current: 0,
+        };
+        let value = parser.value();
+        parser.skip_whitespace();
+        if parser.current < parser.chars.len() {
+            parser.error("Unexpected text after JSON value");
+        }
+        value
+    }
+
+    /// The value of `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "bool",
+            Json::Number(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    /// The value indented by two spaces per level, with arrays of plain
+    /// values such as vectors kept on one line.
+    pub fn pretty(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output, 0);
+        output.push('\n');
+        output
+    }
+
+    fn write(&self, output: &mut String, indent: usize) {
+        match self {
+            Json::Null => output.push_str("null"),
+            Json::Bool(b) => output.push_str(&b.to_string()),
+            Json::Number(num) => output.push_str(num),
+            Json::String(str) => output.push_str(&quote(str)),
+            Json::Array(values) if values.is_empty() => output.push_str("[]"),
+            Json::Array(values)
+                if values
+                    .iter()
+                    .all(|v| !matches!(v, Json::Array(_) | Json::Object(_))) =>
+            {
+                output.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        output.push_str(", ");
+                    }
+                    value.write(output, indent);
+                }
+                output.push(']');
+            }
+            Json::Array(values) => {
+                output.push_str("[\n");
+                for (i, value) in values.iter().enumerate() {
+                    output.push_str(&"  ".repeat(indent + 1));
+                    value.write(output, indent + 1);
+                    output.push_str(if i + 1 < values.len() { ",\n" } else { "\n" });
+                }
+                output.push_str(&"  ".repeat(indent));
+                output.push(']');
+            }
+            Json::Object(entries) if entries.is_empty() => output.push_str("{}"),
+            Json::Object(entries) => {
+                output.push_str("{\n");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    output.push_str(&"  ".repeat(indent + 1));
+                    output.push_str(&quote(key));
+                    output.push_str(": ");
+                    value.write(output, indent + 1);
+                    output.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+                }
+                output.push_str(&"  ".repeat(indent));
+                output.push('}');
+            }
+        }
+    }
+}
+
+/// An object of `entries`, in order.
+pub fn object(entries: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+/// Whether `text` is a number as JSON writes them.
+pub fn is_number(text: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let text = text.strip_prefix('-').unwrap_or(text);
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (text, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    digits(integer)
+        && (integer == "0" || !integer.starts_with('0'))
+        && fraction.is_none_or(digits)
+        && exponent.is_none_or(|e| digits(e.strip_prefix(['+', '-']).unwrap_or(e)))
+}
+
+fn quote(text: &str) -> String {
+    let mut output = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ! {
+        let before = &self.chars[..self.current.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        panic!("{}:{}:{}: {}", self.file, line, column, message)
+    }
+
+    fn peek(&self) -> char {
+        self.chars.get(self.current).copied().unwrap_or('\0')
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), ' ' | '\t' | '\n' | '\r') {
+            self.current += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) {
+        self.skip_whitespace();
+        if self.peek() != c {
+            self.error(&format!("Expected `{}`", c));
+        }
+        self.current += 1;
+    }
+
+    fn value(&mut self) -> Json {
+        self.skip_whitespace();
+        match self.peek() {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => Json::String(self.string()),
+            '-' | '0'..='9' => self.number(),
+            'a'..='z' => {
+                let start = self.current;
+                while self.peek().is_ascii_lowercase() {
+                    self.current += 1;
+                }
+                match self.chars[start..self.current]
+                    .iter()
+                    .collect::<String>()
+                    .as_str()
+                {
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    "null" => Json::Null,
+                    word => {
+                        self.current = start;
+                        self.error(&format!("Unexpected `{}`", word))
+                    }
+                }
+            }
+            '\0' => self.error("Unexpected end of JSON"),
+            c => self.error(&format!("Unexpected `{}`", c)),
+        }
+    }
+
+    fn object(&mut self) -> Json {
+        self.expect('{');
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == '}' {
+            self.current += 1;
+            return Json::Object(entries);
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != '"' {
+                self.error("Expected string key");
+            }
+            let start = self.current;
+            let key = self.string();
+            if entries.iter().any(|(other, _)| *other == key) {
+                self.current = start;
+                self.error(&format!("Duplicate key \"{}\"", key));
+            }
+            self.expect(':');
+            entries.push((key, self.value()));
+
+            self.skip_whitespace();
+            match self.peek() {
+                ',' => self.current += 1,
+                '}' => {
+                    self.current += 1;
+                    return Json::Object(entries);
+                }
+                _ => self.error("Expected `,` or `}`"),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Json {
+        self.expect('[');
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == ']' {
+            self.current += 1;
+            return Json::Array(values);
+        }
+
+        loop {
+            values.push(self.value());
+            self.skip_whitespace();
+            match self.peek() {
+                ',' => self.current += 1,
+                ']' => {
+                    self.current += 1;
+                    return Json::Array(values);
+                }
+                _ => self.error("Expected `,` or `]`"),
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        self.current += 1;
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                '"' => {
+                    self.current += 1;
+                    return string;
+                }
+                '\\' => {
+                    self.current += 1;
+                    let escaped = match self.peek() {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let mut code = self.hex();
+                            // Characters outside the BMP are written as surrogate pairs
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.chars.get(self.current + 1) == Some(&'\\')
+                                && self.chars.get(self.current + 2) == Some(&'u')
+                            {
+                                self.current += 2;
+                                let low = self.hex();
+                                code = 0x10000
+                                    + ((code - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => self.error("Invalid unicode escape"),
+                            }
+                        }
+                        _ => self.error("Invalid escape"),
+                    };
+                    string.push(escaped);
+                    self.current += 1;
+                }
+                '\0' if self.current >= self.chars.len() => self.error("Unterminated string"),
+                c if (c as u32) < 0x20 => self.error("Unescaped control character in string"),
+                c => {
+                    string.push(c);
+                    self.current += 1;
+                }
+            }
+        }
+    }
+
+    /// The four hex digits of a `\u` escape, leaving the cursor on the last.
+    fn hex(&mut self) -> u32 {
+        let digits: String = self.chars.iter().skip(self.current + 1).take(4).collect();
+        match u32::from_str_radix(&digits, 16) {
+            Ok(code) if digits.len() == 4 => {
+                self.current += 4;
+                code
+            }
+            _ => self.error("Expected four hex digits"),
+        }
+    }
+
+    fn number(&mut self) -> Json {
+        let start = self.current;
+        while matches!(self.peek(), '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+            self.current += 1;
+        }
+        let number: String = self.chars[start..self.current].iter().collect();
+        if !is_number(&number) {
+            self.current = start;
+            self.error(&format!("Invalid number `{}`", number));
+        }
+        Json::Number(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let text = "{\n  \"name\": \"a \\\"b\\\"\\n\\u00e9\",\n  \"list\": [1, -2.5e3, true, null],\n  \"empty\": {}\n}\n";
+        let json = Json::parse("test.json", text);
+        assert_eq!(
+            json.get("name"),
+            Some(&Json::String("a \"b\"\né".to_string()))
+        );
+        assert_eq!(json.pretty(), text.replace("\\u00e9", "é"));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.json:2:3: Duplicate key \"a\"")]
+    fn keys_are_unique() {
+        Json::parse("test.json", "{\"a\": 1,\n  \"a\": 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "test.json:1:7: Invalid number `01`")]
+    fn numbers_are_checked() {
+        Json::parse("test.json", "{\"a\": 01}");
+    }
+
+    #[test]
+    #[should_panic(expected = "test.json:1:5: Unterminated string")]
+    fn strings_are_terminated() {
+        Json::parse("test.json", "[\"ab");
+    }
+
+    #[test]
+    #[should_panic(expected = "test.json:1:4: Unexpected text after JSON value")]
+    fn values_end_the_text() {
+        Json::parse("test.json", "{} {}");
+    }
+}
@@ -0,0 +1,892 @@
+use crate::constructor::{self, Constructor, ObjectType};
+use crate::cst::Cst;
+use crate::json::{self, Json};
+use crate::scope::{self, Symbol};
+use crate::tokeniser::{Position, Token};
+use crate::{evaluator, transpiler};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const REQUEST_FAILED: i64 = -32803;
+
+// Completion item kinds
+const PROPERTY: usize = 10;
+const KEYWORD: usize = 14;
+
+/// Serves the Language Server Protocol over standard input and output until
+/// the client exits.
+pub fn serve() {
+    // Errors are reported to the client, which owns the output
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut server = Server {
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    let mut input = std::io::stdin().lock();
+    while let Some(body) = read(&mut input) {
+        let message = match parse(&body) {
+            Ok(message) => message,
+            Err(response) => {
+                send(response);
+                continue;
+            }
+        };
+        let method = match message.get("method") {
+            Some(Json::String(method)) => method.clone(),
+            // A response, though the server never sends requests
+            _ => continue,
+        };
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| server.handle(&method, &params)));
+
+        // Only requests have an id, and need a response
+        if let Some(id) = message.get("id") {
+            let outcome = match result {
+                Ok(Some(result)) => ("result", result),
+                Ok(None) => (
+                    "error",
+                    error(METHOD_NOT_FOUND, format!("Unknown method: {}", method)),
+                ),
+                Err(panic) => ("error", error(REQUEST_FAILED, panic_message(panic))),
+            };
+            send(response(id.clone(), outcome));
+        }
+        if method == "exit" {
+            std::process::exit(if server.shutdown { 0 } else { 1 });
+        }
+    }
+}
+
+struct Server {
+    /// The text of each open document, by URI
+    documents: HashMap<String, String>,
+    /// Whether the client has asked the server to shut down
+    shutdown: bool,
+}
+
+impl Server {
+    /// Handles the request or notification `method`, returning its result,
+    /// or `None` if it is not supported.
+    fn handle(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let result = match method {
+            "initialize" => capabilities(),
+            "initialized" | "exit" => Json::Null,
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/didOpen" => {
+                let uri = string(params, &["textDocument", "uri"]);
+                let text = string(params, &["textDocument", "text"]);
+                self.documents.insert(uri.clone(), text);
+                self.publish(&uri);
+                Json::Null
+            }
+            "textDocument/didChange" => {
+                let uri = string(params, &["textDocument", "uri"]);
+                // Documents are synchronised in full, so the last change is the text
+                let text = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes.last().map(|c| string(c, &["text"])),
+                    _ => None,
+                };
+                if let Some(text) = text {
+                    self.documents.insert(uri.clone(), text);
+                    self.publish(&uri);
+                }
+                Json::Null
+            }
+            "textDocument/didClose" => {
+                let uri = string(params, &["textDocument", "uri"]);
+                self.documents.remove(&uri);
+                notify(
+                    "textDocument/publishDiagnostics",
+                    diagnostics(&uri, Vec::new()),
+                );
+                Json::Null
+            }
+            "textDocument/completion" => {
+                let (uri, text, position) = self.document(params);
+                completion(&Cst::parse(&path(uri), text), position)
+            }
+            "textDocument/hover" => {
+                let (uri, text, position) = self.document(params);
+                hover(text, &Cst::parse(&path(uri), text), position).unwrap_or(Json::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, text, position) = self.document(params);
+                let cst = Cst::parse(&path(uri), text);
+                let locations = match symbol_at(&cst, position) {
+                    Some(symbol) => {
+                        let start = start(&cst, symbol.declaration);
+                        vec![location(uri, text, start, symbol.name.len())]
+                    }
+                    None => match name_at(&cst, position) {
+                        Some(name) => generators(&cst, &name)
+                            .into_iter()
+                            .map(|i| {
+                                let element = &cst.elements[i];
+                                location(uri, text, element.position, element.text.len())
+                            })
+                            .collect(),
+                        None => Vec::new(),
+                    },
+                };
+                Json::Array(locations)
+            }
+            "textDocument/references" => {
+                let (uri, text, position) = self.document(params);
+                let cst = Cst::parse(&path(uri), text);
+                let declarations = matches!(
+                    params
+                        .get("context")
+                        .and_then(|c| c.get("includeDeclaration")),
+                    Some(Json::Bool(true))
+                );
+                let locations = match symbol_at(&cst, position) {
+                    Some(symbol) => symbol
+                        .occurrences()
+                        .skip(if declarations { 0 } else { 1 })
+                        .map(|occurrence| {
+                            location(uri, text, start(&cst, occurrence), symbol.name.len())
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                };
+                Json::Array(locations)
+            }
+            "textDocument/rename" => {
+                let (uri, text, position) = self.document(params);
+                let cst = Cst::parse(&path(uri), text);
+                let new = string(params, &["newName"]);
+                let symbol = match symbol_at(&cst, position) {
+                    Some(symbol) => symbol,
+                    None => panic!("Only declared names can be renamed"),
+                };
+                // Rejects keywords, names already in use and imported names
+                cst.clone().rename(&symbol, &new);
+
+                let edits = symbol
+                    .occurrences()
+                    .map(|occurrence| {
+                        json::object(vec![
+                            (
+                                "range",
+                                range(text, start(&cst, occurrence), symbol.name.len()),
+                            ),
+                            ("newText", Json::String(new.clone())),
+                        ])
+                    })
+                    .collect();
+                json::object(vec![(
+                    "changes",
+                    json::object(vec![(uri, Json::Array(edits))]),
+                )])
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// The URI, text and cursor position of the document a request is about.
+    fn document<'a>(&'a self, params: &'a Json) -> (&'a str, &'a str, Position) {
+        let uri = match params.get("textDocument").and_then(|d| d.get("uri")) {
+            Some(Json::String(uri)) => uri,
+            _ => panic!("Expected textDocument.uri"),
+        };
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => panic!("{} is not open", uri),
+        };
+        let position = match params.get("position") {
+            Some(position) => from_lsp(text, position),
+            None => panic!("Expected position"),
+        };
+        (uri, text, position)
+    }
+
+    /// Checks the document at `uri`, sending the client its diagnostics.
+    fn publish(&self, uri: &str) {
+        let text = &self.documents[uri];
+        let found = check(&path(uri), text).map(|(position, length, message)| {
+            json::object(vec![
+                ("range", range(text, position, length)),
+                // An error
+                ("severity", Json::Number("1".to_string())),
+                ("source", Json::String("zest".to_string())),
+                ("message", Json::String(message)),
+            ])
+        });
+        notify(
+            "textDocument/publishDiagnostics",
+            diagnostics(uri, found.into_iter().collect()),
+        );
+    }
+}
+
+fn capabilities() -> Json {
+    let enabled = Json::Bool(true);
+    json::object(vec![
+        (
+            "capabilities",
+            json::object(vec![
+                // Whole documents are sent on every change
+                ("textDocumentSync", Json::Number("1".to_string())),
+                (
+                    "completionProvider",
+                    json::object(vec![(
+                        "triggerCharacters",
+                        Json::Array(vec![Json::String(".".to_string())]),
+                    )]),
+                ),
+                ("hoverProvider", enabled.clone()),
+                ("definitionProvider", enabled.clone()),
+                ("referencesProvider", enabled.clone()),
+                ("renameProvider", enabled),
+            ]),
+        ),
+        (
+            "serverInfo",
+            json::object(vec![("name", Json::String("zest".to_string()))]),
+        ),
+    ])
+}
+
+/// The first error in `text`, read as the Zest file `file`, with the number
+/// of characters it spans. Files without scenes are libraries for others
+/// to import, so they are only parsed.
+///
+/// Scenes are constructed and transpiled in full on every change, which is
+/// what finds most errors, and is quick enough for files of the examples'
+/// size. Imports are read from disk, so unsaved changes to an imported file
+/// are only seen once it is saved.
+fn check(file: &str, text: &str) -> Option<(Position, usize, String)> {
+    let cst = match panic::catch_unwind(|| Cst::parse(file, text)) {
+        Ok(cst) => cst,
+        Err(panic) => {
+            let message = panic_message(panic);
+            return Some(match locate(file, &message) {
+                Some((position, message)) => (position, 1, message),
+                None => ((1, 1), 0, message),
+            });
+        }
+    };
+    if let Some(position) = cst.unknown() {
+        return Some((position, 1, "Unexpected character".to_string()));
+    }
+
+    let panic = panic::catch_unwind(|| {
+        let mut constructor = Constructor::new(file.to_string(), text.to_string());
+        constructor.parse();
+        if constructor.engine.scenes.is_empty() {
+            return;
+        }
+
+        let mut constructor = Constructor::new(file.to_string(), text.to_string());
+        constructor.construct();
+        evaluator::Evaluator::new().fold_engine(&mut constructor.engine);
+        transpiler::Transpiler::new(constructor.engine).transpile();
+    })
+    .err()?;
+    let message = panic_message(panic);
+    match locate(file, &message) {
+        Some((position, message)) => {
+            let length = match cst.at(position) {
+                Some(i) => cst.elements[i]
+                    .text
+                    .lines()
+                    .next()
+                    .unwrap_or("")
+                    .chars()
+                    .count(),
+                None => 0,
+            };
+            Some((position, length, message))
+        }
+        None => Some(((1, 1), 0, message)),
+    }
+}
+
+/// The position and the rest of an error `message` in `file`. Errors in this
+/// file start with where they are, and others are the whole file's.
+fn locate(file: &str, message: &str) -> Option<(Position, String)> {
+    let rest = message.strip_prefix(&format!("{}:", file))?;
+    let mut parts = rest.splitn(3, ':');
+    let line = parts.next()?.parse().ok()?;
+    let column = parts.next()?.parse().ok()?;
+    Some(((line, column), parts.next()?.trim_start().to_string()))
+}
+
+/// Property names after a `.` starting a property, or else the object type
+/// keywords.
+fn completion(cst: &Cst, position: Position) -> Json {
+    let mut before = cst
+        .elements
+        .partition_point(|element| element.position < position);
+    // Skip the name being typed, though not the `.` just typed before it
+    if before > 0
+        && cst.at(position) == Some(before - 1)
+        && matches!(cst.elements[before - 1].token, Token::Identifier(_))
+    {
+        before -= 1;
+    }
+
+    let items = match before.checked_sub(1) {
+        Some(dot) if cst.elements[dot].token == Token::Dot => {
+            match object_type(cst, dot).filter(|_| starts_property(cst, dot)) {
+                Some(obj_type) => constructor::properties(obj_type)
+                    .into_iter()
+                    .map(|(name, documentation)| item(name, PROPERTY, Some(documentation)))
+                    .collect(),
+                None => Vec::new(),
+            }
+        }
+        _ => constructor::OBJECT_TYPES
+            .iter()
+            .map(|keyword| item(keyword, KEYWORD, None))
+            .collect(),
+    };
+    Json::Array(items)
+}
+
+fn item(label: &str, kind: usize, documentation: Option<&str>) -> Json {
+    let mut item = vec![
+        ("label", Json::String(label.to_string())),
+        ("kind", Json::Number(kind.to_string())),
+    ];
+    if let Some(documentation) = documentation {
+        item.push(("documentation", markdown(documentation.to_string())));
+    }
+    json::object(item)
+}
+
+/// The documentation of the property name under the cursor.
+fn hover(text: &str, cst: &Cst, position: Position) -> Option<Json> {
+    let index = cst.at(position)?;
+    let name = match &cst.elements[index].token {
+        Token::Identifier(name) => name,
+        _ => return None,
+    };
+    let dot = index.checked_sub(1)?;
+    if cst.elements[dot].token != Token::Dot || !starts_property(cst, dot) {
+        return None;
+    }
+
+    let obj_type = object_type(cst, dot)?;
+    let (_, documentation) = constructor::properties(obj_type)
+        .into_iter()
+        .find(|(property, _)| property == name)?;
+    Some(json::object(vec![
+        (
+            "contents",
+            markdown(format!(
+                "`{}` `.{}`\n\n{}",
+                obj_type.keyword(),
+                name,
+                documentation
+            )),
+        ),
+        (
+            "range",
+            range(text, cst.elements[index].position, name.len()),
+        ),
+    ]))
+}
+
+fn markdown(value: String) -> Json {
+    json::object(vec![
+        ("kind", Json::String("markdown".to_string())),
+        ("value", Json::String(value)),
+    ])
+}
+
+/// Whether the `.` at `dot` starts a property, rather than being a member
+/// access such as `ball.position`.
+fn starts_property(cst: &Cst, dot: usize) -> bool {
+    let position = cst.elements[dot].position;
+    match dot.checked_sub(1).map(|i| &cst.elements[i]) {
+        Some(previous) => {
+            matches!(previous.token, Token::LBrace | Token::RBrace | Token::Comma)
+                || previous.position.0 < position.0
+        }
+        None => false,
+    }
+}
+
+/// The type of the object whose body `index` is in, looking out of any IF
+/// blocks.
+fn object_type(cst: &Cst, index: usize) -> Option<ObjectType> {
+    let mut depth = 0;
+    for i in (0..index).rev() {
+        match cst.elements[i].token {
+            Token::RBrace => depth += 1,
+            Token::LBrace if depth > 0 => depth -= 1,
+            Token::LBrace => match header(cst, i) {
+                Some(Token::Identifier(keyword)) if keyword == "IF" || keyword == "ELSE" => {}
+                Some(Token::Identifier(keyword)) => return ObjectType::from_keyword(keyword),
+                _ => return None,
+            },
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The first token of the header of the block opened at `brace`, such as the
+/// `SPHERE` of `SPHERE ball {` or the `ELSE` of `} ELSE IF debug {`.
+fn header(cst: &Cst, brace: usize) -> Option<&Token> {
+    let line = cst.elements[brace].position.0;
+    let mut depth = 0;
+    let mut first = None;
+    for element in cst.elements[..brace].iter().rev() {
+        match element.token {
+            _ if element.position.0 != line => break,
+            Token::LBrace | Token::RBrace => break,
+            Token::Comma if depth == 0 => break,
+            Token::RParen | Token::RBracket => depth += 1,
+            Token::LParen | Token::LBracket => depth -= 1,
+            _ => {}
+        }
+        first = Some(&element.token);
+    }
+    first
+}
+
+/// The declared name under the cursor, such as a constant, an object or the
+/// variable of a `{name}` placeholder.
+fn symbol_at(cst: &Cst, position: Position) -> Option<Symbol> {
+    let index = cst.at(position)?;
+    scope::at(cst, index, position.1 - cst.elements[index].position.1)
+}
+
+/// The name under the cursor: the variable of a `{name}` placeholder, or a
+/// whole name other than a property's.
+fn name_at(cst: &Cst, position: Position) -> Option<String> {
+    let index = cst.at(position)?;
+    let element = &cst.elements[index];
+    let name = match &element.token {
+        Token::Identifier(name) => name,
+        _ => return None,
+    };
+    if index > 0 && cst.elements[index - 1].token == Token::Dot {
+        return None;
+    }
+
+    let offset = position.1 - element.position.1;
+    for (open, _) in name.match_indices('{') {
+        let close = open + name[open..].find('}')?;
+        if open < offset && offset <= close {
+            return Some(name[open + 1..close].to_string());
+        }
+    }
+    Some(name.clone())
+}
+
+/// The indices of the declarations of names generated in loops that `name`
+/// could be one of, such as `ball_{i}` for `ball_2`.
+fn generators(cst: &Cst, name: &str) -> Vec<usize> {
+    (0..cst.elements.len())
+        .filter(|&i| match &cst.elements[i].token {
+            Token::Identifier(generated) => scope::generates(generated, name) && declares(cst, i),
+            _ => false,
+        })
+        .collect()
+}
+
+/// Whether the name at `index` is declared there: after a keyword declaring
+/// one, or as the name of a prefab instance.
+fn declares(cst: &Cst, index: usize) -> bool {
+    let previous = match index.checked_sub(1).map(|i| &cst.elements[i].token) {
+        Some(Token::Identifier(previous)) => previous,
+        _ => return false,
+    };
+    match previous.as_str() {
+        "SCENE" | "CONST" | "PREFAB" | "FOR" => true,
+        keyword if constructor::is_keyword(keyword) => ObjectType::from_keyword(keyword).is_some(),
+        _ => match cst.elements.get(index + 1).map(|next| &next.token) {
+            Some(Token::LBrace) => true,
+            Some(Token::Identifier(next)) => next == "EXTENDS",
+            _ => false,
+        },
+    }
+}
+
+/// Where the occurrence at `(index, column)` starts.
+fn start(cst: &Cst, (index, column): (usize, usize)) -> Position {
+    let (line, start) = cst.elements[index].position;
+    (line, start + column)
+}
+
+/// The location of the `length` characters from `start` in the document
+/// at `uri`.
+fn location(uri: &str, text: &str, start: Position, length: usize) -> Json {
+    json::object(vec![
+        ("uri", Json::String(uri.to_string())),
+        ("range", range(text, start, length)),
+    ])
+}
+
+/// The 1-based position of the LSP position `json`, whose character offset
+/// counts UTF-16 code units.
+fn from_lsp(text: &str, json: &Json) -> Position {
+    let line = number(json, "line");
+    let character = number(json, "character");
+    let mut units = 0;
+    let column = text
+        .split('\n')
+        .nth(line)
+        .unwrap_or("")
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count();
+    (line + 1, column + 1)
+}
+
+fn to_lsp(text: &str, (line, column): Position) -> Json {
+    let character: usize = text
+        .split('\n')
+        .nth(line - 1)
+        .unwrap_or("")
+        .chars()
+        .take(column - 1)
+        .map(char::len_utf16)
+        .sum();
+    json::object(vec![
+        ("line", Json::Number((line - 1).to_string())),
+        ("character", Json::Number(character.to_string())),
+    ])
+}
+
+/// The LSP range of the `length` characters from `start`, on one line.
+fn range(text: &str, start: Position, length: usize) -> Json {
+    json::object(vec![
+        ("start", to_lsp(text, start)),
+        ("end", to_lsp(text, (start.0, start.1 + length))),
+    ])
+}
+
+fn diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json::object(vec![
+        ("uri", Json::String(uri.to_string())),
+        ("diagnostics", Json::Array(diagnostics)),
+    ])
+}
+
+/// The file path of a `file://` URI, which imports are found relative to.
+fn path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < path.len() {
+        let escape = std::str::from_utf8(path.get(i + 1..i + 3).unwrap_or_default())
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) if path[i] == b'%' => {
+                bytes.push(byte);
+                i += 3;
+            }
+            _ => {
+                bytes.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// The string at `path` in `json`.
+fn string(json: &Json, path: &[&str]) -> String {
+    match path.iter().try_fold(json, |json, key| json.get(key)) {
+        Some(Json::String(string)) => string.clone(),
+        _ => panic!("Expected a string at {}", path.join(".")),
+    }
+}
+
+fn number(json: &Json, key: &str) -> usize {
+    match json.get(key) {
+        Some(Json::Number(number)) => match number.parse() {
+            Ok(number) => number,
+            Err(_) => panic!("Expected a whole number for {}, got {}", key, number),
+        },
+        _ => panic!("Expected a number for {}", key),
+    }
+}
+
+/// The response to the request `id`, with its result or error.
+fn response(id: Json, outcome: (&str, Json)) -> Json {
+    json::object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("id", id),
+        outcome,
+    ])
+}
+
+fn error(code: i64, message: String) -> Json {
+    json::object(vec![
+        ("code", Json::Number(code.to_string())),
+        ("message", Json::String(message)),
+    ])
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "Internal error".to_string(),
+        },
+    }
+}
+
+/// Reads the body of the next message, or `None` at the end of the input.
+fn read(input: &mut impl BufRead) -> Option<String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        match header.trim_end().split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("Content-Length") => {
+                length = value.trim().parse().ok()
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    Some(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// The message in `body`, or the error response to send if it is not JSON.
+fn parse(body: &str) -> Result<Json, Json> {
+    panic::catch_unwind(|| Json::parse("message", body)).map_err(|panic| {
+        // The id of a message that cannot be read is unknown, so it is null
+        response(
+            Json::Null,
+            ("error", error(PARSE_ERROR, panic_message(panic))),
+        )
+    })
+}
+
+fn notify(method: &str, params: Json) {
+    send(json::object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("method", Json::String(method.to_string())),
+        ("params", params),
+    ]));
+}
+
+fn send(message: Json) {
+    write(&mut std::io::stdout().lock(), &message).expect("Could not write message");
+}
+
+/// Writes `message` behind the header giving its length in bytes.
+fn write(output: &mut impl Write, message: &Json) -> std::io::Result<()> {
+    let body = message.pretty();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BALL: &str =
+        "SCENE main {\n    SPHERE ball {\n        .radius = 1 / 0,\n    }\n}\n\nSTART main\n";
+
+    /// A server with `text` open as the document `file:///test.zest`.
+    fn server(text: &str) -> Server {
+        let mut server = Server {
+            documents: HashMap::new(),
+            shutdown: false,
+        };
+        server
+            .documents
+            .insert("file:///test.zest".to_string(), text.to_string());
+        server
+    }
+
+    /// The parameters of a request at the zero based `line` and `character`.
+    fn at(line: usize, character: usize, extra: &str) -> Json {
+        Json::parse(
+            "params",
+            &format!(
+                r#"{{"textDocument": {{"uri": "file:///test.zest"}},
+                    "position": {{"line": {}, "character": {}}}{}}}"#,
+                line, character, extra
+            ),
+        )
+    }
+
+    fn labels(items: &Json) -> Vec<String> {
+        match items {
+            Json::Array(items) => items.iter().map(|item| string(item, &["label"])).collect(),
+            _ => panic!("Expected completion items"),
+        }
+    }
+
+    #[test]
+    fn messages_are_framed() {
+        let message = json::object(vec![
+            ("jsonrpc", Json::String("2.0".to_string())),
+            ("method", Json::String("initialized".to_string())),
+        ]);
+        let mut output = Vec::new();
+        write(&mut output, &message).unwrap();
+        write(&mut output, &message).unwrap();
+        let text = String::from_utf8(output.clone()).unwrap();
+        assert!(text.starts_with(&format!(
+            "Content-Length: {}\r\n\r\n{{",
+            message.pretty().len()
+        )));
+
+        let mut input = Cursor::new(output);
+        assert_eq!(read(&mut input), Some(message.pretty()));
+        assert_eq!(read(&mut input), Some(message.pretty()));
+        assert_eq!(read(&mut input), None);
+    }
+
+    #[test]
+    fn other_headers_are_ignored() {
+        let body = "{\"id\": 1, \"method\": \"shutdown\"}";
+        let input = format!(
+            "content-length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let message = parse(&read(&mut Cursor::new(input)).unwrap()).unwrap();
+        assert_eq!(string(&message, &["method"]), "shutdown");
+    }
+
+    #[test]
+    fn malformed_messages_are_answered() {
+        let response = parse("{\"id\": 1, \"method\": ").unwrap_err();
+        assert_eq!(response.get("id"), Some(&Json::Null));
+        let error = response.get("error").unwrap();
+        assert_eq!(
+            error.get("code"),
+            Some(&Json::Number(PARSE_ERROR.to_string()))
+        );
+        assert!(matches!(error.get("message"), Some(Json::String(_))));
+    }
+
+    #[test]
+    fn examples_have_no_diagnostics() {
+        for directory in ["examples", "examples/lib"] {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_none_or(|extension| extension != "zest") {
+                    continue;
+                }
+                let file = path.to_string_lossy().to_string();
+                let text = std::fs::read_to_string(&path).unwrap();
+                assert_eq!(check(&file, &text), None, "{}", file);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_are_diagnosed() {
+        assert_eq!(
+            check("test.zest", BALL),
+            Some(((3, 9), 1, "Division by zero".to_string()))
+        );
+        assert_eq!(
+            check("test.zest", "SCENE main {\n    $\n}\n"),
+            Some(((2, 5), 1, "Unexpected character".to_string()))
+        );
+        assert_eq!(
+            check("test.zest", "WINDOW window {\n    .title = \"Demo,\n}\n"),
+            Some(((2, 14), 1, "Unterminated string".to_string()))
+        );
+    }
+
+    #[test]
+    fn properties_are_completed() {
+        let text = "SCENE main {\n    SPHERE ball {\n        .\n    }\n}\n";
+        let mut server = server(text);
+        let properties = labels(
+            &server
+                .handle("textDocument/completion", &at(2, 9, ""))
+                .unwrap(),
+        );
+        assert!(properties.contains(&"radius".to_string()));
+        assert!(properties.contains(&"mass".to_string()));
+        assert!(!properties.contains(&"intensity".to_string()));
+
+        let keywords = labels(
+            &server
+                .handle("textDocument/completion", &at(1, 4, ""))
+                .unwrap(),
+        );
+        assert!(keywords.contains(&"SPHERE".to_string()));
+
+        // Part way through a name
+        let mut server = self::server(&text.replace(".\n", ".rad\n"));
+        let typing = labels(
+            &server
+                .handle("textDocument/completion", &at(2, 12, ""))
+                .unwrap(),
+        );
+        assert_eq!(typing, properties);
+    }
+
+    #[test]
+    fn properties_are_documented() {
+        let mut server = server(BALL);
+        let hover = server.handle("textDocument/hover", &at(2, 10, "")).unwrap();
+        assert_eq!(
+            string(&hover, &["contents", "value"]),
+            "`SPHERE` `.radius`\n\nThe radius of the sphere."
+        );
+    }
+
+    #[test]
+    fn names_are_renamed() {
+        let text = std::fs::read_to_string("examples/example_02.zest").unwrap();
+        let mut server = server(&text);
+        // The `mat` of `MATERIAL mat {`
+        let params = at(6, 14, r#", "newName": "red""#);
+        let rename = server.handle("textDocument/rename", &params).unwrap();
+
+        let edits = match rename
+            .get("changes")
+            .and_then(|c| c.get("file:///test.zest"))
+        {
+            Some(Json::Array(edits)) => edits,
+            _ => panic!("Expected edits to the document"),
+        };
+        let starts: Vec<(usize, usize)> = edits
+            .iter()
+            .map(|edit| {
+                let start = edit.get("range").and_then(|r| r.get("start")).unwrap();
+                (number(start, "line"), number(start, "character"))
+            })
+            .collect();
+        assert_eq!(starts, vec![(6, 13), (13, 20)]);
+        assert_eq!(string(&edits[0], &["newText"]), "red");
+    }
+
+    #[test]
+    #[should_panic(expected = "is already")]
+    fn renames_keep_names_unique() {
+        let text = std::fs::read_to_string("examples/example_02.zest").unwrap();
+        let mut server = server(&text);
+        server.handle(
+            "textDocument/rename",
+            &at(6, 14, r#", "newName": "sphere""#),
+        );
+    }
+}
@@ -1,36 +1,309 @@
 mod constructor;
+mod cst;
+mod dump;
+mod evaluator;
+mod formatter;
+mod geometry;
+mod gltf;
+mod image;
+mod importer;
+mod json;
+mod lsp;
+mod obj;
+mod plan;
+mod renderer;
+mod scope;
 mod tokeniser;
 mod transpiler;
 
-use std::{collections::VecDeque, io::Write};
+use std::io::Write;
 
-const USAGE: &str = "\nUsage: zest <input file> [output file]\n";
+const USAGE: &str = "
+Usage: zest [-D name=value]... <input file> [output file]
+       zest dump [-D name=value]... <input file> [--format json] [-o file]
+       zest render [-D name=value]... <input file> [-o image] [--scene name] [--size pixels]
+       zest preview [-D name=value]... <input file> [--scene name] [--size columns]
+       zest plan [-D name=value]... <input file> [-o svg] [--scene name] [--size pixels] [--plane axes]
+       zest export [-D name=value]... <input file> [--format gltf|obj] [-o model] [--scene name] [--segments count]
+       zest import-zig <zig file> [-o file]
+       zest fmt [--check] <input file>...
+       zest lsp [--stdio]
+
+The input file is Zest, or JSON written by `zest dump` if it ends in `.json`.
+
+Commands:
+    dump             Write the scenes as versioned JSON, to standard output by default.
+    render           Ray-trace a scene into a PPM or PNG image, without Zig or Z3D.
+    preview          Ray-trace a scene into the terminal, using ANSI truecolour.
+    plan             Draw a labelled SVG map of a scene, projected onto a plane.
+    export           Write a scene as a glTF 2.0 model, a `.gltf` file beside its `.bin` buffer,
+                     or as a Wavefront `.obj` beside its `.mtl` materials.
+    import-zig       Convert Zig written against Z3D back into Zest, to standard output by
+                     default, reporting any Zig that could not be translated.
+    fmt              Rewrite Zest files in the canonical layout, keeping their comments.
+    lsp              Serve the Language Server Protocol to an editor over standard input
+                     and output, with diagnostics, completion, hover, go to definition,
+                     find references and rename. `--stdio` is accepted and implied.
+
+Options:
+    -D name=value    Define a compile-time constant, overriding any CONST of the same name.
+                     `-D name` is short for `-D name=true`.
+    -o file          The file to write. Renders are PNG if it ends in `.png` and PPM
+                     otherwise, defaulting to preview.ppm. Plans default to plan.svg, and
+                     exports to out.gltf or out.obj.
+    --scene name     The scene to render. Defaults to the start scene.
+    --size pixels    The width and height of the image. Defaults to the WINDOW width, or
+                     for previews the terminal width, and for plans 800.
+    --plane axes     The axes along and up the plan. Defaults to xz, looking down.
+    --format format  The format to dump, only json for now, or to export, gltf or obj.
+                     Exports default to the extension of -o, or else gltf.
+    --segments count The number of segments around exported spheres. Defaults to 32.
+    --check          List the files fmt would change, failing if there are any, without
+                     writing them.
+";
 
 pub fn main() {
-    let in_file = std::env::args().nth(1).expect(USAGE);
-    let content = std::fs::read_to_string(&in_file).expect("Could not read file");
+    let mut args = std::env::args().skip(1).peekable();
+    let command = match args.peek().map(|arg| arg.as_str()) {
+        Some("render" | "preview" | "plan" | "export" | "dump" | "import-zig" | "fmt" | "lsp") => {
+            args.next()
+        }
+        _ => None,
+    };
+
+    let mut files = Vec::new();
+    let mut defines = Vec::new();
+    let mut out_file = None;
+    let mut scene = None;
+    let mut size = None;
+    let mut plane = "xz".to_string();
+    let mut format = None;
+    let mut segments = geometry::SEGMENTS;
+    let mut check = false;
+
+    while let Some(arg) = args.next() {
+        if arg == "-D" {
+            defines.push(args.next().expect(USAGE));
+        } else if let Some(define) = arg.strip_prefix("-D") {
+            defines.push(define.to_string());
+        } else if arg == "--check" && command.as_deref() == Some("fmt") {
+            check = true;
+        } else if arg == "--stdio" && command.as_deref() == Some("lsp") {
+            // Editors pass it, and standard input and output are all there is
+        } else if command.is_some() && (arg == "-o" || arg.starts_with("--")) {
+            let value = args.next().expect(USAGE);
+            match arg.as_str() {
+                "-o" if !matches!(command.as_deref(), Some("preview" | "fmt")) => {
+                    out_file = Some(value)
+                }
+                _ if matches!(command.as_deref(), Some("import-zig" | "fmt" | "lsp")) => {
+                    panic!("Unknown option: {}\n{}", arg, USAGE)
+                }
+                "--plane" if command.as_deref() == Some("plan") => plane = value,
+                "--format" if matches!(command.as_deref(), Some("dump" | "export")) => {
+                    format = Some(value)
+                }
+                "--segments" if command.as_deref() == Some("export") => {
+                    match value.parse::<usize>() {
+                        Ok(count) if count >= 3 => segments = count,
+                        _ => panic!("Expected at least 3 segments, got {}", value),
+                    }
+                }
+                "--scene" if command.as_deref() != Some("dump") => scene = Some(value),
+                "--size" if !matches!(command.as_deref(), Some("dump" | "export")) => {
+                    match value.parse::<usize>() {
+                        Ok(pixels) if pixels > 0 => size = Some(pixels),
+                        _ => panic!("Expected a positive size, got {}", value),
+                    }
+                }
+                _ => panic!("Unknown option: {}\n{}", arg, USAGE),
+            }
+        } else {
+            files.push(arg);
+        }
+    }
+
+    if command.as_deref() == Some("lsp") {
+        if !files.is_empty() || !defines.is_empty() {
+            panic!("lsp takes no files or definitions\n{}", USAGE);
+        }
+        return lsp::serve();
+    }
+    let in_file = files.first().expect(USAGE);
+    // Zig input is not Zest, so it is read before any engine is constructed
+    if command.as_deref() == Some("import-zig") {
+        if !defines.is_empty() {
+            panic!("-D cannot be used with import-zig");
+        }
+        return import_zig(in_file, out_file);
+    }
+    if command.as_deref() == Some("fmt") {
+        if !defines.is_empty() {
+            panic!("-D cannot be used with fmt");
+        }
+        return fmt(&files, check);
+    }
+    let engine = engine(in_file, defines);
+
+    match command.as_deref() {
+        Some("dump") => {
+            if format.as_ref().is_some_and(|format| format != "json") {
+                panic!("Unknown format: {}", format.unwrap());
+            }
+            let json = dump::dump(&engine).pretty();
+            match out_file {
+                Some(out_file) => write(&out_file, json.as_bytes()),
+                None => print!("{}", json),
+            }
+        }
+        Some("render") => {
+            let (world, window, render) = world(engine, scene);
+            let image = renderer::render(&world, size.unwrap_or(window.width), render.fov);
+            image.save(&out_file.unwrap_or("preview.ppm".to_string()));
+        }
+        Some("preview") => {
+            // One pixel per column, two per line
+            let columns = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+            let (world, _, render) = world(engine, scene);
+            let image = renderer::render(&world, size.or(columns).unwrap_or(80), render.fov);
+            print!("{}", image.ansi());
+        }
+        Some("plan") => {
+            let axes = plan::axes(&plane);
+            let (world, _, render) = world(engine, scene);
+            let svg = plan::plan(&world, axes, size.unwrap_or(800), render.fov);
+
+            write(&out_file.unwrap_or("plan.svg".to_string()), svg.as_bytes());
+        }
+        Some("export") => {
+            let extension = out_file.as_ref().and_then(|out_file| {
+                let extension = std::path::Path::new(out_file).extension()?;
+                Some(extension.to_string_lossy().to_lowercase())
+            });
+            let format = format.or(extension).unwrap_or("gltf".to_string());
+            let out_file = out_file.unwrap_or(format!("out.{}", format));
+            // The model points to its companion file by name, from beside it
+            let companion = |extension: &str| {
+                let path = std::path::Path::new(&out_file).with_extension(extension);
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (path, name)
+            };
+
+            let (world, _, render) = world(engine, scene);
+            match format.as_str() {
+                "gltf" => {
+                    let (bin, uri) = companion("bin");
+                    let (gltf, buffer) = gltf::gltf(&world, render.fov, segments, &uri);
+                    write(&out_file, gltf.pretty().as_bytes());
+                    write(&bin.to_string_lossy(), &buffer);
+                }
+                "obj" => {
+                    let (mtl_file, mtl_name) = companion("mtl");
+                    let (obj, mtl) = obj::obj(&world, segments, &mtl_name);
+                    write(&out_file, obj.as_bytes());
+                    write(&mtl_file.to_string_lossy(), mtl.as_bytes());
+                }
+                _ => panic!("Unknown format: {}", format),
+            }
+        }
+        _ => {
+            let out_file = files.get(1).cloned().unwrap_or("out.zig".to_string());
+            let transpiler = transpiler::Transpiler::new(engine);
 
-    let out_file = std::env::args().nth(2).unwrap_or("out.zig".to_string());
+            let mut f = std::fs::File::create(out_file).expect("Could not create file");
+            f.write_all(transpiler.transpile().as_bytes())
+                .expect("Could not write to file");
+        }
+    }
+}
+
+/// Converts the Zig in `in_file` into Zest, reporting what it left out.
+fn import_zig(in_file: &str, out_file: Option<String>) {
+    let content = std::fs::read_to_string(in_file).expect("Could not read file");
+    let (zest, skipped) = importer::import(&content);
+
+    for (line, statement) in &skipped {
+        eprintln!("{}:{}: Could not translate: {}", in_file, line, statement);
+    }
+    if !skipped.is_empty() {
+        eprintln!("{} statement(s) could not be translated", skipped.len());
+    }
 
-    let mut tokeniser = tokeniser::Tokeniser::new(content);
-    let mut token = tokeniser.tokenise();
+    match out_file {
+        Some(out_file) => write(&out_file, zest.as_bytes()),
+        None => print!("{}", zest),
+    }
+}
 
-    let mut tokens = VecDeque::new();
+/// Formats `files` in place, or with `check` lists those that aren't
+/// formatted and exits with an error if there are any.
+fn fmt(files: &[String], check: bool) {
+    let mut unformatted = 0;
+    for file in files {
+        let content = std::fs::read_to_string(file).expect("Could not read file");
+        let formatted = formatter::format(file, &content);
+        if formatted == content {
+            continue;
+        }
 
-    loop {
-        match token {
-            tokeniser::Token::EoF | tokeniser::Token::Unknown => break,
-            _ => tokens.push_back(token.clone()),
+        if check {
+            eprintln!("{}: not formatted", file);
+            unformatted += 1;
+        } else {
+            write(file, formatted.as_bytes());
         }
-        token = tokeniser.tokenise();
     }
-    let mut constructor = constructor::Constructor::new(tokens);
+
+    if unformatted > 0 {
+        eprintln!("{} file(s) need formatting", unformatted);
+        std::process::exit(1);
+    }
+}
+
+fn write(file: &str, bytes: &[u8]) {
+    let mut f = std::fs::File::create(file).expect("Could not create file");
+    f.write_all(bytes).expect("Could not write to file");
+}
+
+/// Reads, constructs and folds the engine in `in_file`.
+fn engine(in_file: &str, defines: Vec<String>) -> constructor::Engine {
+    let content = std::fs::read_to_string(in_file).expect("Could not read file");
+
+    if in_file.ends_with(".json") {
+        if !defines.is_empty() {
+            panic!("-D cannot be used with JSON input, its constants are already resolved");
+        }
+        let mut engine = dump::load(in_file, &json::Json::parse(in_file, &content));
+        evaluator::Evaluator::new().fold_engine(&mut engine);
+        return engine;
+    }
+
+    let mut constructor = constructor::Constructor::new(in_file.to_string(), content);
+    for define in defines {
+        let (name, value) = define.split_once('=').unwrap_or((&define, "true"));
+        let value = constructor::Constructor::new(format!("-D{}", name), value.to_string()).definition();
+        constructor.define(name.to_string(), value);
+    }
     constructor.construct();
     //constructor.print();
 
-    let transpiler = transpiler::Transpiler::new(constructor.engine);
+    evaluator::Evaluator::new().fold_engine(&mut constructor.engine);
+    constructor.engine
+}
+
+/// The geometry of `scene`, or the start scene, with the window and render
+/// settings.
+fn world(
+    engine: constructor::Engine,
+    scene: Option<String>,
+) -> (geometry::World, transpiler::Window, transpiler::Render) {
+    let scene = scene.unwrap_or(engine.start.clone());
+    let transpiler = transpiler::Transpiler::new(engine);
+    let (window, render) = transpiler.settings();
+    let scene = match transpiler.engine.scenes.iter().find(|s| s.name == scene) {
+        Some(scene) => scene,
+        None => panic!("Unknown scene: {}", scene),
+    };
 
-    let mut f = std::fs::File::create(out_file).expect("Could not create file");
-    f.write_all(transpiler.transpile().as_bytes())
-        .expect("Could not write to file");
+    (geometry::World::new(scene), window, render)
 }
@@ -0,0 +1,138 @@
+use crate::evaluator::format_number;
+use crate::geometry::{Mesh, Vector, World};
+
+/// The world's spheres and rectangles as a Wavefront OBJ, and the MTL it
+/// loads from `mtl` with a material for each Zest MATERIAL they use. Spheres
+/// are tessellated with `segments` slices.
+pub fn obj(world: &World, segments: usize, mtl: &str) -> (String, String) {
+    let mut obj = format!("# Exported by zest\nmtllib {}\n", mtl);
+    let mut materials: Vec<(String, Vector)> = Vec::new();
+    // OBJ indices count from 1, across the whole file
+    let mut offset = 1;
+
+    let spheres = world
+        .spheres
+        .iter()
+        .map(|s| (&s.name, &s.material, s.color, s.mesh(segments)));
+    let rectangles = world
+        .rectangles
+        .iter()
+        .map(|r| (&r.name, &r.material, r.color, r.mesh()));
+    for (name, material, color, mesh) in spheres.chain(rectangles) {
+        let material = material.clone().unwrap_or("default".to_string());
+        if !materials.iter().any(|(m, _)| *m == material) {
+            materials.push((material.clone(), color));
+        }
+
+        obj.push_str(&format!("\no {}\nusemtl {}\n", name, material));
+        let vertices = mesh.positions.len();
+        obj.push_str(&mesh_lines(mesh.right_handed(), offset));
+        offset += vertices;
+    }
+
+    let mut mtl = "# Exported by zest\n".to_string();
+    for (name, color) in materials {
+        let [r, g, b] = color.map(number);
+        mtl.push_str(&format!(
+            "\nnewmtl {}\nKa 0 0 0\nKd {} {} {}\nKs 0 0 0\nd 1\nillum 1\n",
+            name, r, g, b
+        ));
+    }
+
+    (obj, mtl)
+}
+
+fn mesh_lines(mesh: Mesh, offset: usize) -> String {
+    let mut lines = String::new();
+    for v in &mesh.positions {
+        let [x, y, z] = v.map(number);
+        lines.push_str(&format!("v {} {} {}\n", x, y, z));
+    }
+    for n in &mesh.normals {
+        let [x, y, z] = n.map(number);
+        lines.push_str(&format!("vn {} {} {}\n", x, y, z));
+    }
+    // Normals are indexed like the positions
+    for triangle in mesh.indices.chunks(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize + offset);
+        lines.push_str(&format!("f {a}//{a} {b}//{b} {c}//{c}\n"));
+    }
+    lines
+}
+
+/// `value` to six decimals, which is plenty for a model and hides float noise.
+fn number(value: f64) -> String {
+    format_number((value * 1e6).round() / 1e6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructor::Constructor;
+    use crate::evaluator::Evaluator;
+
+    const SCENE: &str = "SCENE s {
+    MATERIAL red {
+        .color = (255, 0, 0),
+    }
+    SPHERE ball {
+        .position = (0, 1, 0),
+        .radius   = 1,
+        .material = red,
+    }
+    RECTANGLE floor {
+        .v0 = (-5, 0, -5),
+        .v1 = (5, 0, 5),
+    }
+}
+";
+
+    /// The OBJ and MTL of the only scene of `text`.
+    fn export(text: &str, segments: usize) -> (String, String) {
+        let mut constructor = Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        Evaluator::new().fold_engine(&mut constructor.engine);
+        obj(
+            &World::new(&constructor.engine.scenes[0]),
+            segments,
+            "scene.mtl",
+        )
+    }
+
+    /// The number of lines of `obj` starting with `kind` and a space.
+    fn count(obj: &str, kind: &str) -> usize {
+        obj.lines()
+            .filter(|line| line.starts_with(&format!("{} ", kind)))
+            .count()
+    }
+
+    #[test]
+    fn segments_set_the_sphere_resolution() {
+        // Rings of segments + 1 vertices from pole to pole, and a triangle fan
+        // at each pole, plus the rectangle's 4 vertices and 2 triangles
+        for (segments, vertices, faces) in [(8, 5 * 9, 8 * 6), (16, 9 * 17, 16 * 14)] {
+            let (obj, _) = export(SCENE, segments);
+            assert_eq!(count(&obj, "v"), vertices + 4);
+            assert_eq!(count(&obj, "vn"), vertices + 4);
+            assert_eq!(count(&obj, "f"), faces + 2);
+        }
+    }
+
+    #[test]
+    fn faces_index_across_the_file() {
+        let (obj, _) = export(SCENE, 8);
+        assert!(obj.starts_with("# Exported by zest\nmtllib scene.mtl\n\no ball\nusemtl red\n"));
+        assert!(obj.contains("\no floor\nusemtl default\n"));
+        // The rectangle's vertices follow the sphere's 45, wound the other way
+        // round once mirrored into right-handed coordinates
+        assert!(obj.ends_with("f 46//46 48//48 47//47\nf 46//46 49//49 48//48\n"));
+    }
+
+    #[test]
+    fn materials_are_written_to_the_mtl() {
+        let (_, mtl) = export(SCENE, 8);
+        assert_eq!(mtl.matches("newmtl").count(), 2);
+        assert!(mtl.contains("\nnewmtl red\nKa 0 0 0\nKd 1 0 0\n"));
+        assert!(mtl.contains("\nnewmtl default\nKa 0 0 0\nKd 0.8 0.8 0.8\n"));
+    }
+}
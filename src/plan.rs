@@ -0,0 +1,351 @@
+use crate::geometry::{Vector, World};
+
+// Room around the drawing for the grid labels, in pixels
+const MARGIN: f64 = 40.0;
+// The length of camera arrows and fov wedges, in pixels
+const CAMERA_LENGTH: f64 = 60.0;
+
+const HEADER: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="WIDTH" height="HEIGHT" viewBox="0 0 WIDTH HEIGHT" font-family="sans-serif" font-size="12">
+  <defs>
+    <marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto">
+      <path d="M 0 0 L 10 5 L 0 10 z" fill="#1f5fa8"/>
+    </marker>
+  </defs>
+  <rect width="100%" height="100%" fill="white"/>
+"##;
+
+/// The axes named by a plane such as `xz`, as indices into a vector. The
+/// first axis runs to the right of the plan and the second up it.
+pub fn axes(plane: &str) -> [usize; 2] {
+    let axes: Vec<usize> = plane.chars().filter_map(|axis| "xyz".find(axis)).collect();
+    match axes[..] {
+        [horizontal, vertical] if plane.len() == 2 && horizontal != vertical => {
+            [horizontal, vertical]
+        }
+        _ => panic!("Expected a plane like xz, xy or zy, got {}", plane),
+    }
+}
+
+/// Projects `world` onto the plane of `axes` as an SVG about `size` pixels
+/// across, with a labelled grid underneath.
+pub fn plan(world: &World, axes: [usize; 2], size: usize, fov: f64) -> String {
+    let project = |v: Vector| (v[axes[0]], v[axes[1]]);
+
+    let mut points = Vec::new();
+    for sphere in &world.spheres {
+        let (u, v) = project(sphere.center);
+        points.push((u - sphere.radius, v - sphere.radius));
+        points.push((u + sphere.radius, v + sphere.radius));
+    }
+    for rectangle in &world.rectangles {
+        points.extend(rectangle.corners.map(project));
+    }
+    points.extend(world.lights.iter().map(|light| project(light.position)));
+    points.extend(world.cameras.iter().map(|camera| project(camera.position)));
+
+    // The bounds of the scene, padded by a unit and snapped out to the grid
+    let mut min = (-1.0, -1.0);
+    let mut max = (1.0, 1.0);
+    if !points.is_empty() {
+        min = points
+            .iter()
+            .fold((f64::MAX, f64::MAX), |m, p| (m.0.min(p.0), m.1.min(p.1)));
+        max = points
+            .iter()
+            .fold((f64::MIN, f64::MIN), |m, p| (m.0.max(p.0), m.1.max(p.1)));
+        min = (min.0 - 1.0, min.1 - 1.0);
+        max = (max.0 + 1.0, max.1 + 1.0);
+    }
+    let step = grid_step((max.0 - min.0).max(max.1 - min.1));
+    min = ((min.0 / step).floor() * step, (min.1 / step).floor() * step);
+    max = ((max.0 / step).ceil() * step, (max.1 / step).ceil() * step);
+
+    let scale = (size as f64 - 2.0 * MARGIN).max(1.0) / (max.0 - min.0).max(max.1 - min.1);
+    let width = (max.0 - min.0) * scale + 2.0 * MARGIN;
+    let height = (max.1 - min.1) * scale + 2.0 * MARGIN;
+    let to_svg = |(u, v): (f64, f64)| (MARGIN + (u - min.0) * scale, MARGIN + (max.1 - v) * scale);
+
+    let mut output = HEADER
+        .replace("WIDTH", &format!("{:.0}", width))
+        .replace("HEIGHT", &format!("{:.0}", height));
+
+    output.push_str("  <g stroke=\"#e0e0e0\">\n");
+    let columns = ((max.0 - min.0) / step).round() as usize;
+    let rows = ((max.1 - min.1) / step).round() as usize;
+    for i in 0..=columns {
+        let x = MARGIN + i as f64 * step * scale;
+        output.push_str(&format!(
+            "    <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/>\n",
+            x,
+            MARGIN,
+            x,
+            height - MARGIN
+        ));
+    }
+    for i in 0..=rows {
+        let y = MARGIN + i as f64 * step * scale;
+        output.push_str(&format!(
+            "    <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/>\n",
+            MARGIN,
+            y,
+            width - MARGIN,
+            y
+        ));
+    }
+    output.push_str("  </g>\n");
+
+    output.push_str("  <g fill=\"#808080\" font-size=\"10\">\n");
+    for i in 0..=columns {
+        output.push_str(&format!(
+            "    <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+            MARGIN + i as f64 * step * scale,
+            height - MARGIN + 14.0,
+            coordinate(min.0 + i as f64 * step)
+        ));
+    }
+    for i in 0..=rows {
+        output.push_str(&format!(
+            "    <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+            MARGIN - 4.0,
+            height - MARGIN - i as f64 * step * scale + 3.0,
+            coordinate(min.1 + i as f64 * step)
+        ));
+    }
+    let names = ["x", "y", "z"];
+    output.push_str(&format!(
+        "    <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"12\">{}</text>\n",
+        width - MARGIN + 8.0,
+        height - MARGIN + 14.0,
+        names[axes[0]]
+    ));
+    output.push_str(&format!(
+        "    <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"12\" text-anchor=\"end\">{}</text>\n",
+        MARGIN - 4.0,
+        MARGIN - 10.0,
+        names[axes[1]]
+    ));
+    output.push_str("  </g>\n");
+
+    for rectangle in &world.rectangles {
+        let corners = rectangle.corners.map(|corner| to_svg(project(corner)));
+        let points: Vec<String> = corners
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+            .collect();
+        output.push_str(&format!(
+            "  <polygon points=\"{}\" fill=\"{}\" fill-opacity=\"0.4\" stroke=\"#333333\"/>\n",
+            points.join(" "),
+            hex(rectangle.color)
+        ));
+        let center = corners
+            .iter()
+            .fold((0.0, 0.0), |c, p| (c.0 + p.0 / 4.0, c.1 + p.1 / 4.0));
+        output.push_str(&label(center, &rectangle.name, "middle"));
+    }
+
+    for sphere in &world.spheres {
+        let center = to_svg(project(sphere.center));
+        output.push_str(&format!(
+            "  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\" fill=\"{}\" fill-opacity=\"0.6\" stroke=\"#333333\"/>\n",
+            center.0,
+            center.1,
+            sphere.radius * scale,
+            hex(sphere.color)
+        ));
+        output.push_str(&label(center, &sphere.name, "middle"));
+    }
+
+    for light in &world.lights {
+        let center = to_svg(project(light.position));
+        output.push_str(&format!(
+            "  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"5\" fill=\"#ffcc00\" stroke=\"#996600\"/>\n",
+            center.0, center.1
+        ));
+        output.push_str(&label(
+            (center.0 + 8.0, center.1 - 6.0),
+            &light.name,
+            "start",
+        ));
+    }
+
+    let half_fov = fov.to_radians() / 2.0;
+    for camera in &world.cameras {
+        let center = to_svg(project(camera.position));
+
+        // The direction on screen, where y runs down
+        let (u, v) = project(camera.direction);
+        let length = (u * u + v * v).sqrt();
+        if length > 1e-9 {
+            let (dx, dy) = (u / length, -v / length);
+            let edge = |angle: f64| {
+                let (sin, cos) = angle.sin_cos();
+                (
+                    center.0 + (dx * cos - dy * sin) * CAMERA_LENGTH,
+                    center.1 + (dx * sin + dy * cos) * CAMERA_LENGTH,
+                )
+            };
+            let (left, right) = (edge(-half_fov), edge(half_fov));
+            output.push_str(&format!(
+                "  <path d=\"M {:.1} {:.1} L {:.1} {:.1} L {:.1} {:.1} Z\" fill=\"#4a90d9\" fill-opacity=\"0.15\" stroke=\"#4a90d9\" stroke-dasharray=\"4 2\"/>\n",
+                center.0, center.1, left.0, left.1, right.0, right.1
+            ));
+            output.push_str(&format!(
+                "  <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#1f5fa8\" stroke-width=\"2\" marker-end=\"url(#arrow)\"/>\n",
+                center.0,
+                center.1,
+                center.0 + dx * CAMERA_LENGTH * 0.75,
+                center.1 + dy * CAMERA_LENGTH * 0.75
+            ));
+        }
+
+        output.push_str(&format!(
+            "  <rect x=\"{:.1}\" y=\"{:.1}\" width=\"8\" height=\"8\" fill=\"#1f5fa8\"/>\n",
+            center.0 - 4.0,
+            center.1 - 4.0
+        ));
+        let active = world
+            .camera()
+            .is_some_and(|active| active.name == camera.name);
+        let name = if active {
+            format!("{} (active)", camera.name)
+        } else {
+            camera.name.clone()
+        };
+        output.push_str(&label((center.0 + 8.0, center.1 + 14.0), &name, "start"));
+    }
+
+    output.push_str("</svg>\n");
+    output
+}
+
+/// A grid spacing of 1, 2 or 5 times a power of ten, giving about ten lines
+/// across `extent`.
+fn grid_step(extent: f64) -> f64 {
+    let rough = extent / 10.0;
+    let power = 10f64.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * power)
+        .find(|step| *step >= rough)
+        .unwrap_or(10.0 * power)
+}
+
+fn coordinate(value: f64) -> String {
+    // Hides the float noise of stepping, like 0.30000000000000004
+    let value = (value * 1e6).round() / 1e6;
+    if value == 0.0 {
+        "0".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn label((x, y): (f64, f64), name: &str, anchor: &str) -> String {
+    format!(
+        "  <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\">{}</text>\n",
+        x, y, anchor, name
+    )
+}
+
+fn hex(color: Vector) -> String {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructor::Constructor;
+    use crate::evaluator::Evaluator;
+
+    /// The geometry of the only scene of `text`.
+    fn world(text: &str) -> World {
+        let mut constructor = Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        Evaluator::new().fold_engine(&mut constructor.engine);
+        World::new(&constructor.engine.scenes[0])
+    }
+
+    #[test]
+    fn planes_name_two_axes() {
+        assert_eq!(axes("xz"), [0, 2]);
+        assert_eq!(axes("zy"), [2, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a plane like xz, xy or zy, got xx")]
+    fn planes_need_different_axes() {
+        axes("xx");
+    }
+
+    #[test]
+    fn grid_steps_are_round() {
+        assert_eq!(grid_step(4.0), 0.5);
+        assert_eq!(grid_step(12.0), 2.0);
+        assert_eq!(grid_step(100.0), 10.0);
+    }
+
+    #[test]
+    fn spheres_are_scaled_onto_the_grid() {
+        let svg = plan(
+            &world("SCENE s {\n    SPHERE ball {\n        .position = (0, 5, 0),\n        .radius   = 1,\n    }\n}\n"),
+            axes("xz"),
+            440,
+            60.0,
+        );
+        // From -2 to 2 on both axes, in steps of 0.5 drawn 45 pixels apart
+        assert!(svg
+            .starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"440\" height=\"440\""));
+        assert!(svg.contains("<circle cx=\"220.0\" cy=\"220.0\" r=\"90.0\" fill=\"#cccccc\""));
+        assert!(svg.contains(">-1.5</text>"));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn objects_are_drawn_and_labelled() {
+        let svg = plan(
+            &world(
+                "SCENE s {
+    MATERIAL red {
+        .color = (255, 0, 0),
+    }
+    SPHERE ball {
+        .position = (2, 0, 3),
+        .radius   = 1,
+        .material = red,
+    }
+    RECTANGLE floor {
+        .v0 = (-5, -1, -5),
+        .v1 = (5, -1, 5),
+    }
+    LIGHT lamp {
+        .position  = (0, 4, 0),
+        .intensity = (1, 1, 1),
+    }
+    CAMERA side {
+        .position  = (-4, 0, 0),
+        .direction = (1, 0, 0),
+    }
+    CAMERA front {
+        .position  = (0, 0, -4),
+        .direction = (0, 0, 1),
+    }
+    ACTIVE active {
+        .camera = front,
+    }
+}
+",
+            ),
+            axes("xz"),
+            400,
+            60.0,
+        );
+        assert_eq!(svg.matches("<polygon").count(), 1);
+        assert_eq!(svg.matches("<circle").count(), 2);
+        assert_eq!(svg.matches("marker-end=\"url(#arrow)\"").count(), 2);
+        assert!(svg.contains("fill=\"#ff0000\" fill-opacity=\"0.6\""));
+        for label in ["ball", "floor", "lamp", "side", "front (active)", "x", "z"] {
+            assert!(svg.contains(&format!(">{}</text>", label)), "{}", label);
+        }
+    }
+}
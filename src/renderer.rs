@@ -0,0 +1,271 @@
+use crate::geometry::{self, add, cross, dot, multiply, normalize, scale, sub, Vector, World};
+use crate::image::Image;
+
+const AMBIENT: f64 = 0.1;
+const BACKGROUND: Vector = [0.05, 0.05, 0.08];
+// Keeps rays from hitting the surface they start on
+const EPSILON: f64 = 1e-6;
+
+struct Hit {
+    distance: f64,
+    point: Vector,
+    normal: Vector,
+    color: Vector,
+}
+
+/// Ray-traces `world` from its camera into a `size` by `size` image, with
+/// diffuse shading and hard shadows.
+pub fn render(world: &World, size: usize, fov: f64) -> Image {
+    let camera = match world.camera() {
+        Some(camera) => camera,
+        None => panic!("Scene has no CAMERA to render from"),
+    };
+
+    let forward = camera.direction;
+    let up = if cross([0.0, 1.0, 0.0], forward) == [0.0, 0.0, 0.0] {
+        [0.0, 0.0, 1.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    };
+    let right = normalize(cross(up, forward));
+    let up = cross(forward, right);
+    let extent = (fov.to_radians() / 2.0).tan();
+
+    let mut image = Image::new(size, size);
+    for y in 0..size {
+        for x in 0..size {
+            let u = (2.0 * (x as f64 + 0.5) / size as f64 - 1.0) * extent;
+            let v = (1.0 - 2.0 * (y as f64 + 0.5) / size as f64) * extent;
+            let direction = normalize(add(forward, add(scale(right, u), scale(up, v))));
+
+            let color = match intersect(world, camera.position, direction) {
+                Some(hit) => shade(world, &hit, direction),
+                None => BACKGROUND,
+            };
+            image.set(
+                x,
+                y,
+                color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+            );
+        }
+    }
+
+    image
+}
+
+fn shade(world: &World, hit: &Hit, direction: Vector) -> Vector {
+    // Surfaces are lit from whichever side the camera sees
+    let normal = if dot(hit.normal, direction) > 0.0 {
+        scale(hit.normal, -1.0)
+    } else {
+        hit.normal
+    };
+    let origin = add(hit.point, scale(normal, EPSILON * 100.0));
+
+    let mut light = [AMBIENT; 3];
+    for source in &world.lights {
+        let to_light = sub(source.position, origin);
+        let distance = dot(to_light, to_light).sqrt();
+        let to_light = normalize(to_light);
+
+        let diffuse = dot(normal, to_light);
+        if diffuse <= 0.0 {
+            continue;
+        }
+        if occluded(world, origin, to_light, source.position, distance) {
+            continue;
+        }
+        light = add(light, scale(source.intensity, diffuse));
+    }
+
+    multiply(hit.color, light)
+}
+
+fn intersect(world: &World, origin: Vector, direction: Vector) -> Option<Hit> {
+    let mut closest: Option<Hit> = None;
+    let mut keep = |hit: Hit| {
+        if closest.as_ref().is_none_or(|c| hit.distance < c.distance) {
+            closest = Some(hit);
+        }
+    };
+
+    for sphere in &world.spheres {
+        if let Some(distance) = intersect_sphere(sphere, origin, direction) {
+            let point = add(origin, scale(direction, distance));
+            keep(Hit {
+                distance,
+                point,
+                normal: normalize(sub(point, sphere.center)),
+                color: sphere.color,
+            });
+        }
+    }
+
+    for rectangle in &world.rectangles {
+        let [a, b, c, d] = rectangle.corners;
+        for triangle in [[a, b, c], [a, c, d]] {
+            if let Some(distance) = intersect_triangle(triangle, origin, direction) {
+                keep(Hit {
+                    distance,
+                    point: add(origin, scale(direction, distance)),
+                    normal: normalize(cross(
+                        sub(triangle[1], triangle[0]),
+                        sub(triangle[2], triangle[0]),
+                    )),
+                    color: rectangle.color,
+                });
+            }
+        }
+    }
+
+    closest
+}
+
+/// Whether anything lies between `origin` and a light `distance` away.
+/// Spheres around the light, like lamp bulbs, don't cast shadows.
+fn occluded(
+    world: &World,
+    origin: Vector,
+    direction: Vector,
+    light: Vector,
+    distance: f64,
+) -> bool {
+    let spheres = world
+        .spheres
+        .iter()
+        .filter(|sphere| {
+            let offset = sub(light, sphere.center);
+            dot(offset, offset) > sphere.radius * sphere.radius
+        })
+        .filter_map(|sphere| intersect_sphere(sphere, origin, direction));
+    let rectangles = world.rectangles.iter().flat_map(|rectangle| {
+        let [a, b, c, d] = rectangle.corners;
+        [[a, b, c], [a, c, d]]
+            .into_iter()
+            .filter_map(move |triangle| intersect_triangle(triangle, origin, direction))
+    });
+
+    spheres.chain(rectangles).any(|t| t < distance)
+}
+
+fn intersect_sphere(sphere: &geometry::Sphere, origin: Vector, direction: Vector) -> Option<f64> {
+    let offset = sub(origin, sphere.center);
+    let b = dot(offset, direction);
+    let c = dot(offset, offset) - sphere.radius * sphere.radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    [-b - root, -b + root].into_iter().find(|t| *t > EPSILON)
+}
+
+/// Möller-Trumbore ray-triangle intersection.
+fn intersect_triangle(triangle: [Vector; 3], origin: Vector, direction: Vector) -> Option<f64> {
+    let edge1 = sub(triangle[1], triangle[0]);
+    let edge2 = sub(triangle[2], triangle[0]);
+    let p = cross(direction, edge2);
+    let determinant = dot(edge1, p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let s = sub(origin, triangle[0]);
+    let u = dot(s, p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = cross(s, edge1);
+    let v = dot(direction, q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = dot(edge2, q) * inverse;
+    (t > EPSILON).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructor::Constructor;
+    use crate::evaluator::Evaluator;
+
+    /// The geometry of the only scene of `text`.
+    fn world(text: &str) -> World {
+        let mut constructor = Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        Evaluator::new().fold_engine(&mut constructor.engine);
+        World::new(&constructor.engine.scenes[0])
+    }
+
+    #[test]
+    fn lit_sphere_on_background() {
+        let world = world(
+            "SCENE s {
+    MATERIAL red {
+        .color = (255, 0, 0),
+    }
+    SPHERE ball {
+        .position = (0, 0, 5),
+        .radius   = 1,
+        .material = red,
+    }
+    LIGHT light {
+        .position  = (0, 0, 0),
+        .intensity = (1, 1, 1),
+    }
+    CAMERA cam {
+        .position  = (0, 0, 0),
+        .direction = (0, 0, 1),
+    }
+}
+",
+        );
+        let image = render(&world, 3, 90.0);
+        // Facing the light head on, the ambient light saturates the red channel
+        assert_eq!(image.get(1, 1), [255, 0, 0]);
+        for (x, y) in [(0, 0), (2, 0), (0, 2), (2, 2)] {
+            assert_eq!(image.get(x, y), [13, 13, 20]);
+        }
+    }
+
+    #[test]
+    fn blocked_lights_leave_ambient_light() {
+        let scene = |blocker: &str| {
+            format!(
+                "SCENE s {{
+    RECTANGLE wall {{
+        .v0 = (-50, -50, 10),
+        .v1 = (50, 50, 10),
+    }}
+    {}
+    LIGHT light {{
+        .position  = (5, 0, 5),
+        .intensity = (1, 1, 1),
+    }}
+    CAMERA cam {{
+        .position  = (0, 0, 0),
+        .direction = (0, 0, 1),
+    }}
+}}
+",
+                blocker
+            )
+        };
+        let lit = render(&world(&scene("")), 1, 10.0);
+        let shadowed = render(
+            &world(&scene(
+                "SPHERE blocker { .position = (2.5, 0, 7.5), .radius = 1, }",
+            )),
+            1,
+            10.0,
+        );
+        // 0.8 grey lit at 45 degrees: 0.8 * (0.1 + 1 / sqrt(2)) * 255
+        assert_eq!(lit.get(0, 0), [165, 165, 165]);
+        // The wall's default grey under ambient light alone
+        assert_eq!(shadowed.get(0, 0), [20, 20, 20]);
+    }
+}
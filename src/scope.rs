@@ -0,0 +1,792 @@
+use crate::constructor::{self, OBJECT_TYPES};
+use crate::cst::Cst;
+use crate::tokeniser::Token;
+
+/// A name in a file, as the index of its token and the column it starts at
+/// within it, which is past 0 for the `{name}` placeholders of generated names
+/// and the parts of names such as `lamp_bulb` made by prefab instances
+pub type Occurrence = (usize, usize);
+
+/// A declared name and the names that refer to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub declaration: Occurrence,
+    /// Where it is used, in file order, not counting its declaration
+    pub references: Vec<Occurrence>,
+    /// Whether it is brought in by an IMPORT, so declared in another file
+    pub imported: bool,
+}
+
+impl Symbol {
+    /// Its declaration and its references.
+    pub fn occurrences(&self) -> impl Iterator<Item = Occurrence> + '_ {
+        std::iter::once(self.declaration).chain(self.references.iter().copied())
+    }
+}
+
+/// Every name declared in the file, with what refers to it. Names are
+/// resolved the way the constructor expands them: constants, loop variables
+/// and prefab parameters by the blocks they are declared in, ahead of objects,
+/// which are shared by a scene or a prefab. Calls and the keys of maps refer
+/// to nothing, and neither do names that are not declared in the file, such
+/// as `ease_in_out`. Strings and ZIG blocks are not looked into.
+pub fn symbols(cst: &Cst) -> Vec<Symbol> {
+    let mut resolver = Resolver {
+        cst,
+        end: cst.tokens().0.len(),
+        next: 0,
+        scopes: vec![Scope {
+            parent: None,
+            kind: ScopeKind::File,
+        }],
+        declarations: Vec::new(),
+        uses: Vec::new(),
+    };
+    // Stray closing braces end no block at the top level
+    while resolver.next < resolver.end {
+        resolver.statements(0);
+        resolver.next += 1;
+    }
+
+    let mut symbols: Vec<Symbol> = resolver
+        .declarations
+        .iter()
+        .map(|declaration| Symbol {
+            name: declaration.name.clone(),
+            declaration: declaration.occurrence,
+            references: Vec::new(),
+            imported: declaration.kind == Kind::Import,
+        })
+        .collect();
+    for name in &resolver.uses {
+        for (occurrence, declaration) in resolver.resolve(name) {
+            symbols[declaration].references.push(occurrence);
+        }
+    }
+    for symbol in &mut symbols {
+        symbol.references.sort();
+    }
+    // Generated names are matched, but cannot be renamed
+    symbols.retain(|symbol| !symbol.name.contains('{'));
+    symbols
+}
+
+/// The symbol whose name covers the column `offset` of the token at `index`,
+/// where it is declared or used.
+pub fn at(cst: &Cst, index: usize, offset: usize) -> Option<Symbol> {
+    symbols(cst).into_iter().find(|symbol| {
+        symbol.occurrences().any(|(i, column)| {
+            i == index && column <= offset && offset <= column + symbol.name.len()
+        })
+    })
+}
+
+/// Whether the generated name `pattern` can expand to `name`, with each
+/// `{variable}` standing for at least one letter, digit or underscore.
+pub fn generates(pattern: &str, name: &str) -> bool {
+    let mut pieces = Vec::new();
+    let mut rest = pattern;
+    while let Some((before, after)) = rest.split_once('{') {
+        pieces.push(before);
+        rest = after.split_once('}').map_or("", |(_, after)| after);
+    }
+    if pieces.is_empty() {
+        return false;
+    }
+    pieces.push(rest);
+
+    // Each piece in turn, with something between them
+    let mut remaining = match name.strip_prefix(pieces[0]) {
+        Some(remaining) => remaining,
+        None => return false,
+    };
+    let value = |value: &str| {
+        !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let last = pieces.len() - 1;
+    for (i, piece) in pieces.iter().enumerate().skip(1) {
+        // Names are ASCII, so values can be sliced by bytes
+        let (before, after) = if i == last {
+            match remaining.strip_suffix(piece) {
+                Some(before) => (before, ""),
+                None => return false,
+            }
+        } else {
+            match remaining.get(1..).and_then(|rest| rest.find(piece)) {
+                Some(at) => (&remaining[..at + 1], &remaining[at + 1 + piece.len()..]),
+                None => return false,
+            }
+        };
+        if !value(before) {
+            return false;
+        }
+        remaining = after;
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScopeKind {
+    File,
+    Scene,
+    Prefab,
+    /// The body of a FOR or IF block
+    Block,
+}
+
+struct Scope {
+    parent: Option<usize>,
+    kind: ScopeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Constant,
+    Variable,
+    Parameter,
+    Object,
+    Instance,
+    Prefab,
+    Scene,
+    Import,
+}
+
+struct Declaration {
+    name: String,
+    occurrence: Occurrence,
+    kind: Kind,
+    scope: usize,
+    /// The index of the first token that sees it, as a constant or parameter
+    /// is not bound in its own value
+    from: usize,
+    /// The prefab of an instance
+    prefab: Option<String>,
+    /// The scope of a prefab's body
+    body: Option<usize>,
+}
+
+/// What a name can refer to
+#[derive(Clone)]
+enum Expect {
+    /// A constant, variable or parameter, or else an object
+    Value,
+    /// A constant, variable or parameter, for a `{name}` placeholder
+    Binding,
+    Prefab,
+    Scene,
+    /// A parameter of the named prefab, for an argument of an instance
+    Parameter(String),
+}
+
+struct Use {
+    name: String,
+    occurrence: Occurrence,
+    scope: usize,
+    expect: Expect,
+}
+
+/// What a block holds
+#[derive(Clone)]
+enum Block {
+    Statements,
+    /// The properties of an object, or the arguments of an instance of the
+    /// named prefab
+    Properties(Option<String>),
+}
+
+struct Resolver<'a> {
+    cst: &'a Cst,
+    /// The number of tokens before the first unknown character
+    end: usize,
+    next: usize,
+    scopes: Vec<Scope>,
+    declarations: Vec<Declaration>,
+    uses: Vec<Use>,
+}
+
+impl<'a> Resolver<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        let cst = self.cst;
+        (self.next < self.end).then(|| &cst.elements[self.next].token)
+    }
+
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(name)) if name == keyword)
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        let found = self.peek() == Some(&token);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    /// Takes a name, with the index of its token.
+    fn name(&mut self) -> Option<(String, usize)> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                self.next += 1;
+                Some((name.clone(), self.next - 1))
+            }
+            _ => None,
+        }
+    }
+
+    fn scope(&mut self, parent: usize, kind: ScopeKind) -> usize {
+        self.scopes.push(Scope {
+            parent: Some(parent),
+            kind,
+        });
+        self.scopes.len() - 1
+    }
+
+    /// `scope` and the scopes around it, innermost first.
+    fn chain(&self, scope: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(scope), |&scope| self.scopes[scope].parent)
+    }
+
+    /// The scope the objects of `scope` are shared by: its scene, prefab or
+    /// file.
+    fn namespace(&self, scope: usize) -> usize {
+        self.chain(scope)
+            .find(|&scope| self.scopes[scope].kind != ScopeKind::Block)
+            .unwrap_or(0)
+    }
+
+    fn declare(&mut self, (name, index): (String, usize), kind: Kind, scope: usize) -> usize {
+        self.declarations.push(Declaration {
+            name,
+            occurrence: (index, 0),
+            kind,
+            scope,
+            from: self.next,
+            prefab: None,
+            body: None,
+        });
+        self.declarations.len() - 1
+    }
+
+    fn refer(&mut self, name: String, occurrence: Occurrence, scope: usize, expect: Expect) {
+        self.uses.push(Use {
+            name,
+            occurrence,
+            scope,
+            expect,
+        });
+    }
+
+    /// Uses the variables of the `{name}` placeholders in the name at
+    /// `index`.
+    fn placeholders(&mut self, index: usize, scope: usize) {
+        let name = match &self.cst.elements[index].token {
+            Token::Identifier(name) => name,
+            _ => return,
+        };
+        // Identifiers are ASCII, so byte offsets are columns
+        for (open, _) in name.match_indices('{') {
+            if let Some(length) = name[open..].find('}') {
+                let variable = name[open + 1..open + length].to_string();
+                self.refer(variable, (index, open + 1), scope, Expect::Binding);
+            }
+        }
+    }
+
+    /// The statements of a block, up to its closing brace.
+    fn statements(&mut self, scope: usize) {
+        while let Some(token) = self.peek() {
+            let start = self.next;
+            let keyword = match token {
+                Token::RBrace => return,
+                Token::Identifier(keyword) => keyword.as_str(),
+                _ => {
+                    self.next += 1;
+                    continue;
+                }
+            };
+            self.next += 1;
+            match keyword {
+                "SCENE" => {
+                    if let Some(name) = self.name() {
+                        self.declare(name, Kind::Scene, scope);
+                    }
+                    let body = self.scope(scope, ScopeKind::Scene);
+                    self.block(body, &Block::Statements);
+                }
+                "START" => {
+                    if let Some((name, index)) = self.name() {
+                        self.refer(name, (index, 0), scope, Expect::Scene);
+                    }
+                }
+                "FOR" => {
+                    let variable = self.name();
+                    self.eat(Token::Identifier("IN".to_string()));
+                    self.expression(scope);
+                    if self.eat(Token::DotDot) {
+                        self.expression(scope);
+                    }
+                    let body = self.scope(scope, ScopeKind::Block);
+                    if let Some(variable) = variable {
+                        self.declare(variable, Kind::Variable, body);
+                    }
+                    self.block(body, &Block::Statements);
+                }
+                "IF" => self.conditional(scope, &Block::Statements),
+                "CONST" => {
+                    let name = self.name();
+                    self.eat(Token::Equal);
+                    self.expression(scope);
+                    if let Some(name) = name {
+                        self.declare(name, Kind::Constant, scope);
+                    }
+                }
+                "PREFAB" => self.prefab(scope),
+                "IMPORT" => {
+                    while let Some(Token::Identifier(name)) = self.peek() {
+                        if name == "FROM" {
+                            break;
+                        }
+                        self.next += 1;
+                        self.declare((name.clone(), self.next - 1), Kind::Import, scope);
+                        if !self.eat(Token::Comma) {
+                            break;
+                        }
+                    }
+                    self.eat(Token::Identifier("FROM".to_string()));
+                }
+                keyword if OBJECT_TYPES.contains(&keyword) => {
+                    let namespace = self.namespace(scope);
+                    // WINDOW and RENDER blocks have no name
+                    if let Some(name) = self.name() {
+                        self.placeholders(name.1, scope);
+                        self.declare(name, Kind::Object, namespace);
+                    }
+                    if self.keyword("EXTENDS") {
+                        self.next += 1;
+                        if let Some((base, index)) = self.name() {
+                            self.refer(base, (index, 0), scope, Expect::Value);
+                        }
+                    }
+                    self.block(scope, &Block::Properties(None));
+                }
+                keyword if !constructor::is_keyword(keyword) => {
+                    let prefab = keyword.to_string();
+                    self.refer(prefab.clone(), (start, 0), scope, Expect::Prefab);
+                    let namespace = self.namespace(scope);
+                    if let Some(name) = self.name() {
+                        self.placeholders(name.1, scope);
+                        let instance = self.declare(name, Kind::Instance, namespace);
+                        self.declarations[instance].prefab = Some(prefab.clone());
+                    }
+                    self.block(scope, &Block::Properties(Some(prefab)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn prefab(&mut self, scope: usize) {
+        let body = self.scope(scope, ScopeKind::Prefab);
+        if let Some(name) = self.name() {
+            let prefab = self.declare(name, Kind::Prefab, scope);
+            self.declarations[prefab].body = Some(body);
+        }
+
+        self.eat(Token::LParen);
+        while let Some((parameter, index)) = self.name() {
+            // Defaults see the parameters before theirs
+            if self.eat(Token::Equal) {
+                self.expression(body);
+            }
+            self.declare((parameter, index), Kind::Parameter, body);
+            if !self.eat(Token::Comma) {
+                break;
+            }
+        }
+        self.eat(Token::RParen);
+        self.block(body, &Block::Statements);
+    }
+
+    /// An IF block, with its ELSE IF and ELSE branches, from after its `IF`.
+    fn conditional(&mut self, scope: usize, block: &Block) {
+        self.expression(scope);
+        let branch = self.scope(scope, ScopeKind::Block);
+        self.block(branch, block);
+        while self.keyword("ELSE") {
+            self.next += 1;
+            if self.keyword("IF") {
+                self.next += 1;
+                self.expression(scope);
+            }
+            let branch = self.scope(scope, ScopeKind::Block);
+            self.block(branch, block);
+        }
+    }
+
+    fn block(&mut self, scope: usize, block: &Block) {
+        if !self.eat(Token::LBrace) {
+            return;
+        }
+        match block {
+            Block::Statements => self.statements(scope),
+            Block::Properties(prefab) => self.properties(scope, prefab),
+        }
+        self.eat(Token::RBrace);
+    }
+
+    fn properties(&mut self, scope: usize, prefab: &Option<String>) {
+        while let Some(token) = self.peek() {
+            match token {
+                Token::RBrace => return,
+                Token::Identifier(keyword) if keyword == "IF" => {
+                    self.next += 1;
+                    let block = Block::Properties(prefab.clone());
+                    self.conditional(scope, &block);
+                }
+                Token::Dot => {
+                    self.next += 1;
+                    let name = self.name();
+                    if let (Some((name, index)), Some(prefab)) = (name, prefab) {
+                        let expect = Expect::Parameter(prefab.clone());
+                        self.refer(name, (index, 0), scope, expect);
+                    }
+                    self.eat(Token::Equal);
+                    self.expression(scope);
+                    self.eat(Token::Comma);
+                }
+                _ => self.next += 1,
+            }
+        }
+    }
+
+    fn expression(&mut self, scope: usize) {
+        loop {
+            self.operand(scope);
+            match self.peek() {
+                Some(
+                    Token::OrOr
+                    | Token::AndAnd
+                    | Token::EqualEqual
+                    | Token::BangEqual
+                    | Token::Less
+                    | Token::LessEqual
+                    | Token::Greater
+                    | Token::GreaterEqual
+                    | Token::Plus
+                    | Token::Minus
+                    | Token::Star
+                    | Token::Slash,
+                ) => self.next += 1,
+                _ => return,
+            }
+        }
+    }
+
+    fn operand(&mut self, scope: usize) {
+        while matches!(self.peek(), Some(Token::Bang | Token::Minus)) {
+            self.next += 1;
+        }
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let index = self.next;
+                self.next += 1;
+                // Calls are to built in functions
+                if self.eat(Token::LParen) {
+                    self.list(scope, Token::RParen);
+                } else {
+                    self.placeholders(index, scope);
+                    if !name.contains('{') {
+                        self.refer(name.clone(), (index, 0), scope, Expect::Value);
+                    }
+                }
+            }
+            Some(Token::LParen) => {
+                self.next += 1;
+                self.list(scope, Token::RParen);
+            }
+            Some(Token::LBracket) => {
+                self.next += 1;
+                self.list(scope, Token::RBracket);
+            }
+            Some(Token::LBrace) => {
+                self.next += 1;
+                while !matches!(self.peek(), Some(Token::RBrace) | None) {
+                    // The key
+                    self.next += 1;
+                    self.eat(Token::Colon);
+                    self.expression(scope);
+                    self.eat(Token::Comma);
+                }
+                self.eat(Token::RBrace);
+            }
+            Some(Token::Number(_) | Token::String(_) | Token::Color(_) | Token::Zig(_)) => {
+                self.next += 1
+            }
+            _ => return,
+        }
+        // Members, such as the `position` of `ball.position`
+        while self.eat(Token::Dot) {
+            self.name();
+        }
+    }
+
+    /// Comma separated expressions, up to and including `close`.
+    fn list(&mut self, scope: usize, close: Token) {
+        while self.peek().is_some_and(|token| *token != close) {
+            let start = self.next;
+            self.expression(scope);
+            self.eat(Token::Comma);
+            if self.next == start {
+                self.next += 1;
+            }
+        }
+        self.eat(close);
+    }
+
+    /// The declarations that `name` refers to, with where in it each is
+    /// named.
+    fn resolve(&self, name: &Use) -> Vec<(Occurrence, usize)> {
+        let (index, column) = name.occurrence;
+        let declaration = match &name.expect {
+            Expect::Value => match self.binding(name) {
+                Some(declaration) => Some(declaration),
+                None => self.object(&name.name, name.scope),
+            },
+            Expect::Binding => self.binding(name),
+            Expect::Prefab => self.prefab_named(&name.name, name.scope),
+            Expect::Scene => self
+                .declarations
+                .iter()
+                .position(|d| d.kind == Kind::Scene && d.name == name.name),
+            Expect::Parameter(prefab) => self
+                .prefab_named(prefab, name.scope)
+                .and_then(|prefab| self.declarations[prefab].body)
+                .and_then(|body| {
+                    self.declarations.iter().position(|d| {
+                        d.scope == body && d.kind == Kind::Parameter && d.name == name.name
+                    })
+                }),
+        };
+        match (declaration, &name.expect) {
+            (Some(declaration), _) => vec![((index, column), declaration)],
+            // Objects made by prefab instances, named `<instance>_<object>`
+            (None, Expect::Value) => {
+                let namespaces: Vec<usize> = self
+                    .chain(name.scope)
+                    .filter(|&scope| self.scopes[scope].kind != ScopeKind::Block)
+                    .collect();
+                self.compound(&name.name, &namespaces)
+                    .into_iter()
+                    .flatten()
+                    .map(|(offset, declaration)| ((index, column + offset), declaration))
+                    .collect()
+            }
+            (None, _) => Vec::new(),
+        }
+    }
+
+    /// The innermost constant, variable or parameter bound to the name. In a
+    /// scene, everything declared at the top level is bound, as the top level
+    /// is expanded first.
+    fn binding(&self, name: &Use) -> Option<usize> {
+        let in_scene = self
+            .chain(name.scope)
+            .any(|scope| self.scopes[scope].kind == ScopeKind::Scene);
+        let at = name.occurrence.0;
+        self.chain(name.scope).find_map(|scope| {
+            let top_level = in_scene && self.scopes[scope].kind == ScopeKind::File;
+            self.declarations
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| {
+                    d.scope == scope
+                        && d.name == name.name
+                        && matches!(
+                            d.kind,
+                            Kind::Constant | Kind::Variable | Kind::Parameter | Kind::Import
+                        )
+                        && (d.from <= at || top_level)
+                })
+                .max_by_key(|(_, d)| d.from)
+                .map(|(i, _)| i)
+        })
+    }
+
+    fn object(&self, name: &str, scope: usize) -> Option<usize> {
+        self.named(name, scope, &[Kind::Object, Kind::Import])
+    }
+
+    fn prefab_named(&self, name: &str, scope: usize) -> Option<usize> {
+        self.named(name, scope, &[Kind::Prefab, Kind::Import])
+    }
+
+    /// The first declaration of `name` as one of `kinds`, looking out from
+    /// `scope`.
+    fn named(&self, name: &str, scope: usize, kinds: &[Kind]) -> Option<usize> {
+        self.chain(scope).find_map(|scope| {
+            self.declarations
+                .iter()
+                .position(|d| d.scope == scope && d.name == name && kinds.contains(&d.kind))
+        })
+    }
+
+    /// The instance and the object of its prefab that `name` is made of,
+    /// looking through the instances in `namespaces`, with the column each
+    /// part starts at. Generated instances such as `lamp_{i}` match their
+    /// names but are not part of the result, as they cannot be renamed.
+    fn compound(&self, name: &str, namespaces: &[usize]) -> Option<Vec<(usize, usize)>> {
+        for &namespace in namespaces {
+            let instances = self
+                .declarations
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| d.scope == namespace && d.kind == Kind::Instance);
+            for (i, instance) in instances {
+                let body = instance
+                    .prefab
+                    .as_ref()
+                    .and_then(|prefab| self.prefab_named(prefab, instance.scope))
+                    .and_then(|prefab| self.declarations[prefab].body);
+                let body = match body {
+                    Some(body) => body,
+                    None => continue,
+                };
+                for (split, _) in name.match_indices('_') {
+                    let (prefix, local) = (&name[..split], &name[split + 1..]);
+                    let exact = instance.name == prefix;
+                    if !exact && !generates(&instance.name, prefix) {
+                        continue;
+                    }
+                    let object = self
+                        .declarations
+                        .iter()
+                        .position(|d| d.scope == body && d.kind == Kind::Object && d.name == local);
+                    let parts = match object {
+                        Some(object) => vec![(0, object)],
+                        None => match self.compound(local, &[body]) {
+                            Some(parts) => parts,
+                            None => continue,
+                        },
+                    };
+                    let parts = parts
+                        .into_iter()
+                        .map(|(column, declaration)| (column + split + 1, declaration));
+                    return Some(exact.then_some((0, i)).into_iter().chain(parts).collect());
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renames the name at `position` to `new`.
+    fn rename(text: &str, position: (usize, usize), new: &str) -> String {
+        let mut cst = Cst::parse("test.zest", text);
+        let index = cst.at(position).unwrap();
+        let symbol = at(&cst, index, position.1 - cst.elements[index].position.1).unwrap();
+        cst.rename(&symbol, new);
+        cst.to_string()
+    }
+
+    #[test]
+    fn loops_do_not_share_variables() {
+        let text = "SCENE s {
+    FOR i IN 0..2 {
+        SPHERE a_{i} { .radius = i, }
+    }
+    FOR i IN 0..3 {
+        SPHERE b_{i} { .radius = i, }
+    }
+}
+";
+        let renamed = rename(text, (2, 9), "j");
+        assert!(renamed.contains("FOR j IN 0..2 {\n        SPHERE a_{j} { .radius = j, }"));
+        assert!(renamed.contains("FOR i IN 0..3 {\n        SPHERE b_{i} { .radius = i, }"));
+    }
+
+    #[test]
+    fn constants_are_scoped_to_their_blocks() {
+        let text = "CONST size = 1
+SCENE s {
+    IF true {
+        CONST size = size * 2
+        SPHERE a { .radius = size, }
+    }
+    SPHERE b { .radius = size, }
+}
+";
+        let renamed = rename(text, (1, 7), "scale");
+        assert!(renamed.starts_with("CONST scale = 1"));
+        assert!(renamed.contains("CONST size = scale * 2"));
+        assert!(renamed.contains("SPHERE a { .radius = size, }"));
+        assert!(renamed.contains("SPHERE b { .radius = scale, }"));
+    }
+
+    #[test]
+    fn keys_and_calls_are_not_names() {
+        let text = "CONST W = 2
+CONST sin = 1
+SCENE s {
+    CONTROLLER c { .bindings = { W: forward }, }
+    SPHERE a { .radius = sin(W) + sin, }
+}
+";
+        let renamed = rename(text, (1, 7), "width");
+        assert!(renamed.contains("{ W: forward }"));
+        assert!(renamed.contains(".radius = sin(width) + sin,"));
+
+        let renamed = rename(text, (2, 7), "sine");
+        assert!(renamed.contains(".radius = sin(W) + sine,"));
+    }
+
+    #[test]
+    fn parameters_are_renamed_in_instances() {
+        let text = std::fs::read_to_string("examples/example_04.zest").unwrap();
+        let renamed = rename(&text, (1, 13), "base");
+        assert!(renamed.starts_with("PREFAB lamp(base, colour = #ffaa00) {"));
+        assert!(renamed.contains(".v0       = base - (0.1, 0, 0.1),"));
+        assert!(renamed.contains(".position = base + (0, 3.3, 0),"));
+        // Arguments of instances, but not the properties of objects
+        assert!(renamed.contains(".base = (i * 5, -1, 10),"));
+        assert!(renamed.contains(".base = (-5, -1, 10),"));
+        assert!(renamed.contains(".position      = (5, 2, 0),"));
+        assert!(renamed.contains(".position  = bulb.position,"));
+    }
+
+    #[test]
+    fn objects_of_prefabs_are_renamed_in_instance_names() {
+        let text = std::fs::read_to_string("examples/example_04.zest").unwrap();
+        let renamed = rename(&text, (13, 12), "globe");
+        assert!(renamed.contains("SPHERE globe {"));
+        assert!(renamed.contains(".position  = globe.position,"));
+        assert!(renamed.contains(".look_at       = lamp_2_globe,"));
+
+        let text = text.replace("lamp_2_bulb", "blue_lamp_bulb");
+        let renamed = rename(&text, (32, 10), "red_lamp");
+        assert!(renamed.contains("lamp red_lamp {"));
+        assert!(renamed.contains(".look_at       = red_lamp_bulb,"));
+    }
+
+    #[test]
+    fn scenes_do_not_share_objects() {
+        let text = std::fs::read_to_string("examples/example_06.zest").unwrap();
+        let symbols = symbols(&Cst::parse("test.zest", &text));
+        let cameras: Vec<&Symbol> = symbols.iter().filter(|s| s.name == "cam").collect();
+        assert_eq!(cameras.len(), 2);
+        for camera in cameras {
+            assert_eq!(camera.references.len(), 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "`mat_red` is declared in another file")]
+    fn imported_names_are_not_renamed() {
+        let text = std::fs::read_to_string("examples/example_05.zest").unwrap();
+        rename(&text, (1, 9), "red");
+    }
+}
@@ -1,3 +1,8 @@
+use crate::constructor;
+
+/// 1-based line and column of a token
+pub type Position = (usize, usize);
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token {
     Identifier(String),
@@ -5,67 +10,121 @@ pub enum Token {
     LBrace,
     RParen,
     LParen,
+    RBracket,
+    LBracket,
     Dot,
+    DotDot,
     Comma,
+    Colon,
     Equal,
+    EqualEqual,
+    Bang,
+    BangEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    AndAnd,
+    OrOr,
+    Plus,
+    Minus,
+    Star,
+    Slash,
     Number(String),
     String(String),
+    Color(String),
+    /// The code between the braces of a `ZIG { ... }` block, verbatim
+    Zig(String),
     EoF,
     Unknown,
-    Ignore,
+}
+
+/// Whitespace or a `// comment`, which the constructor skips
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    /// From the slashes to the end of the line, without the newline
+    Comment(String),
 }
 
 pub struct Tokeniser {
-    pub text: String,
-    current: usize,
+    /// The file the text was read from, for errors
+    file: String,
+    /// The characters of the text, indexed directly so tokenising stays linear
+    pub text: Vec<char>,
+    /// The index of the next character
+    pub current: usize,
     skip: usize,
 }
 
 impl Tokeniser {
-    pub fn new(text: String) -> Self {
+    pub fn new(file: String, text: String) -> Self {
         Self {
-            text,
+            file,
+            text: text.chars().collect(),
             current: 0,
             skip: 0,
         }
     }
 
     pub fn tokenise(&mut self) -> Token {
-        if self.current >= self.text.len() {
+        let curr = self.char_at(self.current);
+        if curr == '\0' {
             return Token::EoF;
         }
 
-        let curr = self.text.chars().nth(self.current).unwrap();
-
         let (token, adv) = match curr {
             '{' => (Token::LBrace, 1),
             '}' => (Token::RBrace, 1),
             '(' => (Token::LParen, 1),
             ')' => (Token::RParen, 1),
+            '[' => (Token::LBracket, 1),
+            ']' => (Token::RBracket, 1),
+            '.' if self.char_at(self.current + 1) == '.' => (Token::DotDot, 2),
             '.' => (Token::Dot, 1),
             ',' => (Token::Comma, 1),
-            '=' => (Token::Equal, 1),
+            ':' => (Token::Colon, 1),
+            '=' | '!' | '<' | '>' | '&' | '|' => self.operator(curr),
+            '+' => (Token::Plus, 1),
+            '-' => (Token::Minus, 1),
+            '*' => (Token::Star, 1),
+            '/' => (Token::Slash, 1),
             _ => (self.make_token(), self.skip),
         };
 
         self.current += adv;
         self.skip = 0;
 
-        self.skip_whitespace();
-        self.current += self.skip;
-        self.skip = 0;
-
         token
     }
 
+    pub fn operator(&self, curr: char) -> (Token, usize) {
+        let next = self.char_at(self.current + 1);
+        match (curr, next) {
+            ('=', '=') => (Token::EqualEqual, 2),
+            ('!', '=') => (Token::BangEqual, 2),
+            ('<', '=') => (Token::LessEqual, 2),
+            ('>', '=') => (Token::GreaterEqual, 2),
+            ('&', '&') => (Token::AndAnd, 2),
+            ('|', '|') => (Token::OrOr, 2),
+            ('=', _) => (Token::Equal, 1),
+            ('!', _) => (Token::Bang, 1),
+            ('<', _) => (Token::Less, 1),
+            ('>', _) => (Token::Greater, 1),
+            _ => (Token::Unknown, 1),
+        }
+    }
+
     pub fn make_token(&mut self) -> Token {
-        let curr = self.text.chars().nth(self.current).unwrap();
-        if curr.is_ascii_digit() || curr == '-' {
+        let curr = self.text[self.current];
+        if curr.is_ascii_digit() {
             self.number()
         } else if curr.is_ascii_alphabetic() {
             self.identifier()
         } else if curr == '"' {
             self.string()
+        } else if curr == '#' {
+            self.color()
         } else {
             Token::Unknown
         }
@@ -74,48 +133,77 @@ impl Tokeniser {
     pub fn string(&mut self) -> Token {
         let mut string = String::new();
         self.skip += 1;
-        let mut curr = self.text.chars().nth(self.current + self.skip).unwrap();
+        let mut curr = self.char_at(self.current + self.skip);
         while curr != '"' {
+            if curr == '\0' {
+                self.error(self.current, "Unterminated string");
+            }
             string.push(curr);
             self.skip += 1;
-            curr = self.text.chars().nth(self.current + self.skip).unwrap();
+            curr = self.char_at(self.current + self.skip);
         }
         self.skip += 1;
 
         Token::String(string)
     }
 
-    pub fn skip_whitespace(&mut self) -> Token {
-        let mut curr = self.text.chars().nth(self.current).unwrap();
-        while curr.is_whitespace() || curr == '\n' {
+    pub fn color(&mut self) -> Token {
+        let mut hex = String::new();
+        self.skip += 1;
+        let mut curr = self.char_at(self.current + self.skip);
+        while curr.is_ascii_alphanumeric() {
+            hex.push(curr);
             self.skip += 1;
-            if (self.current + self.skip) >= self.text.len() {
-                break;
-            }
+            curr = self.char_at(self.current + self.skip);
+        }
+
+        Token::Color(hex)
+    }
 
-            curr = self.text.chars().nth(self.current + self.skip).unwrap();
+    /// Reads the whitespace and comments at the cursor.
+    pub fn trivia(&mut self) -> Vec<Trivia> {
+        let mut trivia = Vec::new();
+        loop {
+            let start = self.current;
+            let curr = self.char_at(start);
+            if curr == '/' && self.char_at(start + 1) == '/' {
+                while !matches!(self.char_at(self.current), '\n' | '\0') {
+                    self.current += 1;
+                }
+                trivia.push(Trivia::Comment(self.slice(start, self.current)));
+            } else if curr.is_whitespace() {
+                while self.char_at(self.current).is_whitespace() {
+                    self.current += 1;
+                }
+                trivia.push(Trivia::Whitespace(self.slice(start, self.current)));
+            } else {
+                return trivia;
+            }
         }
+    }
 
-        Token::Ignore
+    /// The characters from `start` up to `end`.
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        self.text[start..end].iter().collect()
     }
 
     pub fn number(&mut self) -> Token {
         let mut number = String::new();
-        let mut curr = self.text.chars().nth(self.current).unwrap();
-        if curr == '-' {
-            number.push(curr);
-            self.skip += 1;
-            curr = self.text.chars().nth(self.current + self.skip).unwrap();
-        }
+        let mut curr = self.text[self.current];
         let mut period_seen = false;
 
-        while curr.is_ascii_digit() || (curr == '.' && !period_seen) {
+        // A period only belongs to the number if a digit follows, so `0..10` is a range
+        while curr.is_ascii_digit()
+            || (curr == '.'
+                && !period_seen
+                && self.char_at(self.current + self.skip + 1).is_ascii_digit())
+        {
             if curr == '.' {
                 period_seen = true;
             }
             number.push(curr);
             self.skip += 1;
-            curr = self.text.chars().nth(self.current + self.skip).unwrap();
+            curr = self.char_at(self.current + self.skip);
         }
 
         Token::Number(number)
@@ -123,13 +211,121 @@ impl Tokeniser {
 
     pub fn identifier(&mut self) -> Token {
         let mut identifier = String::new();
-        let mut curr = self.text.chars().nth(self.current).unwrap();
-        while curr.is_ascii_alphabetic() || curr.is_ascii_digit() || curr == '_' {
-            identifier.push(curr);
-            self.skip += 1;
-            curr = self.text.chars().nth(self.current + self.skip).unwrap();
+        let mut curr = self.text[self.current];
+        loop {
+            if curr.is_ascii_alphabetic() || curr.is_ascii_digit() || curr == '_' {
+                identifier.push(curr);
+                self.skip += 1;
+            } else if let Some(length) = self.placeholder() {
+                // `sphere_{i}`: the loop variable is interpolated into the name
+                let start = self.current + self.skip;
+                identifier.extend(&self.text[start..start + length]);
+                self.skip += length;
+            } else {
+                break;
+            }
+            curr = self.char_at(self.current + self.skip);
+        }
+
+        if identifier == "ZIG" {
+            if let Some(code) = self.zig() {
+                return Token::Zig(code);
+            }
         }
 
         Token::Identifier(identifier)
     }
+
+    /// The body of a `ZIG { ... }` block following the cursor, if there is one.
+    /// Braces are matched, skipping those in Zig strings, characters and comments.
+    pub fn zig(&mut self) -> Option<String> {
+        let mut start = self.current + self.skip;
+        while self.char_at(start).is_whitespace() {
+            start += 1;
+        }
+        if self.char_at(start) != '{' {
+            return None;
+        }
+
+        let mut end = start + 1;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.char_at(end) {
+                '\0' => self.error(self.current, "Unterminated ZIG block"),
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                quote @ ('"' | '\'') => {
+                    let literal = end;
+                    end += 1;
+                    while self.char_at(end) != quote {
+                        match self.char_at(end) {
+                            '\0' | '\n' => self.error(literal, "Unterminated literal in ZIG block"),
+                            '\\' => end += 2,
+                            _ => end += 1,
+                        }
+                    }
+                }
+                // Comments and multiline string lines run to the end of the line
+                '/' | '\\' if self.char_at(end + 1) == self.char_at(end) => {
+                    while !matches!(self.char_at(end + 1), '\n' | '\0') {
+                        end += 1;
+                    }
+                }
+                _ => {}
+            }
+            end += 1;
+        }
+
+        let code = self.slice(start + 1, end - 1);
+        self.skip = end - self.current;
+        Some(code)
+    }
+
+    /// The position of the character at `index`.
+    fn position(&self, index: usize) -> Position {
+        let mut position = (1, 1);
+        for &c in &self.text[..index] {
+            if c == '\n' {
+                position = (position.0 + 1, 1);
+            } else {
+                position.1 += 1;
+            }
+        }
+
+        position
+    }
+
+    /// Panics with `message`, located at the character at `index`.
+    fn error(&self, index: usize, message: &str) -> ! {
+        constructor::report(&self.file, self.position(index), message.to_string())
+    }
+
+    /// The character at `index`, or `\0` past the end of the text.
+    pub fn char_at(&self, index: usize) -> char {
+        self.text.get(index).copied().unwrap_or('\0')
+    }
+
+    /// The length of a `{variable}` placeholder directly at the cursor, if there is one.
+    /// Object bodies never match, since they start with whitespace, `.` or `}`.
+    pub fn placeholder(&self) -> Option<usize> {
+        let rest = &self.text[(self.current + self.skip).min(self.text.len())..];
+        if rest.first() != Some(&'{') {
+            return None;
+        }
+
+        let length = rest[1..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .count();
+        let valid = rest
+            .get(1)
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_')
+            && rest.get(length + 1) == Some(&'}');
+
+        if valid {
+            Some(length + 2)
+        } else {
+            None
+        }
+    }
 }
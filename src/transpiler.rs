@@ -1,15 +1,30 @@
 use crate::constructor::{self, Expression};
+use crate::tokeniser::Position;
+use std::cell::RefCell;
 
 const BEGIN: &str = "const z3d = @import(\"root.zig\");
 const std = @import(\"std\");
 
 const Vec3 = z3d.math.Vec3(f32);
+";
 
-// Don't make this too big. Ensure HEIGHT == WIDTH
-const HEIGHT = 400;
-const WIDTH = 400;
+const MAIN: &str = "
+// Set while a scene is running to switch to another scene once it ends
+pub var next_scene: ?SceneId = null;
 
 pub fn main() !void {
+    var current: ?SceneId = .START;
+    while (current) |scene_id| {
+        next_scene = null;
+        switch (scene_id) {
+CASES        }
+        current = next_scene;
+    }
+}
+";
+
+const SCENE_BEGIN: &str = "
+fn scene_NAME() !void {
     var arena = std.heap.ArenaAllocator.init(std.heap.page_allocator);
     defer arena.deinit();
     const allocator = arena.allocator();
@@ -24,12 +39,71 @@ pub fn main() !void {
     defer gui_layer.deinit();
 ";
 
-const END: &str = "
-    var engine = try z3d.engine.Engine.init(\"Z3D\", 0, 0, WIDTH, HEIGHT, z3d.engine.WindowFlags.default(), scene, allocator);
+const SCENE_END: &str = "
+    var engine = try z3d.engine.Engine.init(TITLE, X, Y, WIDTH, HEIGHT, WINDOW_FLAGS, scene, allocator);
     defer engine.deinit();
+";
 
+const MAINLOOP: &str = "
     try engine.mainloop();
-}";
+}
+";
+
+// Used instead of MAINLOOP when the scene has code to run every frame
+const FRAME_BEGIN: &str = "
+    var clock = try std.time.Timer.start();
+    while (engine.running()) {
+        const time = @as(f32, @floatFromInt(clock.read())) / std.time.ns_per_s;
+";
+
+const FRAME_END: &str = "        try engine.frame();
+    }
+}
+";
+
+// Keyframe interpolation, included when any scene has an ANIMATION
+const ANIMATE: &str = "
+const Keyframe = struct { time: f32, value: Vec3 };
+const Easing = enum { linear, ease_in, ease_out, ease_in_out, step };
+const LoopMode = enum { once, repeat, ping_pong };
+
+fn ease(easing: Easing, t: f32) f32 {
+    return switch (easing) {
+        .linear => t,
+        .ease_in => t * t,
+        .ease_out => t * (2 - t),
+        .ease_in_out => if (t < 0.5) 2 * t * t else -1 + (4 - 2 * t) * t,
+        .step => if (t < 1) 0 else 1,
+    };
+}
+
+fn animate(keys: []const Keyframe, time: f32, easing: Easing, mode: LoopMode) Vec3 {
+    const duration = keys[keys.len - 1].time;
+    var t = time;
+    if (duration > 0) {
+        switch (mode) {
+            .once => t = @min(time, duration),
+            .repeat => t = @mod(time, duration),
+            .ping_pong => {
+                t = @mod(time, 2 * duration);
+                if (t > duration) t = 2 * duration - t;
+            },
+        }
+    }
+
+    if (t <= keys[0].time) return keys[0].value;
+    var i: usize = 1;
+    while (i < keys.len) : (i += 1) {
+        if (t <= keys[i].time) {
+            const a = keys[i - 1].value;
+            const b = keys[i].value;
+            const f = ease(easing, (t - keys[i - 1].time) / (keys[i].time - keys[i - 1].time));
+            return Vec3.init(a.x + (b.x - a.x) * f, a.y + (b.y - a.y) * f, a.z + (b.z - a.z) * f);
+        }
+    }
+    return keys[keys.len - 1].value;
+}
+";
 
 struct Sphere {
     pub position: (Expression, Expression, Expression),
@@ -50,55 +124,380 @@ struct Camera {
     pub event_handler: Expression,
 }
 
+pub struct Window {
+    pub title: String,
+    pub width: usize,
+    pub height: usize,
+    pub x: i64,
+    pub y: i64,
+    pub flags: Vec<(String, bool)>,
+}
+
+pub struct Render {
+    pub fov: f64,
+    pub scale: f64,
+}
+
+// Per-body settings, given on the PHYSICS block as defaults or on the body itself
+const BODY_PROPERTIES: [&str; 5] = ["mass", "restitution", "friction", "velocity", "static"];
+
+// The gravity of PHYSICS blocks that don't set .gravity, in m/s²
+pub const DEFAULT_GRAVITY: (f64, f64, f64) = (0.0, -9.8, 0.0);
+
+const EASINGS: [&str; 5] = ["linear", "ease_in", "ease_out", "ease_in_out", "step"];
+const LOOP_MODES: [&str; 3] = ["once", "repeat", "ping_pong"];
+
+/// The properties ANIMATION can target, all of them vectors
+const ANIMATABLE: [(constructor::ObjectType, &str); 5] = [
+    (constructor::ObjectType::Sphere, "position"),
+    (constructor::ObjectType::Camera, "position"),
+    (constructor::ObjectType::Camera, "direction"),
+    (constructor::ObjectType::Light, "position"),
+    (constructor::ObjectType::Light, "intensity"),
+];
+
+const MOVEMENT_ACTIONS: [&str; 6] = ["forward", "backward", "left", "right", "up", "down"];
+
+/// Key names accepted in CONTROLLER bindings (case-insensitively), with the
+/// name of the matching Z3D key
+pub const KEYS: [(&str, &str); 62] = [
+    ("A", "a"), ("B", "b"), ("C", "c"), ("D", "d"), ("E", "e"), ("F", "f"), ("G", "g"),
+    ("H", "h"), ("I", "i"), ("J", "j"), ("K", "k"), ("L", "l"), ("M", "m"), ("N", "n"),
+    ("O", "o"), ("P", "p"), ("Q", "q"), ("R", "r"), ("S", "s"), ("T", "t"), ("U", "u"),
+    ("V", "v"), ("W", "w"), ("X", "x"), ("Y", "y"), ("Z", "z"),
+    ("0", "num_0"), ("1", "num_1"), ("2", "num_2"), ("3", "num_3"), ("4", "num_4"),
+    ("5", "num_5"), ("6", "num_6"), ("7", "num_7"), ("8", "num_8"), ("9", "num_9"),
+    ("F1", "f1"), ("F2", "f2"), ("F3", "f3"), ("F4", "f4"), ("F5", "f5"), ("F6", "f6"),
+    ("F7", "f7"), ("F8", "f8"), ("F9", "f9"), ("F10", "f10"), ("F11", "f11"), ("F12", "f12"),
+    ("Space", "space"), ("Enter", "enter"), ("Escape", "escape"), ("Tab", "tab"),
+    ("Backspace", "backspace"), ("Shift", "shift"), ("Ctrl", "ctrl"), ("Alt", "alt"),
+    ("Up", "up"), ("Down", "down"), ("Left", "left"), ("Right", "right"),
+    ("Insert", "insert"), ("Delete", "delete"),
+];
+
+/// The name of the function handling a custom action, `onOpenMenu` for `open_menu`
+pub fn handler(action: &str) -> String {
+    let mut name = String::from("on");
+    for word in action.split('_').filter(|word| !word.is_empty()) {
+        let mut chars = word.chars();
+        name.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        name.push_str(chars.as_str());
+    }
+    name
+}
+
+/// `text` as a Zig string literal, with quotes, backslashes and unprintable
+/// characters escaped
+fn string_literal(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '"' => literal.push_str("\\\""),
+            '\n' => literal.push_str("\\n"),
+            c if c.is_ascii_control() => literal.push_str(&format!("\\x{:02x}", c as u32)),
+            c if c.is_control() => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Screen-space points GUI elements can be anchored to
+const ANCHORS: [&str; 9] = [
+    "top_left", "top", "top_right", "left", "center", "right", "bottom_left", "bottom", "bottom_right",
+];
+
+/// The properties of each GUI element, in the order they are emitted
+pub fn gui_properties(obj_type: constructor::ObjectType) -> &'static [&'static str] {
+    match obj_type {
+        constructor::ObjectType::Text => &["position", "anchor", "text", "font", "font_size", "color"],
+        constructor::ObjectType::Panel => &["position", "anchor", "size", "color"],
+        constructor::ObjectType::Button => {
+            &["position", "anchor", "size", "text", "font", "font_size", "color", "text_color", "on_click"]
+        }
+        _ => &["position", "anchor", "size", "image"],
+    }
+}
+
+enum Gravity {
+    Vector((f64, f64, f64)),
+    Off,
+}
+
 struct PhysicsEngine {
-    pub gravity: Expression,
-    pub object: Expression,
+    pub bodies: Vec<String>,
+    pub gravity: Gravity,
+    pub timestep: Option<f64>,
+    pub defaults: Vec<constructor::Property>,
 }
 
 pub struct Transpiler {
     pub engine: constructor::Engine,
+    /// The file and position of the object or property being transpiled, for errors
+    location: RefCell<(String, Position)>,
+}
+
+/// Panics with `message`, located at `position` in the file of `object`.
+fn report(object: &constructor::Object, position: Position, message: String) -> ! {
+    constructor::report(&object.file, position, message)
 }
 
 impl Transpiler {
     pub fn new(engine: constructor::Engine) -> Self {
-        Self { engine }
+        Self {
+            engine,
+            location: RefCell::new((String::new(), (0, 0))),
+        }
+    }
+
+    /// Notes that `position` in the file of `object` is being transpiled.
+    fn locate(&self, object: &constructor::Object, position: Position) {
+        *self.location.borrow_mut() = (object.file.clone(), position);
+    }
+
+    /// Panics with `message`, located at what is being transpiled.
+    fn error(&self, message: String) -> ! {
+        let (file, position) = self.location.borrow().clone();
+        constructor::report(&file, position, message)
+    }
+
+    /// The properties of `object`, noting where each is as it is read.
+    fn properties<'a>(&'a self, object: &'a constructor::Object) -> impl Iterator<Item = &'a constructor::Property> {
+        object.properties.iter().inspect(move |prop| self.locate(object, prop.position))
     }
 
     pub fn transpile(&self) -> String {
         let mut output = String::new();
         output.push_str(BEGIN);
-        for object in &self.engine.scene.objects {
+        output.push_str(self.transpile_window().as_str());
+
+        let animated = self.engine.scenes.iter().flat_map(|scene| &scene.objects).any(|o| o.obj_type == constructor::ObjectType::Animation);
+        if animated {
+            output.push_str(ANIMATE);
+        }
+
+        for object in &self.engine.zig {
+            if let Expression::Zig(code) = &object.properties[0].value {
+                output.push('\n');
+                output.push_str(self.splice(code, 0).as_str());
+            }
+        }
+
+        let actions = self.actions();
+        if !actions.is_empty() {
+            output.push_str(self.transpile_dispatch(&actions).as_str());
+        }
+
+        let names: Vec<&str> = self.engine.scenes.iter().map(|scene| scene.name.as_str()).collect();
+        output.push_str(format!("\npub const SceneId = enum {{ {} }};\n", names.join(", ")).as_str());
+
+        let cases: String = names
+            .iter()
+            .map(|name| format!("            .{} => try scene_{}(),\n", name, name))
+            .collect();
+        output.push_str(MAIN.replace("START", &self.engine.start).replace("CASES", &cases).as_str());
+
+        for scene in &self.engine.scenes {
+            output.push_str(self.transpile_scene(scene).as_str());
+        }
+
+        output
+    }
+
+    /// The window and render settings, from the WINDOW and RENDER blocks or
+    /// their defaults.
+    pub fn settings(&self) -> (Window, Render) {
+        let mut window = Window {
+            title: "Z3D".to_string(),
+            width: 400,
+            height: 400,
+            x: 0,
+            y: 0,
+            flags: Vec::new(),
+        };
+        for prop in self.engine.window.iter().flat_map(|w| self.properties(w)) {
+            match prop.name.as_str() {
+                "title" => {
+                    window.title = match &prop.value {
+                        Expression::String(title) => title.clone(),
+                        _ => self.error("Expected string for WINDOW title".to_string()),
+                    };
+                }
+                "width" => window.width = self.evaluate_size(&prop.value, "WINDOW width"),
+                "height" => window.height = self.evaluate_size(&prop.value, "WINDOW height"),
+                "x" => window.x = self.evaluate_integer(&prop.value, "WINDOW x"),
+                "y" => window.y = self.evaluate_integer(&prop.value, "WINDOW y"),
+                "resizable" | "fullscreen" | "vsync" => {
+                    window.flags.push((prop.name.clone(), self.evaluate_bool(&prop.value)));
+                }
+                name => self.error(format!("Unknown WINDOW property: {}", name)),
+            }
+        }
+        if window.width != window.height {
+            let object = self.engine.window.as_ref().unwrap();
+            report(object, object.position, format!("WINDOW width and height must be equal, got {}x{}", window.width, window.height));
+        }
+
+        let mut render = Render {
+            fov: 90.0,
+            scale: 1.0,
+        };
+        for prop in self.engine.render.iter().flat_map(|r| self.properties(r)) {
+            match prop.name.as_str() {
+                "fov" => {
+                    render.fov = self.evaluate_number(&prop.value, "RENDER fov");
+                    if render.fov <= 0.0 || render.fov >= 180.0 {
+                        self.error(format!("RENDER fov must be between 0 and 180 degrees, got {}", render.fov));
+                    }
+                }
+                "scale" => {
+                    render.scale = self.evaluate_number(&prop.value, "RENDER scale");
+                    if render.scale <= 0.0 {
+                        self.error(format!("RENDER scale must be positive, got {}", render.scale));
+                    }
+                }
+                name => self.error(format!("Unknown RENDER property: {}", name)),
+            }
+        }
+
+        (window, render)
+    }
+
+    pub fn transpile_window(&self) -> String {
+        let mut output = String::new();
+
+        let (window, render) = self.settings();
+        let resolution = (window.width as f64 * render.scale).round() as usize;
+        if resolution == 0 {
+            let object = self.engine.render.as_ref().unwrap();
+            report(object, object.position, format!("RENDER scale {} leaves no pixels to render", render.scale));
+        }
+
+        output.push_str(
+            format!(
+                "\n// Don't make this too big. Ensure HEIGHT == WIDTH\nconst HEIGHT = {};\nconst WIDTH = {};\n",
+                window.height, window.width
+            )
+            .as_str()
+        );
+        output.push_str(
+            format!(
+                "\nconst TITLE = {};\nconst X = {};\nconst Y = {};\n",
+                string_literal(&window.title), window.x, window.y
+            )
+            .as_str()
+        );
+        if window.flags.is_empty() {
+            output.push_str("const WINDOW_FLAGS = z3d.engine.WindowFlags.default();\n");
+        } else {
+            output.push_str("const WINDOW_FLAGS = blk: {\n    var flags = z3d.engine.WindowFlags.default();\n");
+            for (flag, value) in &window.flags {
+                output.push_str(format!("    flags.{} = {};\n", flag, value).as_str());
+            }
+            output.push_str("    break :blk flags;\n};\n");
+        }
+        output.push_str(
+            format!(
+                "\nconst FOV = {};\n// Rays are cast for RENDER_HEIGHT x RENDER_WIDTH pixels\nconst RENDER_HEIGHT = {};\nconst RENDER_WIDTH = {};\n",
+                render.fov, resolution, resolution
+            )
+            .as_str()
+        );
+
+        output
+    }
+
+    /// Each scene is built and run by its own function, which returns once
+    /// the scene's window is closed.
+    pub fn transpile_scene(&self, scene: &constructor::Scene) -> String {
+        let mut output = String::new();
+        output.push_str(SCENE_BEGIN.replace("NAME", &scene.name).as_str());
+        // Physics comes after every body it refers to, and before the scene is
+        // created by ACTIVE
+        let (physics, objects): (Vec<&constructor::Object>, Vec<&constructor::Object>) =
+            scene.objects.iter().partition(|o| o.obj_type == constructor::ObjectType::Physics);
+        let (active, objects): (Vec<&constructor::Object>, Vec<&constructor::Object>) =
+            objects.into_iter().partition(|o| o.obj_type == constructor::ObjectType::Active);
+        for object in objects.into_iter().chain(physics).chain(active) {
             output.push('\n');
-            output.push_str(self.transpile_element(object).as_str());
+            output.push_str(self.transpile_element(object, scene).as_str());
         }
 
-        output.push_str(END);
+        output.push_str(SCENE_END);
+
+        let mut frame: Vec<String> = scene
+            .objects
+            .iter()
+            .filter(|o| o.obj_type == constructor::ObjectType::Physics)
+            .map(|o| self.transpile_physics_frame(o))
+            .collect();
+        frame.extend(
+            scene
+                .objects
+                .iter()
+                .filter(|o| o.obj_type == constructor::ObjectType::Animation)
+                .map(|o| self.transpile_animation_frame(o, scene))
+        );
+        for prop in scene.objects.iter().flat_map(|o| self.properties(o)).filter(|p| p.name == "on_update") {
+            match &prop.value {
+                Expression::Zig(code) => frame.push(self.splice(code, 8)),
+                value => self.error(format!("Expected ZIG {{ ... }} for .on_update, got {}", value)),
+            }
+        }
+        if frame.is_empty() {
+            output.push_str(MAINLOOP);
+        } else {
+            output.push_str(FRAME_BEGIN);
+            output.push_str(frame.concat().as_str());
+            output.push_str(FRAME_END);
+        }
         output
     }
 
-    pub fn transpile_element(&self, object: &constructor::Object) -> String {
+    pub fn transpile_element(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
+        self.locate(object, object.position);
+        for prop in self.properties(object) {
+            if let Expression::Zig(code) = &prop.value {
+                if prop.name != "on_update" && object.obj_type != constructor::ObjectType::Zig {
+                    panic!("{}: ZIG blocks can only be used for .on_update, not .{}", code.span(), prop.name);
+                }
+            }
+        }
+        // Hooks run every frame, so they are spliced into the frame loop instead
+        let object = &constructor::Object {
+            properties: object.properties.iter().filter(|p| p.name != "on_update").cloned().collect(),
+            ..object.clone()
+        };
+
         match object.obj_type {
-            constructor::ObjectType::Sphere => self.transpile_sphere(object),
+            constructor::ObjectType::Sphere => self.transpile_sphere(object, scene),
             constructor::ObjectType::Material => self.transpile_material(object),
             constructor::ObjectType::Image => self.transpile_image(object),
             constructor::ObjectType::Controller => self.transpile_controller(object),
-            constructor::ObjectType::Camera => self.transpile_camera(object),
-            constructor::ObjectType::Physics => self.transpile_physics(object),
+            constructor::ObjectType::Camera => self.transpile_camera(object, scene),
+            constructor::ObjectType::Physics => self.transpile_physics(object, scene),
             constructor::ObjectType::Light => self.transpile_light(object),
             constructor::ObjectType::Rectangle => self.transpile_rectangle(object),
             constructor::ObjectType::Active => self.transpile_active(object),
+            constructor::ObjectType::Text
+            | constructor::ObjectType::Panel
+            | constructor::ObjectType::Button
+            | constructor::ObjectType::Sprite => self.transpile_gui(object, scene),
+            constructor::ObjectType::Animation => self.transpile_animation(object, scene),
+            constructor::ObjectType::Zig => match &object.properties[0].value {
+                Expression::Zig(code) => self.splice(code, 4),
+                _ => unreachable!(),
+            },
+            constructor::ObjectType::Window | constructor::ObjectType::Render => {
+                self.error(format!("{} is not part of a scene", object.obj_type.keyword()))
+            }
         }
     }
 
-    pub fn transpile_sphere(&self, object: &constructor::Object) -> String {
+    pub fn transpile_sphere(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
         let mut output = String::new();
-        output.push_str(
-            format!(
-                "    const {} = z3d.graphics.objects.Sphere.init(", 
-                object.name
-            )
-            .as_str()
-        );
 
         let mut sphere = Sphere {
             position: (Expression::Empty, Expression::Empty, Expression::Empty),
@@ -106,12 +505,12 @@ impl Transpiler {
             material: Expression::Empty,
         };
 
-        for prop in &object.properties {
+        for prop in self.properties(object) {
             match prop.name.as_str() {
                 "position" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     sphere.position.0 = values.next().unwrap().clone();
@@ -128,12 +527,18 @@ impl Transpiler {
             }
         }
 
+        let position = self.vector_pointer(object, scene, "position", &sphere.position, &mut output);
         output.push_str(
             format!(
-                "@constCast(&Vec3.init({}, {}, {})), {}, &{}",
-                sphere.position.0,
-                sphere.position.1,
-                sphere.position.2,
+                "    const {} = z3d.graphics.objects.Sphere.init(", 
+                object.name
+            )
+            .as_str()
+        );
+        output.push_str(
+            format!(
+                "{}, {}, &{}",
+                position,
                 sphere.radius,
                 sphere.material
             )
@@ -159,17 +564,32 @@ impl Transpiler {
             format!("    const {} = z3d.graphics.material.Material{{ .texture = z3d.graphics.material.Texture {{", object.name).as_str(),
         );
 
-        if object.properties.len() > 1 {
-            panic!("Too many properties for material");
+        // Properties from EXTENDS come first, so the last texture given wins
+        let mut texture = None;
+        let mut reflectivity = None;
+        for prop in self.properties(object) {
+            match prop.name.as_str() {
+                "color" | "image" => texture = Some(prop),
+                "reflectivity" => {
+                    let value = self.evaluate_number(&prop.value, "MATERIAL reflectivity");
+                    if !(0.0..=1.0).contains(&value) {
+                        self.error(format!("MATERIAL reflectivity must be between 0 and 1, got {}", value));
+                    }
+                    reflectivity = Some(value);
+                }
+                name => self.error(format!("Unknown MATERIAL property: {}", name)),
+            }
         }
-
-        let property = &object.properties[0];
+        let property = match texture {
+            Some(property) => property,
+            None => report(object, object.position, format!("MATERIAL `{}` needs a .color or an .image", object.name)),
+        };
 
         match property.name.as_str() {
             "color" => {
                 let mut values = match &property.value {
                     constructor::Expression::Group(values) => values.iter(),
-                    _ => panic!("Expected group"),
+                    _ => report(object, property.position, "Expected group".to_string()),
                 };
 
                 output.push_str(
@@ -185,14 +605,18 @@ impl Transpiler {
             "image" => {
                 let image = match &property.value {
                     constructor::Expression::Identifier(x) => x.clone(),
-                    _ => panic!("Expected identifier"),
+                    _ => report(object, property.position, "Expected identifier".to_string()),
                 };
                 output.push_str(format!(" .TEXTURE_FILE = {}", image).as_str());
             }
             _ => {}
         }
 
-        output.push_str(" }};\n");
+        output.push_str(" }");
+        if let Some(reflectivity) = reflectivity {
+            output.push_str(format!(", .reflectivity = {} ", reflectivity).as_str());
+        }
+        output.push_str("};\n");
 
         output
     }
@@ -202,12 +626,12 @@ impl Transpiler {
         output.push_str(format!("    var {} = try z3d.images.Image.init(", object.name).as_str());
 
         if object.properties.len() > 1 {
-            panic!("Too many properties for image");
+            report(object, object.position, "Too many properties for image".to_string());
         }
 
         let property = &object.properties[0];
         if let Expression::String(file) = &property.value {
-            output.push_str(format!("\"{}\"", file).as_str());
+            output.push_str(&string_literal(file));
         }
 
         output.push_str(");\n");
@@ -226,34 +650,155 @@ impl Transpiler {
             .as_str(),
         );
 
-        for prop in &object.properties {
+        for prop in self.properties(object) {
             match prop.name.as_str() {
                 "keyboard_movement" => {
                     let key = match &prop.value {
                         Expression::Identifier(x) => x.clone(),
-                        _ => panic!("Expected identifier"),
+                        _ => self.error("Expected identifier".to_string()),
                     };
                     output.push_str(format!(" .keyboard_movement = {},", key).as_str());
                 }
                 "mouse_movement" => {
                     let mouse = match &prop.value {
                         Expression::Identifier(x) => x.clone(),
-                        _ => panic!("Expected identifier"),
+                        _ => self.error("Expected identifier".to_string()),
                     };
                     output.push_str(format!(" .mouse_movement = {},", mouse).as_str());
                 }
-                _ => {}
+                "sensitivity" => {
+                    let sensitivity = self.evaluate_number(&prop.value, "CONTROLLER sensitivity");
+                    if sensitivity <= 0.0 {
+                        self.error(format!("CONTROLLER sensitivity must be positive, got {}", sensitivity));
+                    }
+                    output.push_str(format!(" .mouse_sensitivity = {},", sensitivity).as_str());
+                }
+                "invert_y" => {
+                    output.push_str(format!(" .invert_y = {},", self.evaluate_bool(&prop.value)).as_str());
+                }
+                "speed" => {
+                    let speed = self.evaluate_number(&prop.value, "CONTROLLER speed");
+                    if speed <= 0.0 {
+                        self.error(format!("CONTROLLER speed must be positive, got {}", speed));
+                    }
+                    output.push_str(format!(" .movement_speed = {},", speed).as_str());
+                }
+                "bindings" => output.push_str(self.transpile_bindings(object, &prop.value).as_str()),
+                "actions" => {}
+                name => self.error(format!("Unknown CONTROLLER property: {}", name)),
             }
         }
+        let actions = object.properties.iter().any(|p| p.name == "actions" && matches!(&p.value, Expression::List(actions) if !actions.is_empty()));
+        if actions {
+            output.push_str(" .on_action = &dispatch_action,");
+        }
 
         output.push_str(" .width = WIDTH, .height = HEIGHT };\n");
 
         output
     }
 
-    pub fn transpile_camera(&self, object: &constructor::Object) -> String {
+    /// The custom actions declared by every CONTROLLER, in order.
+    pub fn actions(&self) -> Vec<String> {
+        let mut actions = Vec::new();
+        let controllers = self.engine.scenes.iter().flat_map(|scene| &scene.objects).filter(|o| o.obj_type == constructor::ObjectType::Controller);
+        for prop in controllers.flat_map(|o| self.properties(o)).filter(|p| p.name == "actions") {
+            if let Expression::List(values) = &prop.value {
+                for action in values.iter().map(|v| self.evaluate_name(v, "CONTROLLER actions")) {
+                    if !actions.contains(&action) {
+                        actions.push(action);
+                    }
+                }
+            }
+        }
+        actions
+    }
+
+    /// Custom actions are handled by functions of top level ZIG blocks, named
+    /// after the action as in `fn onJump() void` for `jump`. Controllers call
+    /// `dispatch_action` with the name of the action whose key was pressed.
+    pub fn transpile_dispatch(&self, actions: &[String]) -> String {
+        let mut output = String::from("\n// Calls the handler of a custom action\nfn dispatch_action(action: []const u8) void {\n");
+        for action in actions {
+            let handler = handler(action);
+            let declared = self.engine.zig.iter().any(|object| match &object.properties[0].value {
+                Expression::Zig(code) => code.code.contains(&format!("fn {}(", handler)),
+                _ => false,
+            });
+            if !declared {
+                // Pointing at the first CONTROLLER declaring the action
+                let controllers = self.engine.scenes.iter().flat_map(|scene| &scene.objects).filter(|o| o.obj_type == constructor::ObjectType::Controller);
+                for controller in controllers {
+                    let prop = controller.properties.iter().find(|p| {
+                        p.name == "actions" && matches!(&p.value, Expression::List(values) if values.iter().any(|v| v.to_string() == *action))
+                    });
+                    if let Some(prop) = prop {
+                        self.locate(controller, prop.position);
+                        break;
+                    }
+                }
+                self.error(format!("Action `{}` has no handler, declare `fn {}() void` in a top level ZIG block", action, handler));
+            }
+            output.push_str(format!("    if (std.mem.eql(u8, action, \"{}\")) return {}();\n", action, handler).as_str());
+        }
+        output.push_str("}\n");
+
+        output
+    }
+
+    /// `.bindings = { W: forward, Space: jump }`. Movement actions are built
+    /// in, any other action has to be declared in the controller's `.actions`.
+    pub fn transpile_bindings(&self, object: &constructor::Object, bindings: &Expression) -> String {
+        let bindings = match bindings {
+            Expression::Map(bindings) => bindings,
+            _ => self.error(format!("Expected {{ key: action }} for CONTROLLER bindings, got {}", bindings)),
+        };
+
+        let mut actions = Vec::new();
+        for prop in object.properties.iter().filter(|p| p.name == "actions") {
+            actions = match &prop.value {
+                Expression::List(values) => values.iter().map(|v| self.evaluate_name(v, "CONTROLLER actions")).collect(),
+                _ => report(object, prop.position, "Expected list of action names for CONTROLLER actions".to_string()),
+            };
+            if let Some(action) = actions.iter().find(|action| MOVEMENT_ACTIONS.contains(&action.as_str())) {
+                report(object, prop.position, format!("`{}` is a built-in action and cannot be declared in .actions", action));
+            }
+        }
+
+        let mut output = String::from(" .bindings = &[_]z3d.event_handler.Binding{");
+        for (i, (key, action)) in bindings.iter().enumerate() {
+            if let Some((other, _)) = bindings[..i].iter().find(|(other, _)| other.eq_ignore_ascii_case(key)) {
+                self.error(format!("Key {} is bound twice in `{}` (as {} and {})", key, object.name, other, key));
+            }
+            let code = match KEYS.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)) {
+                Some((_, code)) => code,
+                None => self.error(format!(
+                    "Unknown key `{}` in `{}`, expected one of: {}",
+                    key,
+                    object.name,
+                    KEYS.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ")
+                )),
+            };
+            let action = self.evaluate_name(action, "CONTROLLER bindings");
+            let action = if MOVEMENT_ACTIONS.contains(&action.as_str()) {
+                format!(".{{ .move = .{} }}", action)
+            } else if actions.contains(&action) {
+                format!(".{{ .custom = \"{}\" }}", action)
+            } else {
+                self.error(format!(
+                    "Unknown action `{}` bound to {} in `{}`, declare it in .actions",
+                    action, key, object.name
+                ));
+            };
+            output.push_str(format!(" .{{ .key = .{}, .action = {} }},", code, action).as_str());
+        }
+        output.push_str(" },");
+
+        output
+    }
+
+    pub fn transpile_camera(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
         let mut output = String::new();
-        output.push_str(format!("    const {} = z3d.engine.Camera{{", object.name).as_str());
 
         let mut camera = Camera {
             position: (Expression::Empty, Expression::Empty, Expression::Empty),
@@ -261,12 +806,12 @@ impl Transpiler {
             event_handler: Expression::Empty,
         };
 
-        for prop in &object.properties {
+        for prop in self.properties(object) {
             match prop.name.as_str() {
                 "position" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     camera.position.0 = values.next().unwrap().clone();
@@ -276,7 +821,7 @@ impl Transpiler {
                 "direction" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     camera.direction.0 = values.next().unwrap().clone();
@@ -291,11 +836,14 @@ impl Transpiler {
             }
         }
 
+        let position = self.vector_pointer(object, scene, "position", &camera.position, &mut output);
+        let direction = self.vector_pointer(object, scene, "direction", &camera.direction, &mut output);
+        output.push_str(format!("    const {} = z3d.engine.Camera{{", object.name).as_str());
         output.push_str(
             format!(
-                " .position = &z3d.transform.PositionHandler{{ .single = z3d.transform.SinglePointHandler{{ .point = @constCast(&Vec3.init({}, {}, {})) , .direction = @constCast(&Vec3.init({}, {}, {})) }} }}, .event_handler = &{} }};\n", 
-                camera.position.0, camera.position.1, camera.position.2, 
-                camera.direction.0, camera.direction.1, camera.direction.2, 
+                " .position = &z3d.transform.PositionHandler{{ .single = z3d.transform.SinglePointHandler{{ .point = {} , .direction = {} }} }}, .event_handler = &{} }};\n", 
+                position,
+                direction,
                 camera.event_handler
             )
             .as_str()
@@ -304,53 +852,167 @@ impl Transpiler {
         output
     }
 
-    pub fn transpile_physics(&self, object: &constructor::Object) -> String {
+    pub fn transpile_physics(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
         let mut output = String::new();
-        output.push_str(
-            format!(
-                "    var {} = z3d.physics.PhysicsEngine.init(",
-                object.name
-            )
-            .as_str(),
-        );
 
         let mut physics = PhysicsEngine {
-            gravity: Expression::Empty,
-            object: Expression::Empty,
+            bodies: Vec::new(),
+            gravity: Gravity::Vector(DEFAULT_GRAVITY),
+            timestep: None,
+            defaults: Vec::new(),
         };
 
-        if object.properties.len() > 2 {
-            panic!("Too many properties for physics");
-        }
-
-        for prop in &object.properties {
+        // Where the bodies are named, for errors about them
+        let mut bodies_at = object.position;
+        for prop in self.properties(object) {
             match prop.name.as_str() {
-                "gravity" => {
-                    physics.gravity = prop.value.clone();
+                "bodies" => {
+                    bodies_at = prop.position;
+                    physics.bodies = match &prop.value {
+                        Expression::List(bodies) => bodies.iter().map(|body| self.evaluate_name(body, "PHYSICS bodies")).collect(),
+                        _ => self.error("Expected list of objects for PHYSICS bodies".to_string()),
+                    };
                 }
+                // A single body, as in `.object = sphere`
                 "object" => {
-                    physics.object = prop.value.clone();
+                    bodies_at = prop.position;
+                    physics.bodies = vec![self.evaluate_name(&prop.value, "PHYSICS object")];
                 }
-                _ => {}
+                "gravity" => {
+                    physics.gravity = match &prop.value {
+                        Expression::Group(_) => Gravity::Vector(self.evaluate_vector(&prop.value, "PHYSICS gravity")),
+                        value if self.evaluate_bool(value) => Gravity::Vector(DEFAULT_GRAVITY),
+                        _ => Gravity::Off,
+                    };
+                }
+                "timestep" => {
+                    let timestep = self.evaluate_number(&prop.value, "PHYSICS timestep");
+                    if timestep <= 0.0 {
+                        self.error(format!("PHYSICS timestep must be positive, got {}", timestep));
+                    }
+                    physics.timestep = Some(timestep);
+                }
+                name if BODY_PROPERTIES.contains(&name) => physics.defaults.push(prop.clone()),
+                name => self.error(format!("Unknown PHYSICS property: {}", name)),
             }
         }
 
-        output.push_str(
-            format!(
-                "obj_{}, .{{}});\n",
-                physics.object
-            )
-            .as_str(),
-        );
-        if self.evaluate_bool(&physics.gravity) {
+        if physics.bodies.is_empty() {
+            report(object, object.position, format!("PHYSICS `{}` has no bodies", object.name));
+        }
+
+        for name in &physics.bodies {
+            let body = match scene.objects.iter().find(|o| o.name == *name) {
+                Some(body) => body,
+                None => report(object, bodies_at, format!("PHYSICS `{}` refers to unknown object `{}`", object.name, name)),
+            };
+            if !matches!(body.obj_type, constructor::ObjectType::Sphere | constructor::ObjectType::Rectangle) {
+                report(object, bodies_at, format!(
+                    "PHYSICS `{}` can only simulate SPHERE and RECTANGLE objects, `{}` is a {}",
+                    object.name, name, body.obj_type.keyword()
+                ));
+            }
+            let claimed = scene.objects.iter().filter(|o| o.obj_type == constructor::ObjectType::Physics).any(|other| {
+                other.name != object.name
+                    && other.properties.iter().any(|p| match &p.value {
+                        Expression::List(bodies) if p.name == "bodies" => bodies.iter().any(|b| b.to_string() == *name),
+                        Expression::Identifier(b) if p.name == "object" => b == name,
+                        _ => false,
+                    })
+            });
+            if claimed {
+                report(object, bodies_at, format!("`{}` is simulated by more than one PHYSICS block", name));
+            }
+
+            // The body's own settings override the defaults of the PHYSICS block
+            let defaults = physics.defaults.iter().map(|prop| (object, prop));
+            let own = body.properties.iter().filter(|p| BODY_PROPERTIES.contains(&p.name.as_str())).map(|prop| (body, prop));
+            let mut settings: Vec<(&constructor::Object, &constructor::Property)> = Vec::new();
+            for (owner, prop) in defaults.chain(own) {
+                settings.retain(|(_, p)| p.name != prop.name);
+                settings.push((owner, prop));
+            }
+
+            let mut options = Vec::new();
+            let mut is_static = false;
+            for (owner, prop) in settings {
+                self.locate(owner, prop.position);
+                match prop.name.as_str() {
+                    "mass" => {
+                        let mass = self.evaluate_number(&prop.value, "mass");
+                        if mass <= 0.0 {
+                            self.error(format!("Mass of `{}` must be positive, got {}", name, mass));
+                        }
+                        options.push(format!(".mass = {}", mass));
+                    }
+                    "restitution" => {
+                        let restitution = self.evaluate_number(&prop.value, "restitution");
+                        if !(0.0..=1.0).contains(&restitution) {
+                            self.error(format!("Restitution of `{}` must be between 0 and 1, got {}", name, restitution));
+                        }
+                        options.push(format!(".restitution = {}", restitution));
+                    }
+                    "friction" => {
+                        let friction = self.evaluate_number(&prop.value, "friction");
+                        if friction < 0.0 {
+                            self.error(format!("Friction of `{}` cannot be negative, got {}", name, friction));
+                        }
+                        options.push(format!(".friction = {}", friction));
+                    }
+                    "velocity" => {
+                        let (x, y, z) = self.evaluate_vector(&prop.value, "velocity");
+                        options.push(format!(".velocity = Vec3.init({}, {}, {})", x, y, z));
+                    }
+                    _ => {
+                        is_static = self.evaluate_bool(&prop.value);
+                        options.push(format!(".is_static = {}", is_static));
+                    }
+                }
+            }
+            if let Some(timestep) = physics.timestep {
+                options.push(format!(".timestep = {}", timestep));
+            }
+
+            let engine = format!("{}_{}", object.name, name);
             output.push_str(
                 format!(
-                    "    {}.apply_gravity(null);\n",
-                    object.name
+                    "    var {} = z3d.physics.PhysicsEngine.init(&obj_{}, .{{{}}});\n",
+                    engine,
+                    name,
+                    if options.is_empty() { String::new() } else { format!(" {} ", options.join(", ")) }
                 )
-                .as_str()
+                .as_str(),
             );
+
+            // Static bodies never move, so gravity does not apply to them
+            match physics.gravity {
+                Gravity::Vector((x, y, z)) if !is_static => output.push_str(
+                    format!("    {}.apply_gravity(Vec3.init({}, {}, {}));\n", engine, x, y, z).as_str()
+                ),
+                _ => {}
+            }
         }
+        // The time of the last step, see `transpile_physics_frame`
+        output.push_str(format!("    var {}_time: f32 = 0;\n", object.name).as_str());
+
+        output
+    }
+
+    /// Steps every body of the PHYSICS block by the time since the last frame.
+    pub fn transpile_physics_frame(&self, object: &constructor::Object) -> String {
+        let mut output = String::new();
+        for prop in self.properties(object) {
+            let bodies = match (prop.name.as_str(), &prop.value) {
+                ("bodies", Expression::List(bodies)) => bodies.iter().collect(),
+                ("object", body) => vec![body],
+                _ => continue,
+            };
+            for body in bodies {
+                let name = self.evaluate_name(body, "PHYSICS bodies");
+                output.push_str(format!("        {}_{}.step(time - {}_time);\n", object.name, name, object.name).as_str());
+            }
+        }
+        output.push_str(format!("        {}_time = time;\n", object.name).as_str());
 
         output
     }
@@ -365,16 +1027,12 @@ impl Transpiler {
             .as_str(),
         );
 
-        if object.properties.len() > 2 {
-            panic!("Too many properties for light");
-        }
-
-        for prop in &object.properties {
+        for prop in self.properties(object) {
             match prop.name.as_str() {
                 "position" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     output.push_str(
@@ -390,7 +1048,7 @@ impl Transpiler {
                 "intensity" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     output.push_str(
@@ -403,7 +1061,23 @@ impl Transpiler {
                         .as_str()
                     );
                 }
-                _ => {}
+                "direction" => {
+                    let mut values = match &prop.value {
+                        constructor::Expression::Group(values) => values.iter(),
+                        _ => self.error("Expected group".to_string()),
+                    };
+
+                    output.push_str(
+                        format!(
+                            ".direction = Vec3.init({}, {}, {}), ",
+                            values.next().unwrap(),
+                            values.next().unwrap(),
+                            values.next().unwrap()
+                        )
+                        .as_str()
+                    );
+                }
+                name => self.error(format!("Unknown LIGHT property: {}", name)),
             }
         }
 
@@ -423,8 +1097,10 @@ impl Transpiler {
             .as_str()
         );
 
-        if object.properties.len() > 4 {
-            panic!("Too many properties for rectangle");
+        // Physics settings are read by the PHYSICS block instead
+        let properties = object.properties.iter().filter(|p| !BODY_PROPERTIES.contains(&p.name.as_str()));
+        if properties.count() > 4 {
+            report(object, object.position, "Too many properties for rectangle".to_string());
         }
 
         let mut rectangle = Rectangle {
@@ -433,12 +1109,12 @@ impl Transpiler {
             inverted: Expression::Empty,
         };
 
-        for prop in &object.properties {
+        for prop in self.properties(object) {
             match prop.name.as_str() {
                 "v0" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     rectangle.points.0.0 = values.next().unwrap().clone();
@@ -448,7 +1124,7 @@ impl Transpiler {
                 "v1" => {
                     let mut values = match &prop.value {
                         constructor::Expression::Group(values) => values.iter(),
-                        _ => panic!("Expected group"),
+                        _ => self.error("Expected group".to_string()),
                     };
 
                     rectangle.points.1.0 = values.next().unwrap().clone();
@@ -497,22 +1173,325 @@ impl Transpiler {
         output
     }
 
-    pub fn transpile_active(&self, object: &constructor::Object) -> String {
+    pub fn transpile_gui(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
         let mut output = String::new();
+        let properties = gui_properties(object.obj_type);
 
-        if object.properties.len() > 1 {
-            panic!("Too many properties for active");
+        for prop in self.properties(object) {
+            if !properties.contains(&prop.name.as_str()) {
+                self.error(format!("Unknown {} property: {}", object.obj_type.keyword(), prop.name));
+            }
         }
 
-        let property = &object.properties[0];
-        let object = match &property.value {
-            Expression::Identifier(x) => x.clone(),
-            _ => panic!("Expected identifier"),
+        let mut fields = Vec::new();
+        for name in properties {
+            let value = match object.properties.iter().rev().find(|p| p.name == *name) {
+                Some(prop) => {
+                    self.locate(object, prop.position);
+                    &prop.value
+                }
+                // Elements sit in the top left corner unless placed elsewhere
+                None if *name == "position" => &Expression::Group(vec![Expression::Number("0".to_string()), Expression::Number("0".to_string())]),
+                None if *name == "anchor" => &Expression::Identifier("top_left".to_string()),
+                None if ["size", "text", "image"].contains(name) => {
+                    report(object, object.position, format!("{} `{}` needs a .{}", object.obj_type.keyword(), object.name, name))
+                }
+                None => continue,
+            };
+            let what = format!("{} {}", object.obj_type.keyword(), name);
+
+            let field = match *name {
+                "position" => match value {
+                    Expression::Group(values) if values.len() == 2 => format!(
+                        ".{{ .x = {}, .y = {} }}",
+                        self.evaluate_number(&values[0], &what),
+                        self.evaluate_number(&values[1], &what)
+                    ),
+                    _ => self.error(format!("Expected (x, y) for {}, got {}", what, value)),
+                },
+                "size" => match value {
+                    Expression::Group(values) if values.len() == 2 => format!(
+                        ".{{ .width = {}, .height = {} }}",
+                        self.evaluate_size(&values[0], &what),
+                        self.evaluate_size(&values[1], &what)
+                    ),
+                    _ => self.error(format!("Expected (width, height) for {}, got {}", what, value)),
+                },
+                "anchor" => {
+                    let anchor = self.evaluate_name(value, &what);
+                    if !ANCHORS.contains(&anchor.as_str()) {
+                        self.error(format!("Unknown anchor `{}` for `{}`, expected one of: {}", anchor, object.name, ANCHORS.join(", ")));
+                    }
+                    format!(".{}", anchor)
+                }
+                "text" | "font" => match value {
+                    Expression::String(text) => string_literal(text),
+                    _ => self.error(format!("Expected string for {}, got {}", what, value)),
+                },
+                "font_size" => self.evaluate_size(value, &what).to_string(),
+                "color" | "text_color" => {
+                    let (r, g, b) = self.evaluate_vector(value, &what);
+                    for channel in [r, g, b] {
+                        if !(0.0..=255.0).contains(&channel) {
+                            self.error(format!("Colour channels of {} must be between 0 and 255, got {}", what, value));
+                        }
+                    }
+                    format!("z3d.graphics.RGB{{ .r = {}, .g = {}, .b = {} }}", r, g, b)
+                }
+                "image" => {
+                    let image = self.evaluate_name(value, &what);
+                    let earlier = scene.objects.iter().take_while(|o| o.name != object.name);
+                    match earlier.filter(|o| o.name == image).last() {
+                        Some(o) if o.obj_type == constructor::ObjectType::Image => format!("&{}", image),
+                        _ => self.error(format!("`{}` shows `{}`, which is not an IMAGE declared before it", object.name, image)),
+                    }
+                }
+                // A custom action, as declared in a CONTROLLER's `.actions`
+                _ => {
+                    let action = self.evaluate_name(value, &what);
+                    let declared = scene
+                        .objects
+                        .iter()
+                        .filter(|o| o.obj_type == constructor::ObjectType::Controller)
+                        .flat_map(|o| &o.properties)
+                        .any(|p| p.name == "actions" && matches!(&p.value, Expression::List(actions) if actions.iter().any(|a| a.to_string() == action)));
+                    if !declared {
+                        self.error(format!("Unknown action `{}` for `{}`, declare it in a CONTROLLER's .actions", action, object.name));
+                    }
+                    format!("&{}", handler(&action))
+                }
+            };
+            fields.push(format!(".{} = {}", name, field));
+        }
+
+        let kind = object.obj_type.keyword().to_lowercase();
+        output.push_str(
+            format!(
+                "    const {} = z3d.{}{{ {} }};\n",
+                object.name, object.obj_type, fields.join(", ")
+            )
+            .as_str()
+        );
+        output.push_str(
+            format!(
+                "    try gui_layer.append(z3d.gui.Element{{ .{} = {} }});\n",
+                kind, object.name
+            )
+            .as_str()
+        );
+
+        output
+    }
+
+    /// A pointer to a vector property. Animated vectors get a variable of
+    /// their own, declared into `output`, which the frame loop updates.
+    pub fn vector_pointer(
+        &self,
+        object: &constructor::Object,
+        scene: &constructor::Scene,
+        property: &str,
+        vector: &(Expression, Expression, Expression),
+        output: &mut String,
+    ) -> String {
+        let animated = scene.objects.iter().filter(|o| o.obj_type == constructor::ObjectType::Animation).any(|animation| {
+            self.animation_target(animation).map(|(name, p)| name == object.name && p == property).unwrap_or(false)
+        });
+        if !animated {
+            return format!("@constCast(&Vec3.init({}, {}, {}))", vector.0, vector.1, vector.2);
+        }
+
+        output.push_str(
+            format!(
+                "    var {}_{} = Vec3.init({}, {}, {});\n",
+                object.name, property, vector.0, vector.1, vector.2
+            )
+            .as_str()
+        );
+        format!("&{}_{}", object.name, property)
+    }
+
+    /// The object and property named by an ANIMATION's `.target`.
+    pub fn animation_target(&self, animation: &constructor::Object) -> Option<(String, String)> {
+        match &animation.properties.iter().rev().find(|p| p.name == "target")?.value {
+            Expression::Member(base, property) => match base.as_ref() {
+                Expression::Identifier(name) => Some((name.clone(), property.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Validates an ANIMATION and declares its keyframes.
+    pub fn transpile_animation(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
+        let mut output = String::new();
+
+        // Errors about the target point at it, or else at the ANIMATION
+        let target_at = object.properties.iter().rev().find(|p| p.name == "target").map_or(object.position, |p| p.position);
+        self.locate(object, target_at);
+        let (target, property) = match self.animation_target(object) {
+            Some(target) => target,
+            None => self.error(format!("ANIMATION `{}` needs a .target such as `sphere.position`", object.name)),
+        };
+        let target = match scene.objects.iter().find(|o| o.name == target) {
+            Some(target) => target,
+            None => self.error(format!("ANIMATION `{}` targets unknown object `{}`", object.name, target)),
+        };
+        if !ANIMATABLE.contains(&(target.obj_type, property.as_str())) {
+            let properties: Vec<&str> = ANIMATABLE.iter().filter(|(t, _)| *t == target.obj_type).map(|(_, p)| *p).collect();
+            self.error(format!(
+                "ANIMATION `{}` cannot animate {}.{}, {} has {}",
+                object.name,
+                target.name,
+                property,
+                target.obj_type.keyword(),
+                if properties.is_empty() { "no animatable properties".to_string() } else { format!("animatable properties: {}", properties.join(", ")) }
+            ));
+        }
+
+        let keys = match object.properties.iter().rev().find(|p| p.name == "keys") {
+            Some(constructor::Property { value: Expression::List(keys), position, .. }) if !keys.is_empty() => {
+                self.locate(object, *position);
+                keys
+            }
+            Some(prop) => report(object, prop.position, format!("ANIMATION `{}` needs .keys, a list of (time, value) keyframes", object.name)),
+            None => report(object, object.position, format!("ANIMATION `{}` needs .keys, a list of (time, value) keyframes", object.name)),
+        };
+
+        let mut keyframes = Vec::new();
+        let mut previous = None;
+        for key in keys {
+            let (time, value) = match key {
+                Expression::Group(pair) if pair.len() == 2 => (&pair[0], &pair[1]),
+                _ => self.error(format!("Expected (time, value) keyframe in `{}`, got {}", object.name, key)),
+            };
+            let time = self.evaluate_number(time, "keyframe time");
+            if time < 0.0 || previous.is_some_and(|previous| time <= previous) {
+                self.error(format!("Keyframe times in `{}` must be positive and increasing, got {}", object.name, time));
+            }
+            previous = Some(time);
+
+            let value = match value {
+                Expression::Group(values) if values.len() == 3 => self.evaluate_vector(value, "keyframe value"),
+                _ => self.error(format!(
+                    "{}.{} is a vector, but `{}` has the keyframe value {}",
+                    target.name, property, object.name, value
+                )),
+            };
+            keyframes.push(format!(".{{ .time = {}, .value = Vec3.init({}, {}, {}) }}", time, value.0, value.1, value.2));
+        }
+
+        for prop in self.properties(object) {
+            match prop.name.as_str() {
+                "target" | "keys" => {}
+                "easing" => {
+                    let easing = self.evaluate_name(&prop.value, "ANIMATION easing");
+                    if !EASINGS.contains(&easing.as_str()) {
+                        self.error(format!("Unknown easing `{}` in `{}`, expected one of: {}", easing, object.name, EASINGS.join(", ")));
+                    }
+                }
+                "loop" => {
+                    self.loop_mode(object, &prop.value);
+                }
+                name => self.error(format!("Unknown ANIMATION property: {}", name)),
+            }
+        }
+
+        output.push_str(format!("    const {}_keys = [_]Keyframe{{ {} }};\n", object.name, keyframes.join(", ")).as_str());
+
+        output
+    }
+
+    /// `.loop` is a mode, or true (repeat) or false (play once).
+    pub fn loop_mode(&self, object: &constructor::Object, value: &Expression) -> String {
+        let mode = self.evaluate_name(value, "ANIMATION loop");
+        match mode.as_str() {
+            "true" => "repeat".to_string(),
+            "false" => "once".to_string(),
+            mode if LOOP_MODES.contains(&mode) => mode.to_string(),
+            _ => self.error(format!("Unknown loop mode `{}` in `{}`, expected true, false or one of: {}", mode, object.name, LOOP_MODES.join(", "))),
+        }
+    }
+
+    /// The code updating an animated property every frame.
+    pub fn transpile_animation_frame(&self, object: &constructor::Object, scene: &constructor::Scene) -> String {
+        let (target, property) = self.animation_target(object).unwrap();
+
+        let easing = match object.properties.iter().rev().find(|p| p.name == "easing") {
+            Some(prop) => self.evaluate_name(&prop.value, "ANIMATION easing"),
+            None => "linear".to_string(),
+        };
+        let mode = match object.properties.iter().rev().find(|p| p.name == "loop") {
+            Some(prop) => self.loop_mode(object, &prop.value),
+            None => "once".to_string(),
+        };
+
+        // Lights are copied into `lights`, so the copy is what gets updated
+        let destination = match scene.objects.iter().find(|o| o.name == target) {
+            Some(light) if light.obj_type == constructor::ObjectType::Light => {
+                let index = scene
+                    .objects
+                    .iter()
+                    .filter(|o| o.obj_type == constructor::ObjectType::Light)
+                    .position(|o| o.name == target)
+                    .unwrap();
+                format!("lights.items[{}].{}", index, property)
+            }
+            _ => format!("{}_{}", target, property),
+        };
+
+        format!(
+            "        {} = animate(&{}_keys, time, .{}, .{});\n",
+            destination, object.name, easing, mode
+        )
+    }
+
+    /// The code of a ZIG block, re-indented to `indent` spaces and marked
+    /// with where it was written, so errors in it can be traced back, and
+    /// where it ends, so `zest import-zig` can read it back.
+    pub fn splice(&self, code: &constructor::Code, indent: usize) -> String {
+        let lines: Vec<&str> = code.code.lines().map(|line| line.trim_end()).collect();
+        let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+        let last = lines.iter().rposition(|line| !line.is_empty()).map_or(first, |last| last + 1);
+        let lines = &lines[first..last];
+
+        let common = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+
+        let mut output = format!("{}// zest: {}\n", " ".repeat(indent), code.span());
+        for line in lines {
+            if !line.is_empty() {
+                output.push_str(" ".repeat(indent).as_str());
+                output.push_str(&line[common..]);
+            }
+            output.push('\n');
+        }
+        output.push_str(format!("{}// zest: end\n", " ".repeat(indent)).as_str());
+
+        output
+    }
+
+    pub fn transpile_active(&self, object: &constructor::Object) -> String {
+        let mut output = String::new();
+
+        let mut camera = None;
+        for prop in self.properties(object) {
+            match prop.name.as_str() {
+                "camera" => camera = Some(self.evaluate_name(&prop.value, "ACTIVE camera")),
+                name => self.error(format!("Unknown ACTIVE property: {}", name)),
+            }
+        }
+        let object = match camera {
+            Some(camera) => camera,
+            None => report(object, object.position, format!("ACTIVE `{}` needs a .camera", object.name)),
         };
 
         output.push_str(
             format!(
-                "    const scene = try z3d.engine.Scene.init({}, &scene_objects, &lights, .{{ .ray_casting_options = &z3d.graphics.RayCastingOptions{{ .width = WIDTH, .height = HEIGHT, .fov = 90 }} }}, &gui_layer);\n",
+                "    const scene = try z3d.engine.Scene.init({}, &scene_objects, &lights, .{{ .ray_casting_options = &z3d.graphics.RayCastingOptions{{ .width = RENDER_WIDTH, .height = RENDER_HEIGHT, .fov = FOV }} }}, &gui_layer);\n",
                 object
             )
             .as_str()
@@ -525,12 +1504,345 @@ impl Transpiler {
         match expression {
             Expression::Identifier(b) => {
                 if ![ "true", "false" ].contains(&b.as_str()) {
-                    panic!("Expected true or false");
+                    self.error("Expected true or false".to_string());
                 }
 
                 b == "true"
             },
-            _ => panic!("Expected bool"),
+            _ => self.error("Expected bool".to_string()),
+        }
+    }
+
+    pub fn evaluate_number(&self, expression: &Expression, what: &str) -> f64 {
+        match expression {
+            Expression::Number(num) => num.parse().unwrap(),
+            _ => self.error(format!("Expected number for {}, got {}", what, expression)),
+        }
+    }
+
+    pub fn evaluate_vector(&self, expression: &Expression, what: &str) -> (f64, f64, f64) {
+        match expression {
+            Expression::Group(values) if values.len() == 3 => (
+                self.evaluate_number(&values[0], what),
+                self.evaluate_number(&values[1], what),
+                self.evaluate_number(&values[2], what),
+            ),
+            _ => self.error(format!("Expected (x, y, z) for {}, got {}", what, expression)),
+        }
+    }
+
+    pub fn evaluate_name(&self, expression: &Expression, what: &str) -> String {
+        match expression {
+            Expression::Identifier(name) => name.clone(),
+            _ => self.error(format!("Expected object name for {}, got {}", what, expression)),
         }
     }
+
+    pub fn evaluate_integer(&self, expression: &Expression, what: &str) -> i64 {
+        let num = self.evaluate_number(expression, what);
+        if num.fract() != 0.0 {
+            self.error(format!("Expected integer for {}, got {}", what, num));
+        }
+
+        num as i64
+    }
+
+    pub fn evaluate_size(&self, expression: &Expression, what: &str) -> usize {
+        let num = self.evaluate_integer(expression, what);
+        if num <= 0 {
+            self.error(format!("{} must be positive, got {}", what, num));
+        }
+
+        num as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transpile(text: &str) -> String {
+        let mut constructor = constructor::Constructor::new("test.zest".to_string(), text.to_string());
+        constructor.construct();
+        crate::evaluator::Evaluator::new().fold_engine(&mut constructor.engine);
+        Transpiler::new(constructor.engine).transpile()
+    }
+
+    #[test]
+    fn material_extends_with_reflectivity() {
+        let zig = transpile(
+            "SCENE s {
+                MATERIAL red { .color = (255, 0, 0) }
+                MATERIAL shiny_red EXTENDS red { .reflectivity = 0.8 }
+            }",
+        );
+        assert!(zig.contains(
+            "const shiny_red = z3d.graphics.material.Material{ .texture = z3d.graphics.material.Texture { \
+             .SOLID_COLOR = z3d.graphics.RGB{ .r = 255, .g = 0, .b = 0 } }, .reflectivity = 0.8 };"
+        ));
+    }
+
+    #[test]
+    fn light_extends_all_properties() {
+        let zig = transpile(
+            "SCENE s {
+                LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1), .direction = (0, -1, 0) }
+                LIGHT moon EXTENDS sun { .intensity = (0.2, 0.2, 0.2) }
+            }",
+        );
+        assert!(zig.contains(
+            "const moon = z3d.graphics.Light{.position = Vec3.init(0, 5, 0), \
+             .intensity = Vec3.init(0.2, 0.2, 0.2), .direction = Vec3.init(0, -1, 0), };"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:1:48: Unknown MATERIAL property: shine")]
+    fn material_rejects_unknown_properties() {
+        transpile("SCENE s { MATERIAL red { .color = (255, 0, 0), .shine = 1 } }");
+    }
+
+    #[test]
+    fn physics_is_created_before_the_scene_and_stepped_every_frame() {
+        let zig = transpile(
+            "SCENE s {
+                MATERIAL red { .color = (255, 0, 0) }
+                SPHERE ball { .position = (0, 2, 0), .radius = 1, .material = red }
+                CONTROLLER controller { .keyboard_movement = true }
+                CAMERA cam { .position = (0, 0, -5), .direction = (0, 0, 1), .event_handler = controller }
+                ACTIVE active { .camera = cam }
+                PHYSICS world { .bodies = [ball] }
+            }",
+        );
+        let engine = zig.find("var world_ball = z3d.physics.PhysicsEngine.init(&obj_ball, .{});").unwrap();
+        let gravity = zig.find("world_ball.apply_gravity(Vec3.init(0, -9.8, 0));").unwrap();
+        let scene = zig.find("const scene = try z3d.engine.Scene.init(").unwrap();
+        let step = zig.find("world_ball.step(time - world_time);").unwrap();
+        assert!(engine < gravity && gravity < scene && scene < step);
+    }
+
+    #[test]
+    fn actions_are_dispatched_to_their_handlers() {
+        let zig = transpile(
+            "ZIG { fn onOpenMenu() void {} }
+            SCENE s {
+                CONTROLLER controller { .actions = [open_menu], .bindings = { M: open_menu } }
+            }",
+        );
+        assert!(zig.contains("    if (std.mem.eql(u8, action, \"open_menu\")) return onOpenMenu();\n"));
+        assert!(zig.contains(" .on_action = &dispatch_action,"));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:1:35: Action `jump` has no handler, declare `fn onJump() void` in a top level ZIG block")]
+    fn actions_need_handlers() {
+        transpile("SCENE s { CONTROLLER controller { .actions = [jump], .bindings = { Space: jump } } }");
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:17: Unknown key `Spacebar` in `controller`")]
+    fn bindings_need_known_keys() {
+        transpile(
+            "SCENE s {
+                CONTROLLER controller { .keyboard_movement = true,
+                .bindings = { Spacebar: forward } }
+            }",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:5:17: PHYSICS `world` refers to unknown object `bal`")]
+    fn physics_bodies_are_located() {
+        transpile(
+            "SCENE s {
+                MATERIAL red { .color = (255, 0, 0) }
+                SPHERE ball { .position = (0, 2, 0), .radius = 1, .material = red }
+                PHYSICS world {
+                .bodies = [bal] }
+            }",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:17: WINDOW width and height must be equal, got 300x400")]
+    fn window_settings_are_located() {
+        transpile(
+            "SCENE s {}
+                WINDOW window { .width = 300 }",
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(string_literal("Demo"), "\"Demo\"");
+        assert_eq!(string_literal("a\\b \"c\""), r#""a\\b \"c\"""#);
+        assert_eq!(string_literal("one\ntwo\tthree\r"), r#""one\ntwo\x09three\x0d""#);
+        assert_eq!(string_literal("caf\u{e9} \u{85}"), "\"caf\u{e9} \\u{85}\"");
+    }
+
+    #[test]
+    fn titles_and_paths_are_escaped() {
+        let zig = transpile(
+            "WINDOW window { .title = \"C:\\Demo\" }
+            SCENE s {
+                IMAGE sky { .file = \"assets\\sky.png\" }
+                TEXT label { .text = \"two
+lines\" }
+            }",
+        );
+        assert!(zig.contains("const TITLE = \"C:\\\\Demo\";"));
+        assert!(zig.contains("\"assets\\\\sky.png\""));
+        assert!(zig.contains(".text = \"two\\nlines\""));
+    }
+
+    #[test]
+    fn gui_elements_are_added_to_the_layer() {
+        let zig = transpile(
+            "ZIG { fn onPlay() void {} }
+            SCENE s {
+                CONTROLLER controller { .actions = [play] }
+                IMAGE logo_image { .file = \"logo.png\" }
+                SPRITE logo { .anchor = top, .size = (200, 100), .image = logo_image }
+                PANEL backdrop { .anchor = center, .size = (220, 80), .color = #202020 }
+                BUTTON play { .size = (200, 60), .text = \"Play\", .text_color = #fff, .on_click = play }
+                TEXT hint { .anchor = bottom, .position = (0, -20), .text = \"Hi\", .font_size = 14 }
+            }",
+        );
+        assert!(zig.contains(
+            "    const logo = z3d.gui.Sprite{ .position = .{ .x = 0, .y = 0 }, .anchor = .top, \
+             .size = .{ .width = 200, .height = 100 }, .image = &logo_image };\n    \
+             try gui_layer.append(z3d.gui.Element{ .sprite = logo });\n"
+        ));
+        assert!(zig.contains(
+            "    const backdrop = z3d.gui.Panel{ .position = .{ .x = 0, .y = 0 }, .anchor = .center, \
+             .size = .{ .width = 220, .height = 80 }, .color = z3d.graphics.RGB{ .r = 32, .g = 32, .b = 32 } };\n    \
+             try gui_layer.append(z3d.gui.Element{ .panel = backdrop });\n"
+        ));
+        assert!(zig.contains(
+            "    const play = z3d.gui.Button{ .position = .{ .x = 0, .y = 0 }, .anchor = .top_left, \
+             .size = .{ .width = 200, .height = 60 }, .text = \"Play\", \
+             .text_color = z3d.graphics.RGB{ .r = 255, .g = 255, .b = 255 }, .on_click = &onPlay };\n"
+        ));
+        assert!(zig.contains(
+            "    const hint = z3d.gui.Text{ .position = .{ .x = 0, .y = -20 }, .anchor = .bottom, \
+             .text = \"Hi\", .font_size = 14 };\n    \
+             try gui_layer.append(z3d.gui.Element{ .text = hint });\n"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:29: Unknown anchor `middle` for `hint`, expected one of: top_left, top, ")]
+    fn gui_anchors_are_checked() {
+        transpile(
+            "SCENE s {
+                TEXT hint { .anchor = middle, .text = \"Hi\" }
+            }",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:2:17: PANEL `bar` needs a .size")]
+    fn gui_elements_need_a_size() {
+        transpile(
+            "SCENE s {
+                PANEL bar { .color = #000 }
+            }",
+        );
+    }
+
+    #[test]
+    fn animations_are_eased_every_frame() {
+        let zig = transpile(
+            "SCENE s {
+                MATERIAL red { .color = (255, 0, 0) }
+                SPHERE ball { .position = (0, 1, 5), .radius = 1, .material = red }
+                LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1) }
+                LIGHT lamp { .position = (0, 2, 0), .intensity = (1, 1, 1) }
+                ANIMATION bob { .target = ball.position, .keys = [(0, (0, 1, 5)), (1.5, (0, 2, 5))], .easing = ease_out, .loop = true }
+                ANIMATION dim { .target = lamp.intensity, .keys = [(0, (1, 1, 1)), (2, (0.2, 0.2, 0.2))] }
+            }",
+        );
+        assert_eq!(zig.matches("\nfn ease(easing: Easing, t: f32) f32 {").count(), 1);
+        assert!(zig.contains("    var ball_position = Vec3.init(0, 1, 5);\n"));
+        assert!(zig.contains("z3d.graphics.objects.Sphere.init(&ball_position, 1, &red)"));
+        assert!(zig.contains(
+            "    const bob_keys = [_]Keyframe{ .{ .time = 0, .value = Vec3.init(0, 1, 5) }, \
+             .{ .time = 1.5, .value = Vec3.init(0, 2, 5) } };\n"
+        ));
+        assert!(zig.contains("        ball_position = animate(&bob_keys, time, .ease_out, .repeat);\n"));
+        // Without .easing and .loop, animations run linearly and once
+        assert!(zig.contains("        lights.items[1].intensity = animate(&dim_keys, time, .linear, .once);\n"));
+    }
+
+    #[test]
+    fn animation_support_is_only_emitted_when_used() {
+        let zig = transpile("SCENE s { LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1) } }");
+        assert!(!zig.contains("const Keyframe"));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:99: Unknown easing `bounce` in `bob`, expected one of: linear, ease_in, ease_out, ease_in_out, step")]
+    fn easings_are_checked() {
+        transpile(
+            "SCENE s {
+                LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1) }
+                ANIMATION bob { .target = sun.position, .keys = [(0, (0, 5, 0)), (1, (0, 6, 0))], .easing = bounce }
+            }",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:3:57: Keyframe times in `bob` must be positive and increasing, got 1")]
+    fn keyframes_are_in_order() {
+        transpile(
+            "SCENE s {
+                LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1) }
+                ANIMATION bob { .target = sun.position, .keys = [(2, (0, 5, 0)), (1, (0, 6, 0))] }
+            }",
+        );
+    }
+
+    #[test]
+    fn scenes_without_frame_code_use_the_mainloop() {
+        let zig = transpile("SCENE s { LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1) } }");
+        assert!(zig.contains("\n    try engine.mainloop();\n}\n"));
+        assert!(!zig.contains("while (engine.running())"));
+    }
+
+    #[test]
+    fn hooks_are_spliced_into_the_frame_loop() {
+        let zig = transpile(
+            "ZIG {
+                var frames: u32 = 0;
+            }
+            SCENE s {
+                LIGHT sun { .position = (0, 5, 0), .intensity = (1, 1, 1), .on_update = ZIG {
+                    frames += 1;
+                } }
+                ANIMATION rise { .target = sun.position, .keys = [(0, (0, 5, 0)), (1, (0, 6, 0))] }
+                ZIG {
+                    std.debug.print(\"loaded\\n\", .{});
+                }
+            }",
+        );
+        let global = zig.find("// zest: test.zest:1:1\nvar frames: u32 = 0;\n// zest: end\n").unwrap();
+        let scene = zig.find("fn scene_s() !void {").unwrap();
+        let loaded = zig
+            .find("    // zest: test.zest:9:17\n    std.debug.print(\"loaded\\n\", .{});\n    // zest: end\n")
+            .unwrap();
+        let clock = zig.find("    var clock = try std.time.Timer.start();\n    while (engine.running()) {\n").unwrap();
+        let animate = zig.find("        lights.items[0].position = animate(&rise_keys, time, .linear, .once);\n").unwrap();
+        let hook = zig.find("        // zest: test.zest:5:89\n        frames += 1;\n        // zest: end\n").unwrap();
+        let frame = zig.find("        try engine.frame();\n    }\n}\n").unwrap();
+        assert!(global < scene && scene < loaded && loaded < clock);
+        assert!(clock < animate && animate < hook && hook < frame);
+        // The hook is not a property of the light itself
+        assert!(!zig.contains(".on_update"));
+    }
+
+    #[test]
+    #[should_panic(expected = "test.zest:1:35: ZIG blocks can only be used for .on_update, not .radius")]
+    fn zig_blocks_are_only_hooks() {
+        transpile("SCENE s { SPHERE ball { .radius = ZIG { 1 } } }");
+    }
 }